@@ -0,0 +1,209 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+use crate::color::Color;
+use crate::noise::value_noise;
+use crate::palette;
+
+// The clock is cut into slots; a slot may hold one flight, which starts
+// somewhere inside it. Times are in the same units as the cloud clock.
+const SLOT_LENGTH: f32 = 1500.0;
+const FLIGHT_LENGTH: f32 = 400.0;
+const FLIGHT_CHANCE: f32 = 0.6;
+const MIN_BIRDS: usize = 5;
+const MAX_BIRDS: usize = 9;
+// How far around the sky a flight goes and how high its arc peaks, in
+// radians. Flights start and end on the horizon, so birds never pop in.
+const FLIGHT_SWEEP: f32 = 1.6;
+const MIN_PEAK: f32 = 0.15;
+const MAX_PEAK: f32 = 0.5;
+// Formation and silhouette sizes, as angles on the sky.
+const SPACING: f32 = 0.025;
+const WING_SPAN: f32 = 0.012;
+const WING_THICKNESS: f32 = 0.003;
+const WING_SWEEP: f32 = 0.5;
+const FLAP_DEPTH: f32 = 0.4;
+const FLAP_RATE: f32 = 0.35;
+
+// Seeds the flights. With the same seed and clock the same flocks cross
+// the same sky, so a paused or slowed clock pauses or slows them too.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Birds {
+    pub seed: u32,
+}
+
+impl Default for Birds {
+    fn default() -> Self {
+        Birds { seed: 5 }
+    }
+}
+
+struct Bird {
+    direction: Vec3,
+    heading: Vec3,
+    side: Vec3,
+    flap: f32,
+}
+
+// One flock in flight at one moment, already placed on the sky.
+pub struct Flock {
+    birds: Vec<Bird>,
+    center: Vec3,
+    // Cosine of the cone around `center` that holds every bird, so rays
+    // elsewhere skip the flock with one dot product.
+    reach: f32,
+}
+
+impl Birds {
+    // The flock in the sky at `time`, if a flight is on.
+    pub fn flock_at(&self, time: f32) -> Option<Flock> {
+        let slot = (time / SLOT_LENGTH).floor();
+        // Integer lattice points give the raw hash for each roll.
+        let roll = |channel: f32| value_noise(&Vec3::new(slot, channel, 0.0), self.seed);
+        if roll(0.0) >= FLIGHT_CHANCE {
+            return None;
+        }
+        let start = slot * SLOT_LENGTH + roll(1.0) * (SLOT_LENGTH - FLIGHT_LENGTH);
+        let progress = (time - start) / FLIGHT_LENGTH;
+        if !(0.0..=1.0).contains(&progress) {
+            return None;
+        }
+
+        let count = MIN_BIRDS + (roll(2.0) * (MAX_BIRDS - MIN_BIRDS + 1) as f32) as usize;
+        let azimuth = roll(3.0) * 2.0 * PI;
+        let peak = MIN_PEAK + roll(4.0) * (MAX_PEAK - MIN_PEAK);
+        let turn = if roll(5.0) < 0.5 { FLIGHT_SWEEP } else { -FLIGHT_SWEEP };
+        let path = |progress: f32| {
+            let around = azimuth + turn * (progress - 0.5);
+            let up = peak * (PI * progress).sin();
+            Vec3::new(up.cos() * around.cos(), up.sin(), up.cos() * around.sin())
+        };
+
+        let center = path(progress);
+        let heading = (path(progress + 1e-3) - center).normalize();
+        let side = center.cross(&heading);
+        let birds: Vec<Bird> = (0..count.min(MAX_BIRDS))
+            .map(|i| {
+                // A V behind the leader, alternating sides.
+                let row = i.div_ceil(2);
+                let lateral = if i % 2 == 0 { 1.0 } else { -1.0 } * row as f32;
+                let offset = -heading * (row as f32 * SPACING) + side * (lateral * SPACING);
+                let direction = (center + offset).normalize();
+                let heading = (heading - direction * direction.dot(&heading)).normalize();
+                Bird {
+                    direction,
+                    heading,
+                    side: direction.cross(&heading),
+                    flap: (time * FLAP_RATE + i as f32 * 1.7).sin(),
+                }
+            })
+            .collect();
+        let widest = birds.iter().map(|bird| bird.direction.dot(&center)).fold(1.0, f32::min);
+        let reach = (widest.clamp(-1.0, 1.0).acos() + 2.0 * WING_SPAN).cos();
+        Some(Flock { birds, center, reach })
+    }
+}
+
+impl Flock {
+    pub fn bird_count(&self) -> usize {
+        self.birds.len()
+    }
+
+    // Each bird is a dark chevron, wings swept back and flapping; only the
+    // part above the horizon shows.
+    pub fn over(&self, sky: Color, direction: &Vec3) -> Color {
+        let length = direction.magnitude();
+        if length == 0.0 || direction.y <= 0.0 {
+            return sky;
+        }
+        let direction = direction / length;
+        if direction.dot(&self.center) < self.reach {
+            return sky;
+        }
+
+        let cover = self
+            .birds
+            .iter()
+            .filter(|bird| bird.direction.y > 0.0)
+            .map(|bird| {
+                let offset = direction - bird.direction;
+                let (along, across) = (offset.dot(&bird.heading), offset.dot(&bird.side));
+                if across.abs() > WING_SPAN {
+                    return 0.0;
+                }
+                let wing = -(WING_SWEEP + FLAP_DEPTH * bird.flap) * across.abs();
+                let distance = (along - wing).abs();
+                1.0 - smoothstep(0.5 * WING_THICKNESS, WING_THICKNESS, distance)
+            })
+            .fold(0.0, f32::max);
+        if cover <= 0.0 {
+            return sky;
+        }
+        sky.lerp(palette::BIRD, cover)
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every few clock units over many slots.
+    fn clock() -> impl Iterator<Item = f32> {
+        (0..20_000).map(|i| i as f32 * 5.0)
+    }
+
+    #[test]
+    fn flights_come_and_go_on_a_seeded_schedule() {
+        let birds = Birds::default();
+        let flying = clock().filter(|&time| birds.flock_at(time).is_some()).count();
+        assert!(flying > 0 && flying < clock().count() / 2, "{} of {}", flying, clock().count());
+
+        for time in clock().step_by(97) {
+            let (a, b) = (birds.flock_at(time), birds.flock_at(time));
+            assert_eq!(a.as_ref().map(|flock| flock.center), b.as_ref().map(|flock| flock.center));
+            if let Some(flock) = a {
+                assert!((MIN_BIRDS..=MAX_BIRDS).contains(&flock.bird_count()));
+            }
+        }
+        let other = Birds { seed: 6 };
+        assert!(clock().any(|time| birds.flock_at(time).is_some() != other.flock_at(time).is_some()));
+    }
+
+    #[test]
+    fn flocks_glide_smoothly_and_rise_from_the_horizon() {
+        let birds = Birds::default();
+        let first = clock().find(|&time| birds.flock_at(time).is_some()).expect("no flight");
+        assert!(birds.flock_at(first).unwrap().center.y < 0.05);
+
+        let mut time = first;
+        let mut previous = birds.flock_at(time).unwrap().center;
+        while let Some(flock) = birds.flock_at(time + 0.5) {
+            assert!((flock.center - previous).magnitude() < 0.01);
+            previous = flock.center;
+            time += 0.5;
+        }
+        assert!(time - first > FLIGHT_LENGTH * 0.9);
+        assert!(previous.y < 0.05);
+    }
+
+    #[test]
+    fn only_the_birds_darken_the_sky() {
+        let birds = Birds::default();
+        let flock = clock()
+            .filter_map(|time| birds.flock_at(time))
+            .find(|flock| flock.center.y > 0.2)
+            .expect("no flock high in the sky");
+        let sky = palette::DAY_ZENITH;
+
+        let leader = &flock.birds[0];
+        let on_the_body = flock.over(sky, &leader.direction);
+        assert!(on_the_body.luminance() < sky.luminance());
+        let elsewhere = -flock.center + Vec3::new(0.0, 2.0, 0.0);
+        assert_eq!(flock.over(sky, &elsewhere), sky);
+        assert_eq!(flock.over(sky, &Vec3::new(1.0, -0.1, 0.0)), sky);
+    }
+}
@@ -1,6 +1,7 @@
 
 use nalgebra_glm::Vec3;
 use std::f32::consts::PI;
+use crate::aabb::Aabb;
 
 pub struct Camera {
     pub eye: Vec3,
@@ -24,7 +25,18 @@ impl Camera {
 
         let rotated = vector.x * right + vector.y * up - vector.z * forward;
 
-        return rotated.normalize();
+        rotated.normalize()
+    }
+
+    // The inverse of `base_change` for points: how far right, up and in
+    // front of the eye a world point lies.
+    pub fn view_space(&self, point: &Vec3) -> Vec3 {
+        let forward = (self.center - self.eye).normalize();
+        let right = forward.cross(&self.up).normalize();
+        let up = right.cross(&forward).normalize();
+        let offset = point - self.eye;
+
+        Vec3::new(offset.dot(&right), offset.dot(&up), offset.dot(&forward))
     }
 
     pub fn orbit(&mut self, delta_yaw: f32, delta_pitch: f32) {
@@ -46,6 +58,29 @@ impl Camera {
 
         self.eye = new_eye;
     }
+    pub fn stereo_pair(&self, eye_separation: f32) -> (Camera, Camera) {
+        let forward = (self.center - self.eye).normalize();
+        let right = forward.cross(&self.up).normalize();
+        let offset = right * (eye_separation * 0.5);
+
+        (
+            Camera::new(self.eye - offset, self.center, self.up),
+            Camera::new(self.eye + offset, self.center, self.up),
+        )
+    }
+
+    pub fn frame_bounds(&mut self, bounds: &Aabb, fov: f32, margin: f32) {
+        if bounds.is_empty() {
+            return;
+        }
+
+        let view_direction = (self.eye - self.center).normalize();
+        let distance = bounds.radius() * margin / (fov * 0.5).sin();
+
+        self.center = bounds.center();
+        self.eye = self.center + view_direction * distance;
+    }
+
     pub fn zoom(&mut self, amount: f32) {
         let direction = (self.center - self.eye).normalize();
         self.eye += direction * amount;
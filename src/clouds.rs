@@ -0,0 +1,138 @@
+use nalgebra_glm::Vec3;
+use crate::color::Color;
+use crate::noise::value_noise;
+use crate::palette;
+
+const CLOUD_HEIGHT: f32 = 1.0;
+const CLOUD_SCALE: f32 = 1.5;
+const CLOUD_SOFTNESS: f32 = 0.12;
+const HORIZON_FADE: f32 = 0.2;
+const DETAIL_DRIFT: f32 = 1.7;
+const SUN_DIMMING: f32 = 0.35;
+const DEFAULT_COVERAGE: f32 = 0.45;
+const DEFAULT_WIND: (f32, f32) = (0.004, 0.0015);
+
+// A flat layer of value noise at a fixed height over the camera. Looking
+// up, a direction is projected onto the layer; the coarse octave drifts
+// with the wind and the fine one a little faster, so the clouds change
+// shape as they move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clouds {
+    pub coverage: f32,
+    pub wind: (f32, f32),
+    pub time: f32,
+    pub seed: u32,
+}
+
+impl Default for Clouds {
+    fn default() -> Self {
+        Clouds { coverage: DEFAULT_COVERAGE, wind: DEFAULT_WIND, time: 0.0, seed: 11 }
+    }
+}
+
+impl Clouds {
+    pub fn with_coverage(mut self, coverage: f32) -> Self {
+        self.coverage = coverage.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_wind(mut self, x: f32, z: f32) -> Self {
+        self.wind = (x, z);
+        self
+    }
+
+    // How much of the sky in this direction is cloud, in [0, 1]. Nothing
+    // shows at or below the horizon, and the layer fades out toward it
+    // since the projection stretches the noise there.
+    pub fn density(&self, direction: &Vec3) -> f32 {
+        let length = direction.magnitude();
+        if length == 0.0 || self.coverage <= 0.0 {
+            return 0.0;
+        }
+        let up = direction.y / length;
+        if up <= 0.0 {
+            return 0.0;
+        }
+
+        let along = CLOUD_HEIGHT / up;
+        let (drift_x, drift_z) = (self.wind.0 * self.time, self.wind.1 * self.time);
+        let x = (direction.x / length * along + drift_x) * CLOUD_SCALE;
+        let z = (direction.z / length * along + drift_z) * CLOUD_SCALE;
+        let coarse = value_noise(&Vec3::new(x, 0.0, z), self.seed);
+        let detail = value_noise(
+            &Vec3::new(2.0 * x + drift_x * DETAIL_DRIFT, 0.0, 2.0 * z + drift_z * DETAIL_DRIFT),
+            self.seed.wrapping_add(1),
+        );
+        let noise = coarse * 0.65 + detail * 0.35;
+
+        let threshold = 1.0 - self.coverage;
+        let cover = smoothstep(threshold - CLOUD_SOFTNESS, threshold + CLOUD_SOFTNESS, noise);
+        cover * smoothstep(0.0, HORIZON_FADE, up)
+    }
+
+    // Thin cloud is bright; thick cloud turns gray underneath.
+    pub fn over(&self, sky: Color, direction: &Vec3) -> Color {
+        let density = self.density(direction);
+        if density <= 0.0 {
+            return sky;
+        }
+        let cloud = palette::CLOUD_TOP.lerp(palette::CLOUD_BASE, density);
+        sky.lerp(cloud, density)
+    }
+
+    // Fraction of sunlight that gets through the layer toward the sun.
+    pub fn sun_factor(&self, to_sun: &Vec3) -> f32 {
+        1.0 - SUN_DIMMING * self.density(to_sun)
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fan of upward directions across the sky.
+    fn sky_directions() -> impl Iterator<Item = Vec3> {
+        (0..64).map(|i| {
+            let angle = i as f32 * 0.7;
+            Vec3::new(angle.cos(), 0.3 + (i % 8) as f32 * 0.1, angle.sin())
+        })
+    }
+
+    #[test]
+    fn nothing_shows_below_the_horizon() {
+        let overcast = Clouds::default().with_coverage(1.0);
+        assert_eq!(overcast.density(&Vec3::new(1.0, 0.0, 0.0)), 0.0);
+        assert_eq!(overcast.density(&Vec3::new(0.3, -0.5, 0.2)), 0.0);
+        assert!(overcast.density(&Vec3::new(1.0, 0.01, 0.0)) < overcast.density(&Vec3::new(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn coverage_controls_how_much_sky_is_cloud() {
+        let total = |clouds: Clouds| sky_directions().map(|direction| clouds.density(&direction)).sum::<f32>();
+        assert_eq!(total(Clouds::default().with_coverage(0.0)), 0.0);
+        assert!(total(Clouds::default().with_coverage(0.2)) < total(Clouds::default().with_coverage(0.8)));
+    }
+
+    #[test]
+    fn clouds_drift_with_the_clock_and_the_wind() {
+        let now = Clouds::default().with_coverage(0.5);
+        let later = Clouds { time: 20.0, ..now };
+        let calm = Clouds { time: 20.0, ..now.with_wind(0.0, 0.0) };
+        let densities = |clouds: Clouds| sky_directions().map(|direction| clouds.density(&direction)).collect::<Vec<_>>();
+
+        assert_ne!(densities(now), densities(later));
+        assert_eq!(densities(now), densities(calm));
+    }
+
+    #[test]
+    fn thick_cloud_dims_the_sun() {
+        let overhead = Vec3::new(0.0, 1.0, 0.0);
+        assert_eq!(Clouds::default().with_coverage(0.0).sun_factor(&overhead), 1.0);
+        assert!(Clouds::default().with_coverage(1.0).sun_factor(&overhead) < 0.7);
+    }
+}
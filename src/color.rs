@@ -1,30 +1,139 @@
 use std::fmt;
 
-#[derive(Debug, Clone, Copy)]
+// Channels are linear light, so shading and blending math is physically
+// meaningful. The 8-bit constructors take display-referred sRGB values, the
+// way colors are picked and stored in images, and decode them; `to_hex`
+// encodes back to sRGB when a pixel is written out. Sums of light may go
+// past 1 while a pixel is shaded and are only clipped in that last step,
+// so a bright highlight dimmed by a reflection weight keeps its color.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
+    r: f32,
+    g: f32,
+    b: f32,
 }
 
 impl Color {
     pub const fn new(r: u8, g: u8, b: u8) -> Self {
-        Color { r, g, b }
+        Color {
+            r: SRGB_TO_LINEAR[r as usize],
+            g: SRGB_TO_LINEAR[g as usize],
+            b: SRGB_TO_LINEAR[b as usize],
+        }
     }
 
     pub const fn from_hex(hex: u32) -> Self {
         let r = ((hex >> 16) & 0xFF) as u8;
         let g = ((hex >> 8) & 0xFF) as u8;
         let b = (hex & 0xFF) as u8;
-        Color { r, g, b }
+        Color::new(r, g, b)
+    }
+
+    // Light can't be negative, but there is no upper limit here.
+    pub fn from_linear(r: f32, g: f32, b: f32) -> Self {
+        Color {
+            r: r.max(0.0),
+            g: g.max(0.0),
+            b: b.max(0.0),
+        }
     }
 
     pub const fn black() -> Self {
-        Color { r: 0, g: 0, b: 0 }
+        Color { r: 0.0, g: 0.0, b: 0.0 }
+    }
+
+    pub fn to_hex(self) -> u32 {
+        let [r, g, b] = self.to_srgb_bytes();
+        ((r as u32) << 16) | ((g as u32) << 8) | (b as u32)
+    }
+
+    pub fn to_srgb_bytes(self) -> [u8; 3] {
+        [encode(self.r), encode(self.g), encode(self.b)]
+    }
+
+    pub fn scale_channels(self, r: f32, g: f32, b: f32) -> Self {
+        Color::from_linear(self.r * r, self.g * g, self.b * b)
+    }
+
+    pub fn average(colors: &[Color]) -> Self {
+        if colors.is_empty() {
+            return Color::black();
+        }
+        let count = colors.len() as f32;
+        let sum = |channel: fn(&Color) -> f32| colors.iter().map(channel).sum::<f32>();
+        Color {
+            r: sum(|c| c.r) / count,
+            g: sum(|c| c.g) / count,
+            b: sum(|c| c.b) / count,
+        }
+    }
+
+    pub fn lerp(self, other: Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Color {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+        }
+    }
+
+    pub fn to_unit_rgb(self) -> [f32; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    // The display color as 8-bit values over 255.
+    pub fn to_srgb_unit(self) -> [f32; 3] {
+        self.to_srgb_bytes().map(|channel| channel as f32 / 255.0)
+    }
+
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
     }
 
-    pub fn to_hex(&self) -> u32 {
-        ((self.r as u32) << 16) | ((self.g as u32) << 8) | (self.b as u32)
+    // Hue, saturation and value of the display color, so palette tweaks
+    // like `lighter` move in perceptual steps.
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let [r, g, b] = self.to_srgb_unit();
+
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let h = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { delta / max };
+
+        (h, s, max)
+    }
+
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Self {
+        let h = h.rem_euclid(360.0);
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+
+        let c = v * s;
+        let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+        let m = v - c;
+
+        let (r, g, b) = match (h / 60.0) as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+
+        let byte = |channel: f32| ((channel + m) * 255.0).round() as u8;
+        Color::new(byte(r), byte(g), byte(b))
     }
 }
 
@@ -34,11 +143,7 @@ impl Add for Color {
     type Output = Color;
 
     fn add(self, other: Color) -> Color {
-        Color {
-            r: self.r.saturating_add(other.r),
-            g: self.g.saturating_add(other.g),
-            b: self.b.saturating_add(other.b),
-        }
+        Color::from_linear(self.r + other.r, self.g + other.g, self.b + other.b)
     }
 }
 
@@ -48,16 +153,153 @@ impl Mul<f32> for Color {
     type Output = Color;
 
     fn mul(self, scalar: f32) -> Color {
+        Color::from_linear(self.r * scalar, self.g * scalar, self.b * scalar)
+    }
+}
+
+impl Mul<Color> for Color {
+    type Output = Color;
+
+    fn mul(self, other: Color) -> Color {
         Color {
-            r: (self.r as f32 * scalar).clamp(0.0, 255.0) as u8,
-            g: (self.g as f32 * scalar).clamp(0.0, 255.0) as u8,
-            b: (self.b as f32 * scalar).clamp(0.0, 255.0) as u8,
+            r: self.r * other.r,
+            g: self.g * other.g,
+            b: self.b * other.b,
         }
     }
 }
 
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Color(r: {}, g: {}, b: {})", self.r, self.g, self.b)
+        let [r, g, b] = self.to_srgb_bytes();
+        write!(f, "Color(r: {}, g: {}, b: {})", r, g, b)
+    }
+}
+
+fn encode(linear: f32) -> u8 {
+    let linear = linear.clamp(0.0, 1.0);
+    let srgb = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * crate::math::powf(linear, 1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+// The sRGB transfer function for every 8-bit value, precomputed so `new`
+// can stay a const fn for the palette.
+#[allow(clippy::excessive_precision)]
+const SRGB_TO_LINEAR: [f32; 256] = [
+    0.0, 3.035269835e-04, 6.070539671e-04, 9.105809506e-04, 1.214107934e-03, 1.517634918e-03, 1.821161901e-03, 2.124688885e-03,
+    2.428215868e-03, 2.731742852e-03, 3.035269835e-03, 3.346535764e-03, 3.676507324e-03, 4.024717018e-03, 4.391442037e-03, 4.776953481e-03,
+    5.181516702e-03, 5.605391624e-03, 6.048833023e-03, 6.512090793e-03, 6.995410187e-03, 7.499032043e-03, 8.023192985e-03, 8.568125618e-03,
+    9.134058702e-03, 9.721217320e-03, 1.032982303e-02, 1.096009401e-02, 1.161224518e-02, 1.228648836e-02, 1.298303234e-02, 1.370208305e-02,
+    1.444384360e-02, 1.520851442e-02, 1.599629337e-02, 1.680737575e-02, 1.764195449e-02, 1.850022013e-02, 1.938236096e-02, 2.028856306e-02,
+    2.121901038e-02, 2.217388479e-02, 2.315336618e-02, 2.415763245e-02, 2.518685963e-02, 2.624122189e-02, 2.732089164e-02, 2.842603950e-02,
+    2.955683444e-02, 3.071344373e-02, 3.189603307e-02, 3.310476657e-02, 3.433980681e-02, 3.560131488e-02, 3.688945040e-02, 3.820437160e-02,
+    3.954623528e-02, 4.091519691e-02, 4.231141062e-02, 4.373502926e-02, 4.518620439e-02, 4.666508634e-02, 4.817182423e-02, 4.970656598e-02,
+    5.126945837e-02, 5.286064702e-02, 5.448027644e-02, 5.612849005e-02, 5.780543019e-02, 5.951123816e-02, 6.124605423e-02, 6.301001765e-02,
+    6.480326669e-02, 6.662593864e-02, 6.847816984e-02, 7.036009570e-02, 7.227185068e-02, 7.421356838e-02, 7.618538148e-02, 7.818742181e-02,
+    8.021982031e-02, 8.228270713e-02, 8.437621154e-02, 8.650046204e-02, 8.865558629e-02, 9.084171118e-02, 9.305896285e-02, 9.530746663e-02,
+    9.758734714e-02, 9.989872825e-02, 1.022417331e-01, 1.046164841e-01, 1.070231030e-01, 1.094617108e-01, 1.119324278e-01, 1.144353738e-01,
+    1.169706678e-01, 1.195384280e-01, 1.221387722e-01, 1.247718176e-01, 1.274376804e-01, 1.301364767e-01, 1.328683216e-01, 1.356333297e-01,
+    1.384316150e-01, 1.412632911e-01, 1.441284709e-01, 1.470272665e-01, 1.499597898e-01, 1.529261520e-01, 1.559264637e-01, 1.589608351e-01,
+    1.620293756e-01, 1.651321945e-01, 1.682694002e-01, 1.714411007e-01, 1.746474037e-01, 1.778884160e-01, 1.811642442e-01, 1.844749945e-01,
+    1.878207723e-01, 1.912016827e-01, 1.946178304e-01, 1.980693196e-01, 2.015562538e-01, 2.050787364e-01, 2.086368701e-01, 2.122307574e-01,
+    2.158605001e-01, 2.195261997e-01, 2.232279573e-01, 2.269658735e-01, 2.307400485e-01, 2.345505822e-01, 2.383975738e-01, 2.422811225e-01,
+    2.462013267e-01, 2.501582847e-01, 2.541520943e-01, 2.581828529e-01, 2.622506575e-01, 2.663556048e-01, 2.704977910e-01, 2.746773121e-01,
+    2.788942635e-01, 2.831487404e-01, 2.874408377e-01, 2.917706498e-01, 2.961382708e-01, 3.005437944e-01, 3.049873141e-01, 3.094689228e-01,
+    3.139887134e-01, 3.185467781e-01, 3.231432091e-01, 3.277780981e-01, 3.324515363e-01, 3.371636150e-01, 3.419144249e-01, 3.467040564e-01,
+    3.515325995e-01, 3.564001441e-01, 3.613067798e-01, 3.662525956e-01, 3.712376805e-01, 3.762621230e-01, 3.813260114e-01, 3.864294338e-01,
+    3.915724777e-01, 3.967552307e-01, 4.019777798e-01, 4.072402119e-01, 4.125426135e-01, 4.178850708e-01, 4.232676700e-01, 4.286904966e-01,
+    4.341536362e-01, 4.396571738e-01, 4.452011945e-01, 4.507857828e-01, 4.564110232e-01, 4.620769997e-01, 4.677837961e-01, 4.735314961e-01,
+    4.793201831e-01, 4.851499401e-01, 4.910208498e-01, 4.969329951e-01, 5.028864580e-01, 5.088813209e-01, 5.149176654e-01, 5.209955732e-01,
+    5.271151257e-01, 5.332764040e-01, 5.394794890e-01, 5.457244614e-01, 5.520114015e-01, 5.583403896e-01, 5.647115057e-01, 5.711248295e-01,
+    5.775804404e-01, 5.840784179e-01, 5.906188409e-01, 5.972017884e-01, 6.038273389e-01, 6.104955708e-01, 6.172065624e-01, 6.239603917e-01,
+    6.307571363e-01, 6.375968740e-01, 6.444796820e-01, 6.514056374e-01, 6.583748173e-01, 6.653872983e-01, 6.724431570e-01, 6.795424696e-01,
+    6.866853124e-01, 6.938717613e-01, 7.011018919e-01, 7.083757799e-01, 7.156935005e-01, 7.230551289e-01, 7.304607401e-01, 7.379104088e-01,
+    7.454042095e-01, 7.529422168e-01, 7.605245047e-01, 7.681511472e-01, 7.758222183e-01, 7.835377915e-01, 7.912979403e-01, 7.991027380e-01,
+    8.069522577e-01, 8.148465722e-01, 8.227857544e-01, 8.307698768e-01, 8.387990117e-01, 8.468732315e-01, 8.549926081e-01, 8.631572135e-01,
+    8.713671192e-01, 8.796223969e-01, 8.879231179e-01, 8.962693534e-01, 9.046611744e-01, 9.130986518e-01, 9.215818563e-01, 9.301108584e-01,
+    9.386857285e-01, 9.473065367e-01, 9.559733532e-01, 9.646862479e-01, 9.734452904e-01, 9.822505503e-01, 9.911020971e-01, 1.000000000e+00,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette;
+
+    #[test]
+    fn hsv_round_trip_keeps_the_color() {
+        let colors = [
+            Color::new(255, 0, 0),
+            Color::new(0, 255, 0),
+            Color::new(0, 0, 255),
+            Color::new(255, 255, 0),
+            Color::new(0, 255, 255),
+            Color::new(255, 0, 255),
+            Color::black(),
+            palette::SUNLIGHT,
+            palette::STONE,
+            palette::DIRT,
+            palette::OAK_LOG,
+            palette::RUBY_BLOCK,
+            palette::GLOWSTONE,
+            palette::MOONLIGHT,
+        ];
+        for color in colors {
+            let (h, s, v) = color.to_hsv();
+            assert_eq!(Color::from_hsv(h, s, v), color, "hsv ({}, {}, {})", h, s, v);
+        }
+    }
+
+    #[test]
+    fn hsv_of_known_colors() {
+        assert_eq!(Color::new(255, 0, 0).to_hsv(), (0.0, 1.0, 1.0));
+        assert_eq!(Color::new(0, 0, 255).to_hsv(), (240.0, 1.0, 1.0));
+        let (_, saturation, value) = palette::STONE.to_hsv();
+        assert_eq!(saturation, 0.0);
+        assert!((value - 125.0 / 255.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn from_hsv_wraps_hue_and_clamps_the_rest() {
+        assert_eq!(Color::from_hsv(360.0, 1.0, 1.0), Color::new(255, 0, 0));
+        assert_eq!(Color::from_hsv(-120.0, 1.0, 1.0), Color::new(0, 0, 255));
+        assert_eq!(Color::from_hsv(0.0, 2.0, 1.5), Color::new(255, 0, 0));
+    }
+
+    #[test]
+    fn multiplying_colors_filters_each_channel() {
+        let white = Color::new(255, 255, 255);
+        assert_eq!(palette::DIRT * white, palette::DIRT);
+        assert_eq!(palette::DIRT * Color::black(), Color::black());
+        assert_eq!(Color::new(255, 128, 0) * Color::new(128, 255, 255), Color::new(128, 128, 0));
+    }
+
+    #[test]
+    fn every_srgb_byte_survives_the_round_trip() {
+        for byte in 0..=255u8 {
+            assert_eq!(Color::new(byte, byte, byte).to_srgb_bytes(), [byte; 3]);
+        }
+    }
+
+    #[test]
+    fn blending_happens_in_linear_light() {
+        // Half of white in linear light is much brighter than byte 128.
+        let [r, g, b] = Color::black().lerp(Color::new(255, 255, 255), 0.5).to_srgb_bytes();
+        assert_eq!((r, g, b), (188, 188, 188));
+        assert!((Color::new(188, 188, 188).to_unit_rgb()[0] - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn light_adds_up_past_white_until_written_out() {
+        let white = Color::new(255, 255, 255);
+        let overbright = white + white + white;
+        assert_eq!(overbright.to_unit_rgb(), [3.0, 3.0, 3.0]);
+        assert_eq!(overbright.to_hex(), 0xFFFFFF);
+        assert_eq!((overbright * 0.25).to_unit_rgb(), [0.75, 0.75, 0.75]);
+        assert_eq!(Color::new(255, 0, 0).scale_channels(2.0, 2.0, 2.0).to_unit_rgb(), [2.0, 0.0, 0.0]);
+        assert_eq!(Color::from_linear(-1.0, 0.5, 4.0).to_unit_rgb(), [0.0, 0.5, 4.0]);
     }
 }
@@ -0,0 +1,312 @@
+use nalgebra_glm::Vec3;
+use crate::aabb::Aabb;
+use crate::cube::Cube;
+use crate::cuboid::Cuboid;
+use crate::layers;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::ray_intersect::{hit_interval, Intersect, RayIntersect};
+use crate::sphere::Sphere;
+
+// One stretch of a ray inside a solid. Both boundaries carry the solid's
+// outward normal, whichever way the ray crosses them.
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub enter: Intersect,
+    pub exit: Intersect,
+}
+
+// Solids report every span along the whole ray, ignoring its t range, so a
+// CSG node can combine them before clipping.
+// Solids live in the scene, which is built on a worker thread and handed
+// to the render loop, so they must be shareable across threads.
+pub trait Solid: RayIntersect + Send + Sync {
+    fn spans(&self, ray: &Ray) -> Vec<Span>;
+    fn bounds(&self) -> Aabb;
+}
+
+impl Solid for Cube {
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let aabb = self.aabb();
+        match hit_interval(&aabb.min, &aabb.max, &ray.origin, &ray.direction) {
+            Some((t_near, t_far)) => vec![Span { enter: self.surface_at(ray, t_near), exit: self.surface_at(ray, t_far) }],
+            None => vec![],
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.aabb()
+    }
+}
+
+impl Solid for Cuboid {
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let aabb = self.aabb();
+        match hit_interval(&aabb.min, &aabb.max, &ray.origin, &ray.direction) {
+            Some((t_near, t_far)) => vec![Span { enter: self.surface_at(ray, t_near), exit: self.surface_at(ray, t_far) }],
+            None => vec![],
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.aabb()
+    }
+}
+
+impl Solid for Sphere {
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        match self.hit_interval(ray) {
+            Some((t_near, t_far)) => vec![Span { enter: self.surface_at(ray, t_near), exit: self.surface_at(ray, t_far) }],
+            None => vec![],
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.aabb()
+    }
+}
+
+// The first boundary inside the ray's t range is the visible surface.
+pub fn nearest_in_range(spans: &[Span], ray: &Ray) -> Intersect {
+    for span in spans {
+        if ray.accepts(span.enter.distance) {
+            return span.enter.clone();
+        }
+        if span.enter.distance < ray.t_min && ray.accepts(span.exit.distance) {
+            return span.exit.clone().into_exit();
+        }
+    }
+    Intersect::empty()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+pub struct CsgNode {
+    pub op: CsgOp,
+    pub left: Box<dyn Solid>,
+    pub right: Box<dyn Solid>,
+    pub layers: u32,
+}
+
+impl CsgNode {
+    pub fn new(op: CsgOp, left: impl Solid + 'static, right: impl Solid + 'static) -> Self {
+        CsgNode { op, left: Box::new(left), right: Box::new(right), layers: layers::DEFAULT }
+    }
+
+    pub fn with_layers(mut self, layers: u32) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn union(left: impl Solid + 'static, right: impl Solid + 'static) -> Self {
+        CsgNode::new(CsgOp::Union, left, right)
+    }
+
+    pub fn intersection(left: impl Solid + 'static, right: impl Solid + 'static) -> Self {
+        CsgNode::new(CsgOp::Intersection, left, right)
+    }
+
+    pub fn difference(left: impl Solid + 'static, right: impl Solid + 'static) -> Self {
+        CsgNode::new(CsgOp::Difference, left, right)
+    }
+}
+
+struct Boundary {
+    hit: Intersect,
+    entering: bool,
+    from_left: bool,
+}
+
+fn boundaries(spans: Vec<Span>, from_left: bool) -> impl Iterator<Item = Boundary> {
+    spans.into_iter().flat_map(move |span| {
+        [
+            Boundary { hit: span.enter, entering: true, from_left },
+            Boundary { hit: span.exit, entering: false, from_left },
+        ]
+    })
+}
+
+impl Solid for CsgNode {
+    // Walks both children's boundaries in ray order and keeps the ones where
+    // the combined inside/outside state flips. Surfaces carved by the right
+    // child of a difference face the other way and take the left material.
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let mut events: Vec<Boundary> = boundaries(self.left.spans(ray), true)
+            .chain(boundaries(self.right.spans(ray), false))
+            .collect();
+        events.sort_by(|a, b| a.hit.distance.total_cmp(&b.hit.distance));
+
+        let mut spans = Vec::new();
+        let mut depth_left = 0;
+        let mut depth_right = 0;
+        let mut left_material: Option<Material> = None;
+        let mut open: Option<Intersect> = None;
+
+        for event in events {
+            let delta = if event.entering { 1 } else { -1 };
+            if event.from_left {
+                depth_left += delta;
+                if event.entering {
+                    left_material = Some(event.hit.material.clone());
+                }
+            } else {
+                depth_right += delta;
+            }
+
+            let inside = self.op.inside(depth_left > 0, depth_right > 0);
+            let mut hit = event.hit;
+            if self.op == CsgOp::Difference && !event.from_left {
+                hit.normal = -hit.normal;
+                if let Some(material) = &left_material {
+                    hit.material = material.clone();
+                }
+            }
+
+            match open.take() {
+                None if inside => open = Some(hit),
+                Some(enter) if !inside => spans.push(Span { enter, exit: hit }),
+                still_open => open = still_open,
+            }
+        }
+
+        spans
+    }
+
+    fn bounds(&self) -> Aabb {
+        match self.op {
+            CsgOp::Union => self.left.bounds().union(&self.right.bounds()),
+            CsgOp::Intersection | CsgOp::Difference => self.left.bounds(),
+        }
+    }
+}
+
+impl RayIntersect for CsgNode {
+    fn ray_intersect(&self, ray: &Ray) -> Intersect {
+        nearest_in_range(&self.spans(ray), ray)
+    }
+}
+
+// A wall standing on `base` with a round-topped doorway through its middle.
+// The doorway is a box topped by a sphere as wide as the opening, so the
+// wall should stay thin next to that width for the arch to read as round.
+pub fn arched_doorway(base: Vec3, wall_size: Vec3, door_width: f32, door_height: f32, material: Material) -> CsgNode {
+    let wall = Cuboid::new(base + Vec3::new(0.0, wall_size.y * 0.5, 0.0), wall_size * 0.5, material.clone());
+
+    let arch_radius = door_width * 0.5;
+    let jamb_height = (door_height - arch_radius).max(0.0);
+    let cut_depth = wall_size.z;
+    let opening = Cuboid::new(
+        base + Vec3::new(0.0, jamb_height * 0.5 - cut_depth * 0.5, 0.0),
+        Vec3::new(arch_radius, jamb_height * 0.5 + cut_depth * 0.5, cut_depth),
+        material.clone(),
+    );
+    let arch = Sphere::new(base + Vec3::new(0.0, jamb_height, 0.0), arch_radius, material);
+
+    CsgNode::difference(wall, CsgNode::union(opening, arch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn stone() -> Material {
+        Material::new(Color::new(125, 125, 125), 10.0, [1.0, 0.0, 0.0, 0.0], 1.0)
+    }
+
+    // A 1 x 1 wall, 0.1 thick, centered on z = 0, with a 0.4 wide, 0.6 tall
+    // doorway whose arch starts at y = 0.4.
+    fn doorway() -> CsgNode {
+        arched_doorway(Vec3::zeros(), Vec3::new(1.0, 1.0, 0.1), 0.4, 0.6, stone())
+    }
+
+    fn toward_wall(x: f32, y: f32) -> Ray {
+        Ray::primary(Vec3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn rays_through_the_doorway_pass() {
+        let door = doorway();
+        assert!(!door.ray_intersect(&toward_wall(0.0, 0.2)).is_intersecting);
+        assert!(!door.ray_intersect(&toward_wall(0.1, 0.5)).is_intersecting);
+    }
+
+    #[test]
+    fn rays_beside_the_doorway_hit_the_wall_face() {
+        let hit = doorway().ray_intersect(&toward_wall(0.3, 0.2));
+        assert!(hit.is_intersecting);
+        assert!(hit.entering);
+        assert!((hit.distance - 0.95).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let above_the_arch = doorway().ray_intersect(&toward_wall(0.0, 0.65));
+        assert!(above_the_arch.is_intersecting);
+    }
+
+    #[test]
+    fn rays_grazing_the_jamb_split_on_either_side_of_it() {
+        let door = doorway();
+        assert!(!door.ray_intersect(&toward_wall(0.199, 0.2)).is_intersecting);
+        assert!(door.ray_intersect(&toward_wall(0.201, 0.2)).is_intersecting);
+    }
+
+    #[test]
+    fn slanted_rays_hit_the_carved_jamb_with_the_wall_material() {
+        // Enters through the opening and runs into the right-hand jamb
+        // halfway through the wall.
+        let eye = Vec3::new(0.0, 0.2, 0.2);
+        let direction = (Vec3::new(0.3, 0.2, -0.05) - eye).normalize();
+        let hit = doorway().ray_intersect(&Ray::primary(eye, direction));
+
+        assert!(hit.is_intersecting);
+        assert!(hit.entering);
+        assert!((hit.point.x - 0.2).abs() < 1e-4);
+        assert_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
+        assert_eq!(hit.material, stone());
+    }
+
+    #[test]
+    fn rays_off_to_the_side_miss() {
+        let door = doorway();
+        assert!(!door.ray_intersect(&toward_wall(0.8, 0.2)).is_intersecting);
+        assert!(!door.ray_intersect(&toward_wall(0.0, 1.2)).is_intersecting);
+        assert!(door.spans(&Ray::primary(Vec3::new(0.0, 0.5, 1.0), Vec3::new(0.0, 0.0, 1.0))).is_empty());
+    }
+
+    #[test]
+    fn union_and_intersection_of_overlapping_cubes() {
+        let left = || Cube::new(Vec3::new(-0.25, 0.0, 0.0), 1.0, stone());
+        let right = || Cube::new(Vec3::new(0.25, 0.0, 0.0), 1.0, stone());
+        let along_x = Ray::primary(Vec3::new(-3.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let extents = |spans: Vec<Span>| spans.iter().map(|span| (span.enter.distance, span.exit.distance)).collect::<Vec<_>>();
+
+        assert_eq!(extents(CsgNode::union(left(), right()).spans(&along_x)), vec![(2.25, 3.75)]);
+        assert_eq!(extents(CsgNode::intersection(left(), right()).spans(&along_x)), vec![(2.75, 3.25)]);
+        assert_eq!(extents(CsgNode::difference(left(), right()).spans(&along_x)), vec![(2.25, 2.75)]);
+    }
+
+    #[test]
+    fn rays_starting_inside_report_the_exit() {
+        let block = CsgNode::union(Cube::new(Vec3::zeros(), 1.0, stone()), Sphere::new(Vec3::new(0.5, 0.0, 0.0), 0.25, stone()));
+        let hit = block.ray_intersect(&Ray::primary(Vec3::zeros(), Vec3::new(1.0, 0.0, 0.0)));
+        assert!(hit.is_intersecting);
+        assert!(!hit.entering);
+        assert!((hit.distance - 0.75).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
+    }
+}
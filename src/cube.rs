@@ -1,12 +1,57 @@
 use nalgebra_glm::Vec3;
+use crate::aabb::Aabb;
+use crate::layers;
 use crate::material::Material;
-use crate::ray_intersect::{Intersect, RayIntersect};
+use crate::ray::Ray;
+use crate::ray_intersect::{hit_interval, Intersect, RayIntersect};
+
+pub const FACE_RIGHT: usize = 0;
+pub const FACE_LEFT: usize = 1;
+pub const FACE_TOP: usize = 2;
+pub const FACE_BOTTOM: usize = 3;
+pub const FACE_FRONT: usize = 4;
+pub const FACE_BACK: usize = 5;
+
+pub fn face_index(face_normal: &Vec3) -> usize {
+    if face_normal.x > 0.0 {
+        FACE_RIGHT
+    } else if face_normal.x < 0.0 {
+        FACE_LEFT
+    } else if face_normal.y > 0.0 {
+        FACE_TOP
+    } else if face_normal.y < 0.0 {
+        FACE_BOTTOM
+    } else if face_normal.z > 0.0 {
+        FACE_FRONT
+    } else {
+        FACE_BACK
+    }
+}
+
+// The directions u and v grow in on a box face. v runs up the sides and
+// toward +z on the top and bottom; u is picked so u, v and the outward
+// normal are right-handed, which keeps every face's texture reading left
+// to right from outside instead of mirrored on half of them.
+pub fn face_tangents(face_normal: &Vec3) -> (Vec3, Vec3) {
+    let bitangent = if face_normal.y != 0.0 { Vec3::new(0.0, 0.0, 1.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+    (bitangent.cross(face_normal), bitangent)
+}
+
+// UVs on a box face, from a point's offset to the box's min corner and the
+// box's full size. A u that runs toward -axis starts from the far side.
+pub fn box_face_uv(local: &Vec3, size: &Vec3, face_normal: &Vec3) -> (f32, f32) {
+    let (tangent, bitangent) = face_tangents(face_normal);
+    let along = |axis: Vec3| axis.dot(local) + (-axis).sup(&Vec3::zeros()).dot(size);
+    (along(tangent), along(bitangent))
+}
 
 #[derive(Clone, Debug)]
 pub struct Cube {
     pub center: Vec3, 
     pub size: f32,     
     pub material: Material,
+    pub face_materials: Option<[Material; 6]>,
+    pub layers: u32,
 }
 
 
@@ -16,63 +61,317 @@ impl Cube {
             center,
             size,
             material,
+            face_materials: None,
+            layers: layers::DEFAULT,
         }
     }
-}
 
+    pub fn with_face_materials(center: Vec3, size: f32, face_materials: [Material; 6]) -> Self {
+        Cube {
+            face_materials: Some(face_materials.clone()),
+            ..Cube::new(center, size, face_materials[FACE_TOP].clone())
+        }
+    }
 
-impl RayIntersect for Cube {
-    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect {
-        let min = self.center - Vec3::new(self.size / 2.0, self.size / 2.0, self.size / 2.0);
-        let max = self.center + Vec3::new(self.size / 2.0, self.size / 2.0, self.size / 2.0);
+    pub fn with_layers(mut self, layers: u32) -> Self {
+        self.layers = layers;
+        self
+    }
 
-        
-        let inv_dir = Vec3::new(1.0, 1.0, 1.0).component_div(ray_direction);
+    pub fn face_material(&self, face_normal: &Vec3) -> Material {
+        match &self.face_materials {
+            Some(face_materials) => face_materials[face_index(face_normal)].clone(),
+            None => self.material.clone(),
+        }
+    }
 
-        
-        let t_min = (min - ray_origin).component_mul(&inv_dir);
-        let t_max = (max - ray_origin).component_mul(&inv_dir);
+    pub fn try_new(center: Vec3, size: f32, material: Material) -> Result<Self, String> {
+        if !size.is_finite() || size <= 0.0 {
+            return Err(format!("cube size must be a positive finite number, got {}", size));
+        }
+        if !center.iter().all(|c| c.is_finite()) {
+            return Err(format!("cube center must be finite, got {:?}", center));
+        }
+        Ok(Cube::new(center, size, material))
+    }
 
-        
-        let t1 = t_min.zip_map(&t_max, |a, b| a.min(b));
-        let t2 = t_min.zip_map(&t_max, |a, b| a.max(b));
+    pub fn try_with_face_materials(center: Vec3, size: f32, face_materials: [Material; 6]) -> Result<Self, String> {
+        Cube::try_new(center, size, face_materials[FACE_TOP].clone())?;
+        Ok(Cube::with_face_materials(center, size, face_materials))
+    }
 
-        let t_near = t1.max();  
-        let t_far = t2.min();   
+    // Face index of a point on the surface, ignoring any bevel.
+    pub fn face_at(&self, point: &Vec3) -> usize {
+        face_index(&self.compute_normal(*point))
+    }
 
-        if t_near > t_far || t_far < 0.0 {
-            return Intersect::empty();  
+    pub fn aabb(&self) -> Aabb {
+        let half = Vec3::new(self.size / 2.0, self.size / 2.0, self.size / 2.0);
+        Aabb::new(self.center - half, self.center + half)
+    }
+}
+
+
+impl RayIntersect for Cube {
+    fn ray_intersect(&self, ray: &Ray) -> Intersect {
+        debug_assert!(self.size > 0.0, "degenerate cube with size {}", self.size);
+
+        let aabb = self.aabb();
+        let (t_near, t_far) = match hit_interval(&aabb.min, &aabb.max, &ray.origin, &ray.direction) {
+            Some(interval) => interval,
+            None => return Intersect::empty(),
+        };
+        let entering = t_near >= ray.t_min;
+        let t = if entering { t_near } else { t_far };
+        if !ray.accepts(t) {
+            return Intersect::empty();
         }
 
-        
-        let point = ray_origin + ray_direction * t_near;
+        let hit = self.surface_at(ray, t);
+        if entering { hit } else { hit.into_exit() }
+    }
+}
 
-        
-        let normal = self.compute_normal(point);
+impl Cube {
+    // The hit at distance t along the ray, with the face's outward normal.
+    pub fn surface_at(&self, ray: &Ray, t: f32) -> Intersect {
+        let point = ray.point_at(t);
+        let face_normal = self.compute_normal(point);
+        let material = self.face_material(&face_normal);
+        let uv = self.face_uv(point, face_normal);
+        let (tangent, bitangent) = face_tangents(&face_normal);
+        let normal = if material.bevel > 0.0 {
+            self.bevel_normal(point, face_normal, material.bevel)
+        } else {
+            face_normal
+        };
 
-        Intersect::new(point, normal, t_near, self.material)
+        Intersect::new(point, normal, t, material)
+            .with_uv(uv)
+            .with_tangents(tangent, bitangent)
+            .with_extent(self.size)
     }
 }
 
 impl Cube {
     fn compute_normal(&self, point: Vec3) -> Vec3 {
-        let local_point = point - self.center;
-        let bias = 0.001;  
-
-        
-        if (local_point.x - self.size / 2.0).abs() < bias {
-            Vec3::new(1.0, 0.0, 0.0)  
-        } else if (local_point.x + self.size / 2.0).abs() < bias {
-            Vec3::new(-1.0, 0.0, 0.0)  
-        } else if (local_point.y - self.size / 2.0).abs() < bias {
-            Vec3::new(0.0, 1.0, 0.0)  
-        } else if (local_point.y + self.size / 2.0).abs() < bias {
-            Vec3::new(0.0, -1.0, 0.0)  
-        } else if (local_point.z - self.size / 2.0).abs() < bias {
-            Vec3::new(0.0, 0.0, 1.0)  
+        let local_point = (point - self.center) / (self.size / 2.0);
+        let distance = local_point.abs();
+
+        if distance.x >= distance.y && distance.x >= distance.z {
+            Vec3::new(local_point.x.signum(), 0.0, 0.0)
+        } else if distance.y >= distance.z {
+            Vec3::new(0.0, local_point.y.signum(), 0.0)
         } else {
-            Vec3::new(0.0, 0.0, -1.0)  
+            Vec3::new(0.0, 0.0, local_point.z.signum())
         }
     }
+
+    fn face_uv(&self, point: Vec3, face_normal: Vec3) -> (f32, f32) {
+        let local_point = (point - self.center) / self.size + Vec3::new(0.5, 0.5, 0.5);
+        let uv = box_face_uv(&local_point, &Vec3::new(1.0, 1.0, 1.0), &face_normal);
+        (uv.0.clamp(0.0, 1.0), uv.1.clamp(0.0, 1.0))
+    }
+
+    fn bevel_normal(&self, point: Vec3, face_normal: Vec3, bevel: f32) -> Vec3 {
+        let half = self.size / 2.0;
+        let local_point = (point - self.center) / half;
+        let band = bevel * 2.0;
+
+        let mut normal = face_normal;
+        for axis in 0..3 {
+            if face_normal[axis] != 0.0 {
+                continue;
+            }
+            let edge_distance = 1.0 - local_point[axis].abs();
+            if edge_distance < band {
+                let weight = 1.0 - edge_distance / band;
+                normal[axis] += local_point[axis].signum() * weight;
+            }
+        }
+
+        normal.normalize()
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn unit_cube() -> Cube {
+        let material = Material::new(Color::new(200, 200, 200), 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        Cube::new(Vec3::zeros(), 1.0, material)
+    }
+
+    const AXES: [Vec3; 6] = [
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(-1.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, -1.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(0.0, 0.0, -1.0),
+    ];
+
+    #[test]
+    fn axis_aligned_rays_hit_each_face() {
+        let cube = unit_cube();
+        for axis in AXES {
+            let ray = Ray::primary(axis * 3.0, -axis);
+            let hit = cube.ray_intersect(&ray);
+            assert!(hit.is_intersecting, "missed face {:?}", axis);
+            assert!(hit.entering);
+            assert!((hit.distance - 2.5).abs() < 1e-6);
+            assert!((hit.point - axis * 0.5).magnitude() < 1e-6);
+            assert_eq!(hit.normal, axis);
+        }
+    }
+
+    #[test]
+    fn ray_beside_the_cube_misses() {
+        let cube = unit_cube();
+        let ray = Ray::primary(Vec3::new(-3.0, 0.6, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(!cube.ray_intersect(&ray).is_intersecting);
+
+        let behind = Ray::primary(Vec3::new(3.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(!cube.ray_intersect(&behind).is_intersecting);
+    }
+
+    #[test]
+    fn ray_from_the_center_exits_through_the_far_face() {
+        let cube = unit_cube();
+        for axis in AXES {
+            let hit = cube.ray_intersect(&Ray::primary(Vec3::zeros(), axis));
+            assert!(hit.is_intersecting);
+            assert!(!hit.entering);
+            assert!((hit.distance - 0.5).abs() < 1e-6);
+            assert!((hit.point - axis * 0.5).magnitude() < 1e-6);
+            assert_eq!(hit.normal, -axis, "exit normal should point back toward the origin");
+        }
+    }
+
+    #[test]
+    fn ray_just_outside_enters_as_before() {
+        let cube = unit_cube();
+        let ray = Ray::primary(Vec3::new(-0.5001, 0.1, 0.2), Vec3::new(1.0, 0.0, 0.0));
+        let hit = cube.ray_intersect(&ray);
+        assert!(hit.is_intersecting);
+        assert!(hit.entering);
+        assert!((hit.distance - 0.0001).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn cube_respects_the_ray_t_range() {
+        let cube = unit_cube();
+        let origin = Vec3::new(-3.0, 0.0, 0.0);
+        let direction = Vec3::new(1.0, 0.0, 0.0);
+
+        let short_shadow = Ray::shadow(origin, direction * 2.0);
+        assert!(!cube.ray_intersect(&short_shadow).is_intersecting);
+
+        let long_shadow = Ray::shadow(origin, direction * 10.0);
+        assert!(cube.ray_intersect(&long_shadow).is_intersecting);
+
+        let clipped = Ray::primary(origin, direction).with_t_min(3.0);
+        let hit = cube.ray_intersect(&clipped);
+        assert!(hit.is_intersecting);
+        assert!(!hit.entering);
+        assert!((hit.distance - 3.5).abs() < 1e-6);
+
+        let past = Ray::primary(origin, direction).with_t_min(4.0);
+        assert!(!cube.ray_intersect(&past).is_intersecting);
+    }
+
+    #[test]
+    fn try_new_rejects_degenerate_sizes() {
+        let material = unit_cube().material;
+        for size in [0.0, -0.1, f32::NAN, f32::INFINITY] {
+            assert!(Cube::try_new(Vec3::zeros(), size, material.clone()).is_err(), "size {} accepted", size);
+        }
+        assert!(Cube::try_new(Vec3::zeros(), 0.1, material).is_ok());
+    }
+
+    #[test]
+    fn try_new_rejects_non_finite_centers() {
+        let material = unit_cube().material;
+        for center in [Vec3::new(f32::NAN, 0.0, 0.0), Vec3::new(0.0, f32::INFINITY, 0.0), Vec3::new(0.0, 0.0, f32::NEG_INFINITY)] {
+            assert!(Cube::try_new(center, 0.1, material.clone()).is_err(), "center {:?} accepted", center);
+        }
+    }
+
+    #[test]
+    fn try_with_face_materials_validates_like_try_new() {
+        let faces = std::array::from_fn(|_| unit_cube().material);
+        assert!(Cube::try_with_face_materials(Vec3::zeros(), 0.0, faces.clone()).is_err());
+        assert!(Cube::try_with_face_materials(Vec3::new(f32::NAN, 0.0, 0.0), 0.1, faces.clone()).is_err());
+        let cube = Cube::try_with_face_materials(Vec3::zeros(), 0.1, faces).unwrap();
+        assert!(cube.face_materials.is_some());
+    }
+
+    #[test]
+    fn bevel_follows_the_face_material() {
+        let flat = unit_cube().material;
+        let beveled = flat.clone().with_bevel(0.1);
+        let mut faces: [Material; 6] = std::array::from_fn(|_| beveled.clone());
+        faces[FACE_TOP] = flat;
+        let cube = Cube::with_face_materials(Vec3::zeros(), 1.0, faces);
+
+        let side = cube.ray_intersect(&Ray::primary(Vec3::new(2.0, 0.48, 0.0), Vec3::new(-1.0, 0.0, 0.0)));
+        assert!(side.normal.y > 0.0, "beveled side face stayed flat");
+        assert!((side.point - Vec3::new(0.5, 0.48, 0.0)).magnitude() < 1e-5);
+
+        let top = cube.ray_intersect(&Ray::primary(Vec3::new(0.48, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0)));
+        assert_eq!(top.normal, Vec3::new(0.0, 1.0, 0.0), "flat top face picked up the side bevel");
+    }
+
+    const UP: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+    const RIGHT: Vec3 = Vec3::new(1.0, 0.0, 0.0);
+    const FRONT: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+
+    fn close(uv: (f32, f32), expected: (f32, f32)) -> bool {
+        (uv.0 - expected.0).abs() < 1e-6 && (uv.1 - expected.1).abs() < 1e-6
+    }
+
+    #[test]
+    fn face_centers_map_to_the_middle_of_the_texture() {
+        let cube = unit_cube();
+        for axis in AXES {
+            let hit = cube.ray_intersect(&Ray::primary(axis * 2.0, -axis));
+            assert!(close(hit.uv, (0.5, 0.5)), "face {:?} gave {:?}", axis, hit.uv);
+        }
+    }
+
+    #[test]
+    fn face_corners_map_to_the_texture_corners() {
+        let cube = Cube::new(Vec3::new(1.0, 2.0, 3.0), 0.5, unit_cube().material);
+        let min = cube.aabb().min;
+        let max = cube.aabb().max;
+
+        assert!(close(cube.face_uv(Vec3::new(max.x, max.y, min.z), UP), (0.0, 0.0)));
+        assert!(close(cube.face_uv(Vec3::new(min.x, max.y, max.z), UP), (1.0, 1.0)));
+        assert!(close(cube.face_uv(Vec3::new(max.x, min.y + 0.25, max.z), RIGHT), (0.0, 0.5)));
+        assert!(close(cube.face_uv(Vec3::new(min.x + 0.125, max.y - 0.125, max.z), FRONT), (0.25, 0.75)));
+    }
+
+    // Seen from outside with v up, u has to grow toward the viewer's right
+    // on every face, or the texture shows mirrored. Opposite faces used to
+    // share a u direction, which mirrored one of each pair.
+    #[test]
+    fn no_face_is_mirrored() {
+        let cube = unit_cube();
+        for normal in AXES {
+            let (tangent, bitangent) = face_tangents(&normal);
+            assert!((tangent.cross(&bitangent) - normal).magnitude() < 1e-6, "face {:?}", normal);
+
+            // A camera looking straight at the face, with v as its up, the
+            // way the renderer builds its right vector.
+            let up = if normal.y != 0.0 { Vec3::new(0.0, 0.0, 1.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+            let right = (-normal).cross(&up);
+            let at = |offset: Vec3| cube.ray_intersect(&Ray::primary(normal * 2.0 + offset, -normal)).uv;
+            let (center, to_the_right, above) = (at(Vec3::zeros()), at(right * 0.1), at(up * 0.1));
+            assert!(to_the_right.0 > center.0 && (to_the_right.1 - center.1).abs() < 1e-6, "face {:?}", normal);
+            assert!(above.1 > center.1 && (above.0 - center.0).abs() < 1e-6, "face {:?}", normal);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use nalgebra_glm::Vec3;
+use crate::aabb::Aabb;
+use crate::cube::{box_face_uv, face_tangents};
+use crate::layers;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::ray_intersect::{hit_interval, Intersect, RayIntersect};
+
+// A box with its own size along each axis, for walls and slabs that a
+// cube can't cover.
+#[derive(Clone, Debug)]
+pub struct Cuboid {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+    pub material: Material,
+    pub layers: u32,
+    // Turn about the vertical axis through the center, in radians, for
+    // things that face where they are going.
+    pub yaw: f32,
+}
+
+// Turns a vector about the vertical axis; positive angles take +x toward -z.
+fn rotate_y(vector: &Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(vector.x * cos + vector.z * sin, vector.y, -vector.x * sin + vector.z * cos)
+}
+
+impl Cuboid {
+    pub fn new(center: Vec3, half_extents: Vec3, material: Material) -> Self {
+        Cuboid { center, half_extents, material, layers: layers::DEFAULT, yaw: 0.0 }
+    }
+
+    pub fn with_layers(mut self, layers: u32) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn with_yaw(mut self, yaw: f32) -> Self {
+        self.yaw = yaw;
+        self
+    }
+
+    // Bounds in the world, which grow to hold the box when it is turned.
+    pub fn aabb(&self) -> Aabb {
+        let (sin, cos) = (self.yaw.sin().abs(), self.yaw.cos().abs());
+        let half = &self.half_extents;
+        let extent = Vec3::new(cos * half.x + sin * half.z, half.y, sin * half.x + cos * half.z);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+
+    fn local_aabb(&self) -> Aabb {
+        Aabb::new(self.center - self.half_extents, self.center + self.half_extents)
+    }
+
+    fn face_normal(&self, point: &Vec3) -> Vec3 {
+        let local = (point - self.center).component_div(&self.half_extents);
+        let distance = local.abs();
+
+        if distance.x >= distance.y && distance.x >= distance.z {
+            Vec3::new(local.x.signum(), 0.0, 0.0)
+        } else if distance.y >= distance.z {
+            Vec3::new(0.0, local.y.signum(), 0.0)
+        } else {
+            Vec3::new(0.0, 0.0, local.z.signum())
+        }
+    }
+
+    // UVs span the face in world units so textures keep their scale on
+    // long walls instead of stretching.
+    fn face_uv(&self, point: &Vec3, face_normal: &Vec3) -> (f32, f32) {
+        let local = point - (self.center - self.half_extents);
+        box_face_uv(&local, &(self.half_extents * 2.0), face_normal)
+    }
+
+    pub fn surface_at(&self, ray: &Ray, t: f32) -> Intersect {
+        let point = ray.point_at(t);
+        let normal = self.face_normal(&point);
+        let (tangent, bitangent) = face_tangents(&normal);
+
+        Intersect::new(point, normal, t, self.material.clone())
+            .with_uv(self.face_uv(&point, &normal))
+            .with_tangents(tangent, bitangent)
+            .with_extent(2.0 * self.half_extents.min())
+    }
+}
+
+impl RayIntersect for Cuboid {
+    // A turned box is hit by turning the ray the other way around its
+    // center, then turning the hit back.
+    fn ray_intersect(&self, ray: &Ray) -> Intersect {
+        if self.yaw == 0.0 {
+            return self.aligned_intersect(ray);
+        }
+        let local_ray = Ray {
+            origin: self.center + rotate_y(&(ray.origin - self.center), -self.yaw),
+            direction: rotate_y(&ray.direction, -self.yaw),
+            ..ray.clone()
+        };
+        let mut hit = self.aligned_intersect(&local_ray);
+        if hit.is_intersecting {
+            hit.point = self.center + rotate_y(&(hit.point - self.center), self.yaw);
+            hit.normal = rotate_y(&hit.normal, self.yaw);
+            hit.tangent = rotate_y(&hit.tangent, self.yaw);
+            hit.bitangent = rotate_y(&hit.bitangent, self.yaw);
+        }
+        hit
+    }
+}
+
+impl Cuboid {
+    fn aligned_intersect(&self, ray: &Ray) -> Intersect {
+        let aabb = self.local_aabb();
+        let (t_near, t_far) = match hit_interval(&aabb.min, &aabb.max, &ray.origin, &ray.direction) {
+            Some(interval) => interval,
+            None => return Intersect::empty(),
+        };
+        let entering = t_near >= ray.t_min;
+        let t = if entering { t_near } else { t_far };
+        if !ray.accepts(t) {
+            return Intersect::empty();
+        }
+
+        let hit = self.surface_at(ray, t);
+        if entering { hit } else { hit.into_exit() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn slab() -> Cuboid {
+        let material = Material::new(Color::new(125, 125, 125), 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        Cuboid::new(Vec3::zeros(), Vec3::new(1.0, 0.5, 0.1), material)
+    }
+
+    #[test]
+    fn each_axis_uses_its_own_extent() {
+        let slab = slab();
+        let cases = [
+            (Vec3::new(3.0, 0.0, 0.0), 2.0, Vec3::new(1.0, 0.0, 0.0)),
+            (Vec3::new(0.0, 3.0, 0.0), 2.5, Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(0.0, 0.0, 3.0), 2.9, Vec3::new(0.0, 0.0, 1.0)),
+        ];
+        for (origin, distance, normal) in cases {
+            let hit = slab.ray_intersect(&Ray::primary(origin, -origin.normalize()));
+            assert!(hit.is_intersecting);
+            assert!((hit.distance - distance).abs() < 1e-5);
+            assert_eq!(hit.normal, normal);
+        }
+    }
+
+    #[test]
+    fn rays_from_inside_exit_through_the_far_face() {
+        let hit = slab().ray_intersect(&Ray::primary(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0)));
+        assert!(hit.is_intersecting);
+        assert!(!hit.entering);
+        assert!((hit.distance - 0.1).abs() < 1e-6);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn turned_boxes_are_hit_along_their_own_axes() {
+        let turned = slab().with_yaw(std::f32::consts::FRAC_PI_2);
+        // A quarter turn swaps the long x side onto z.
+        let from_front = turned.ray_intersect(&Ray::primary(Vec3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0)));
+        assert!((from_front.distance - 2.0).abs() < 1e-5);
+        assert!((from_front.normal - Vec3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+        let from_side = turned.ray_intersect(&Ray::primary(Vec3::new(3.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)));
+        assert!((from_side.distance - 2.9).abs() < 1e-5);
+        assert!((from_side.point - Vec3::new(0.1, 0.0, 0.0)).magnitude() < 1e-5);
+
+        let bounds = turned.aabb();
+        assert!((bounds.max - Vec3::new(0.1, 0.5, 1.0)).magnitude() < 1e-5);
+        let diagonal = slab().with_yaw(std::f32::consts::FRAC_PI_4).aabb();
+        assert!((diagonal.max.x - 1.1 * std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::color::Color;
+use crate::texture::TextureHandle;
+
+pub const MAX_DECALS_PER_FACE: usize = 4;
+
+// An image stamped onto one face of one cube. The rectangle is in the face's
+// own UVs, so the same decal lands the same way on any cube size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decal {
+    pub image: TextureHandle,
+    pub cube: usize,
+    pub face: usize,
+    pub uv_min: (f32, f32),
+    pub uv_max: (f32, f32),
+}
+
+impl Decal {
+    pub fn new(image: TextureHandle, cube: usize, face: usize, uv_min: (f32, f32), uv_max: (f32, f32)) -> Self {
+        Decal { image, cube, face, uv_min, uv_max }
+    }
+
+    // Face UV mapped into the decal's own [0, 1] square, if it lands on it.
+    fn local_uv(&self, uv: (f32, f32)) -> Option<(f32, f32)> {
+        let width = self.uv_max.0 - self.uv_min.0;
+        let height = self.uv_max.1 - self.uv_min.1;
+        if width <= 0.0 || height <= 0.0 {
+            return None;
+        }
+        let u = (uv.0 - self.uv_min.0) / width;
+        let v = (uv.1 - self.uv_min.1) / height;
+        ((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)).then_some((u, v))
+    }
+
+    pub fn composite(&self, base: Color, uv: (f32, f32)) -> Color {
+        match self.local_uv(uv) {
+            Some((u, v)) => {
+                let image = self.image.get();
+                base.lerp(image.sample(u, v), image.sample_alpha(u, v))
+            }
+            None => base,
+        }
+    }
+}
+
+// Decals live on the scene rather than the material, so cubes sharing a
+// material can still carry different ones.
+#[derive(Debug, Clone, Default)]
+pub struct Decals {
+    decals: Vec<Decal>,
+}
+
+impl Decals {
+    pub fn add(&mut self, decal: Decal) -> Result<(), String> {
+        let on_face = self.on_face(decal.cube, decal.face).count();
+        if on_face >= MAX_DECALS_PER_FACE {
+            return Err(format!(
+                "cube {} face {} already has {} decals",
+                decal.cube, decal.face, MAX_DECALS_PER_FACE
+            ));
+        }
+        self.decals.push(decal);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+    }
+
+    pub fn on_face(&self, cube: usize, face: usize) -> impl Iterator<Item = &Decal> {
+        self.decals.iter().filter(move |decal| decal.cube == cube && decal.face == face)
+    }
+
+    // Later decals go over earlier ones.
+    pub fn composite(&self, cube: usize, face: usize, uv: (f32, f32), base: Color) -> Color {
+        self.on_face(cube, face).fold(base, |color, decal| decal.composite(color, uv))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::{FACE_FRONT, FACE_TOP};
+    use crate::texture::Texture;
+
+    const GRAY: Color = Color::new(100, 100, 100);
+    const RED: Color = Color::new(255, 0, 0);
+    const BLUE: Color = Color::new(0, 0, 255);
+
+    // Left texel opaque, right texel fully transparent.
+    fn half_clear(color: Color) -> TextureHandle {
+        let texture = Texture::from_texels(2, 1, vec![color, color]).unwrap().with_alpha(vec![255, 0]).unwrap();
+        TextureHandle::ready(texture)
+    }
+
+    fn solid(color: Color) -> TextureHandle {
+        TextureHandle::ready(Texture::from_texels(1, 1, vec![color]).unwrap())
+    }
+
+    #[test]
+    fn decals_cover_only_their_rectangle_and_opaque_texels() {
+        let decal = Decal::new(half_clear(RED), 0, FACE_FRONT, (0.25, 0.25), (0.75, 0.75));
+        assert_eq!(decal.composite(GRAY, (0.3, 0.5)), RED);
+        assert_eq!(decal.composite(GRAY, (0.7, 0.5)), GRAY);
+        assert_eq!(decal.composite(GRAY, (0.1, 0.5)), GRAY);
+    }
+
+    #[test]
+    fn later_decals_go_on_top_and_stay_on_their_face() {
+        let mut decals = Decals::default();
+        decals.add(Decal::new(solid(RED), 0, FACE_FRONT, (0.0, 0.0), (1.0, 1.0))).unwrap();
+        decals.add(Decal::new(solid(BLUE), 0, FACE_FRONT, (0.5, 0.0), (1.0, 1.0))).unwrap();
+
+        assert_eq!(decals.composite(0, FACE_FRONT, (0.25, 0.5), GRAY), RED);
+        assert_eq!(decals.composite(0, FACE_FRONT, (0.75, 0.5), GRAY), BLUE);
+        assert_eq!(decals.composite(0, FACE_TOP, (0.75, 0.5), GRAY), GRAY);
+        assert_eq!(decals.composite(1, FACE_FRONT, (0.75, 0.5), GRAY), GRAY);
+    }
+
+    #[test]
+    fn faces_refuse_decals_past_the_cap() {
+        let mut decals = Decals::default();
+        for _ in 0..MAX_DECALS_PER_FACE {
+            decals.add(Decal::new(solid(RED), 2, FACE_TOP, (0.0, 0.0), (1.0, 1.0))).unwrap();
+        }
+        assert!(decals.add(Decal::new(solid(RED), 2, FACE_TOP, (0.0, 0.0), (1.0, 1.0))).is_err());
+        assert!(decals.add(Decal::new(solid(RED), 2, FACE_FRONT, (0.0, 0.0), (1.0, 1.0))).is_ok());
+    }
+}
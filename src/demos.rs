@@ -0,0 +1,379 @@
+use nalgebra_glm::Vec3;
+
+use crate::aabb::Aabb;
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::cube::Cube;
+use crate::decal::Decals;
+use crate::light::Light;
+use crate::material::Material;
+use crate::noise::fractal_noise;
+use crate::palette;
+use crate::texture::{Texture, TextureHandle};
+use crate::waves::Waves;
+use crate::{load_skybox, Plane, Scene};
+
+const STRESS_LATTICE: f32 = 0.1;
+const STRESS_SIZES: [f32; 3] = [0.05, 0.1, 0.2];
+
+// Small xorshift generator so a seed always scatters the same scene.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift, so it gets nudged.
+        XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn stress_materials() -> Vec<Material> {
+    vec![
+        Material::new(palette::STONE, 10.0, [0.9, 0.1, 0.0, 0.0], 1.0),
+        Material::new(palette::DIRT, 5.0, [1.0, 0.0, 0.0, 0.0], 1.0),
+        Material::new(palette::OAK_LOG, 10.0, [0.8, 0.2, 0.0, 0.0], 1.0),
+        Material::new(palette::COBBLESTONE, 20.0, [0.6, 0.2, 0.2, 0.0], 1.0),
+        Material::new(palette::WATER, 50.0, [0.2, 0.2, 0.1, 0.5], 1.33),
+        Material::metal(palette::GOLD_BLOCK),
+    ]
+}
+
+pub fn stress_region() -> Aabb {
+    Aabb::new(Vec3::new(-2.0, 0.0, -2.0), Vec3::new(2.0, 1.0, 2.0))
+}
+
+pub fn stress_scene(n: usize, seed: u64) -> Vec<Cube> {
+    stress_scene_in(n, seed, &stress_region())
+}
+
+// Centers snap to a lattice inside the region; cubes bigger than the lattice
+// step may overlap their neighbours, which is fine for timing runs.
+pub fn stress_scene_in(n: usize, seed: u64, region: &Aabb) -> Vec<Cube> {
+    let mut rng = XorShift::new(seed);
+    let materials = stress_materials();
+    let extent = region.max - region.min;
+    let steps = [0, 1, 2].map(|axis| ((extent[axis] / STRESS_LATTICE) as usize).max(1));
+
+    (0..n)
+        .map(|_| {
+            let size = STRESS_SIZES[rng.below(STRESS_SIZES.len())];
+            let center = Vec3::new(
+                region.min.x + rng.below(steps[0]) as f32 * STRESS_LATTICE,
+                region.min.y + rng.below(steps[1]) as f32 * STRESS_LATTICE + size * 0.5,
+                region.min.z + rng.below(steps[2]) as f32 * STRESS_LATTICE,
+            );
+            Cube::new(center, size, materials[rng.below(materials.len())].clone())
+        })
+        .collect()
+}
+
+pub const MIRROR_LAKE_CUBE: Vec3 = Vec3::new(0.0, 0.45, -0.65);
+pub const MIRROR_LAKE_CUBE_SIZE: f32 = 0.1;
+pub const MIRROR_LAKE_EYE: Vec3 = Vec3::new(0.0, 0.2, 0.95);
+
+// Validation scene for the reflection math: a perfect horizontal mirror and
+// one red cube above it, seen by a camera looking straight down -z. The
+// reflection must match a real cube at the mirror image (x, -y, z). The
+// cube glows a flat red so lighting can't shift its silhouette, and since
+// the ground plane only spans [-1, 1] in x and z everything stays inside
+// that square.
+pub fn mirror_lake() -> (Scene, Camera) {
+    let mirror = Material::new(Color::black(), 10.0, [0.0, 0.0, 1.0, 0.0], 1.0);
+    let plane = Plane::try_new(Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0), mirror, None).expect("invalid mirror plane");
+
+    let scene = Scene {
+        plane,
+        cubes: vec![Cube::new(MIRROR_LAKE_CUBE, MIRROR_LAKE_CUBE_SIZE, glowing_red())],
+        cuboids: vec![],
+        decorations: vec![],
+        solids: vec![],
+        lights: vec![Light::directional(Vec3::new(0.0, -0.5, -1.0), palette::SUNLIGHT, 1.0)],
+        decals: Decals::default(),
+        skybox: load_skybox(),
+        probe: None,
+        waves: Waves::default(),
+    };
+    let camera = Camera::new(
+        MIRROR_LAKE_EYE,
+        MIRROR_LAKE_EYE + Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+    (scene, camera)
+}
+
+fn glowing_red() -> Material {
+    glowing(Color::new(220, 20, 20))
+}
+
+fn glowing(color: Color) -> Material {
+    let dark = Material::new(Color::black(), 10.0, [0.0, 0.0, 0.0, 0.0], 1.0);
+    let glow = Texture::from_texels(1, 1, vec![color]).expect("1x1 texture");
+    dark.with_emissive_map(TextureHandle::ready(glow))
+}
+
+pub const CAVE_CELL: f32 = 0.1;
+pub const CAVE_CELLS: [usize; 3] = [12, 7, 12];
+const CAVE_SEED: u32 = 11;
+const CAVE_NOISE_SCALE: f32 = 3.5;
+const CAVE_CARVE_THRESHOLD: f32 = 0.52;
+// The middle of the cave is always open so the camera has room to stand.
+const CAVE_CHAMBER_RADIUS: f32 = 0.22;
+const CAVE_CRYSTALS: [Color; 3] = [palette::RUBY_BLOCK, palette::EMERALD_BLOCK, palette::DIAMOND_BLOCK];
+const CRYSTAL_SIZE: f32 = 0.05;
+const CRYSTAL_INTENSITY: f32 = 0.9;
+const CRYSTAL_RADIUS: f32 = 0.5;
+
+fn cave_center(cell: [usize; 3]) -> Vec3 {
+    let offset = |axis: usize| (cell[axis] as f32 + 0.5 - CAVE_CELLS[axis] as f32 * 0.5) * CAVE_CELL;
+    Vec3::new(offset(0), offset(1), offset(2))
+}
+
+// Solid cells of the cave lattice. The outer shell is always rock; inside
+// it, noise above the threshold carves air.
+fn cave_rock(cell: [usize; 3]) -> bool {
+    let shell = (0..3).any(|axis| cell[axis] == 0 || cell[axis] + 1 == CAVE_CELLS[axis]);
+    if shell {
+        return true;
+    }
+    let center = cave_center(cell);
+    if center.norm() < CAVE_CHAMBER_RADIUS {
+        return false;
+    }
+    fractal_noise(&(center * CAVE_NOISE_SCALE), CAVE_SEED) < CAVE_CARVE_THRESHOLD
+}
+
+fn cave_cells() -> impl Iterator<Item = [usize; 3]> {
+    (0..CAVE_CELLS[0]).flat_map(|x| (0..CAVE_CELLS[1]).flat_map(move |y| (0..CAVE_CELLS[2]).map(move |z| [x, y, z])))
+}
+
+fn neighbours(cell: [usize; 3]) -> impl Iterator<Item = [usize; 3]> {
+    (0..3).flat_map(move |axis| {
+        [cell[axis].checked_sub(1), Some(cell[axis] + 1).filter(|&i| i < CAVE_CELLS[axis])]
+            .into_iter()
+            .flatten()
+            .map(move |i| {
+                let mut neighbour = cell;
+                neighbour[axis] = i;
+                neighbour
+            })
+    })
+}
+
+// Rock with no air next to it can't be seen or block anything that isn't
+// already blocked, so only the walls around the carved space become cubes.
+pub fn cave_walls() -> Vec<Cube> {
+    let stone = Material::new(palette::STONE, 10.0, [0.9, 0.1, 0.0, 0.0], 1.0);
+    cave_cells()
+        .filter(|&cell| cave_rock(cell) && neighbours(cell).any(|neighbour| !cave_rock(neighbour)))
+        .map(|cell| Cube::new(cave_center(cell), CAVE_CELL, stone.clone()))
+        .collect()
+}
+
+// Open cells with rock underneath, nearest the chamber first, so crystals
+// sit on the floor where the camera can see them.
+fn cave_floor_spots() -> Vec<Vec3> {
+    let mut spots: Vec<Vec3> = cave_cells()
+        .filter(|&[x, y, z]| y > 0 && !cave_rock([x, y, z]) && cave_rock([x, y - 1, z]))
+        .map(|cell| cave_center(cell) - Vec3::new(0.0, (CAVE_CELL - CRYSTAL_SIZE) * 0.5, 0.0))
+        .collect();
+    spots.sort_by(|a, b| a.norm().total_cmp(&b.norm()));
+    spots
+}
+
+// Interior lighting testbed: a sealed rock box with a noise-carved hollow,
+// lit only by a few glowing crystals with a colored light each. The sky
+// adds no ambient, so any light leaking through a seam shows right away.
+pub fn cave() -> (Scene, Camera) {
+    let spots = cave_floor_spots();
+    // Spread the crystals out instead of clustering them on the nearest spots.
+    let stride = (spots.len() / CAVE_CRYSTALS.len()).clamp(1, 4);
+    let crystals: Vec<(Vec3, Color)> = spots.iter().step_by(stride).copied().zip(CAVE_CRYSTALS).collect();
+
+    let mut cubes = cave_walls();
+    let mut lights = Vec::new();
+    for &(position, color) in &crystals {
+        cubes.push(Cube::new(position, CRYSTAL_SIZE, glowing(color)));
+        let above = position + Vec3::new(0.0, CRYSTAL_SIZE, 0.0);
+        lights.push(Light::new(above, color, CRYSTAL_INTENSITY).with_radius(CRYSTAL_RADIUS));
+    }
+
+    let floor = Vec3::new(0.0, -(CAVE_CELLS[1] as f32) * CAVE_CELL, 0.0);
+    let stone = Material::new(palette::STONE, 10.0, [0.9, 0.1, 0.0, 0.0], 1.0);
+    let plane = Plane::try_new(floor, Vec3::new(0.0, 1.0, 0.0), stone, None).expect("invalid cave floor");
+
+    let mut skybox = load_skybox();
+    skybox.ambient_factor = 0.0;
+    skybox.show_clouds = false;
+    skybox.show_birds = false;
+
+    let scene = Scene {
+        plane,
+        cubes,
+        cuboids: vec![],
+        decorations: vec![],
+        solids: vec![],
+        lights,
+        decals: Decals::default(),
+        skybox,
+        probe: None,
+        waves: Waves::default(),
+    };
+    let camera = Camera::new(
+        Vec3::new(-0.15, 0.05, -0.05),
+        Vec3::new(0.3, -0.1, 0.1),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+    (scene, camera)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+    use crate::settings::RenderSettings;
+    use crate::render;
+
+    #[test]
+    fn same_seed_scatters_the_same_cubes() {
+        let first = stress_scene(50, 7);
+        let again = stress_scene(50, 7);
+        let other = stress_scene(50, 8);
+        let centers = |cubes: &[Cube]| cubes.iter().map(|cube| cube.center).collect::<Vec<_>>();
+
+        assert_eq!(first.len(), 50);
+        assert_eq!(centers(&first), centers(&again));
+        assert_ne!(centers(&first), centers(&other));
+    }
+
+    #[test]
+    fn cubes_stay_on_the_lattice_inside_the_region() {
+        let region = Aabb::new(Vec3::new(-0.5, 0.0, -0.5), Vec3::new(0.5, 0.5, 0.5));
+        for cube in stress_scene_in(500, 3, &region) {
+            assert!(STRESS_SIZES.contains(&cube.size));
+            assert!(region.contains(&Vec3::new(cube.center.x, cube.center.y - cube.size * 0.5, cube.center.z)));
+            let steps = (cube.center.x - region.min.x) / STRESS_LATTICE;
+            assert!((steps - steps.round()).abs() < 1e-3);
+        }
+    }
+
+    fn red_centroid(framebuffer: &Framebuffer, rows: std::ops::Range<usize>) -> (f32, f32) {
+        let (mut sum_x, mut sum_y, mut count) = (0.0, 0.0, 0.0);
+        for y in rows {
+            for x in 0..framebuffer.width {
+                let pixel = framebuffer.buffer[y * framebuffer.width + x];
+                let (r, g, b) = ((pixel >> 16) & 0xff, (pixel >> 8) & 0xff, pixel & 0xff);
+                if r > 30 && r > 2 * (g + b) {
+                    sum_x += x as f32;
+                    sum_y += y as f32;
+                    count += 1.0;
+                }
+            }
+        }
+        assert!(count > 0.0, "no red pixels found");
+        (sum_x / count, sum_y / count)
+    }
+
+    fn lake_render(scene: &Scene, camera: &Camera) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(160, 120);
+        render(&mut framebuffer, scene, camera, false, &RenderSettings::default());
+        framebuffer
+    }
+
+    #[test]
+    fn mirror_lake_reflection_lands_on_the_mirror_image() {
+        let (scene, camera) = mirror_lake();
+        let lake = lake_render(&scene, &camera);
+        let horizon = lake.height / 2;
+        let above = red_centroid(&lake, 0..horizon);
+        let below = red_centroid(&lake, horizon..lake.height);
+
+        // The same cube placed for real at its mirror image, with the plane
+        // moved out of the way, is what the reflection has to look like.
+        let (mut image, _) = mirror_lake();
+        image.plane.point.y = -10.0;
+        image.cubes[0].center.y = -MIRROR_LAKE_CUBE.y;
+        let expected = red_centroid(&lake_render(&image, &camera), 0..lake.height);
+
+        assert!((above.0 - below.0).abs() < 1.0, "columns differ: {:?} vs {:?}", above, below);
+        assert!((below.0 - expected.0).abs() < 1.0 && (below.1 - expected.1).abs() < 1.0, "reflection at {:?}, expected {:?}", below, expected);
+        assert!(above.1 < horizon as f32 && below.1 > horizon as f32);
+    }
+
+    #[test]
+    fn cave_is_sealed() {
+        let (scene, camera) = cave();
+        for i in 0..400 {
+            // Fibonacci sphere, so every direction out of the chamber is tried.
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / 400.0;
+            let angle = i as f32 * 2.399_963;
+            let ring = (1.0 - y * y).sqrt();
+            let direction = Vec3::new(ring * angle.cos(), y, ring * angle.sin());
+            let hit = crate::scene_intersect(&crate::ray::Ray::primary(camera.eye, direction), &scene);
+            assert!(hit.is_intersecting, "sky visible along {:?}", direction);
+            let inside = (0..3).all(|axis| hit.point[axis].abs() <= CAVE_CELLS[axis] as f32 * CAVE_CELL * 0.5 + 1e-4);
+            assert!(inside, "escaped to {:?}", hit.point);
+        }
+    }
+
+    #[test]
+    fn only_rock_next_to_air_becomes_cubes() {
+        let walls = cave_walls();
+        let cells = CAVE_CELLS.iter().product::<usize>();
+        let rock = cave_cells().filter(|&cell| cave_rock(cell)).count();
+        assert!(!walls.is_empty() && walls.len() < rock && rock < cells);
+    }
+
+    // Renders the cave and compares it with an image in golden/; run with
+    // UPDATE_GOLDEN=1 to write it again after an intended change to the
+    // lighting. Up to `max_differing` pixels may be off by more than
+    // `tolerance` in some channel.
+    fn check_golden(name: &str, tolerance: i32, max_differing: usize) {
+        let path = format!("{}/golden/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let (scene, camera) = cave();
+        let mut framebuffer = Framebuffer::new(64, 48);
+        render(&mut framebuffer, &scene, &camera, false, &RenderSettings::default());
+        let pixels: Vec<[u8; 3]> = framebuffer.buffer.iter().map(|&p| [(p >> 16) as u8, (p >> 8) as u8, p as u8]).collect();
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let mut golden = image::RgbImage::new(64, 48);
+            for (pixel, rgb) in golden.pixels_mut().zip(&pixels) {
+                *pixel = image::Rgb(*rgb);
+            }
+            golden.save(&path).expect("could not write the golden image");
+        }
+
+        let golden = image::open(&path).expect("missing golden image, run with UPDATE_GOLDEN=1").to_rgb8();
+        assert_eq!(golden.dimensions(), (64, 48));
+        let differing = golden
+            .pixels()
+            .zip(&pixels)
+            .filter(|(expected, actual)| (0..3).any(|c| (expected[c] as i32 - actual[c] as i32).abs() > tolerance))
+            .count();
+        assert!(differing <= max_differing, "{} pixels differ from {}", differing, name);
+    }
+
+    // Platform math may round a little differently, so a few pixels can
+    // drift.
+    #[test]
+    fn cave_matches_its_golden_image() {
+        check_golden("cave.png", 4, 64 * 48 / 100);
+    }
+
+    // With portable math the render has to match to the bit on any machine.
+    #[cfg(feature = "reproducible")]
+    #[test]
+    fn reproducible_cave_matches_its_golden_image_exactly() {
+        crate::math::set_reproducible(true);
+        check_golden("cave_reproducible.png", 0, 0);
+    }
+}
@@ -0,0 +1,177 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+use crate::aabb::Aabb;
+use crate::cuboid::Cuboid;
+use crate::material::Material;
+use crate::noise::value_noise;
+
+// A fish is a flat little box, longest along its own x, which is where it
+// swims.
+pub const FISH_HALF_EXTENTS: [f32; 3] = [0.012, 0.005, 0.003];
+const SWIM_SPEED: f32 = 0.03;
+const MAX_TURN_RATE: f32 = 2.5;
+const MAX_CLIMB_SPEED: f32 = 0.01;
+// How many wander changes a fish makes per second.
+const WANDER_RATE: f32 = 0.7;
+// Fish start turning back toward the middle this far from a side.
+const EDGE_MARGIN: f32 = 0.015;
+
+#[derive(Debug, Clone)]
+pub struct Fish {
+    pub position: Vec3,
+    pub yaw: f32,
+    seed: u32,
+}
+
+impl Fish {
+    // Yaw turns +x toward -z, the same way as a turned cuboid, so the box
+    // faces this direction.
+    pub fn heading(&self) -> Vec3 {
+        Vec3::new(self.yaw.cos(), 0.0, -self.yaw.sin())
+    }
+}
+
+// A few fish wandering inside a box of water. Heading and depth drift with
+// smooth noise, and each fish turns back toward the middle before it
+// reaches a side, the floor or the surface.
+#[derive(Debug, Clone)]
+pub struct School {
+    pub fish: Vec<Fish>,
+    pub water: Aabb,
+    time: f32,
+}
+
+impl School {
+    pub fn new(water: Aabb, count: usize, seed: u32) -> Self {
+        let swim = swimmable(&water);
+        let fish = (0..count as u32)
+            .map(|i| {
+                let fish_seed = seed.wrapping_add(i.wrapping_mul(0x9e37_79b9));
+                let spot = |axis: f32| value_noise(&Vec3::new(axis, 0.5, 0.5), fish_seed);
+                let offset = Vec3::new(spot(0.5), spot(1.5), spot(2.5));
+                Fish {
+                    position: swim.min + (swim.max - swim.min).component_mul(&offset),
+                    yaw: spot(3.5) * 2.0 * PI,
+                    seed: fish_seed,
+                }
+            })
+            .collect();
+        School { fish, water, time: 0.0 }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+        let swim = swimmable(&self.water);
+        let center = swim.center();
+
+        for fish in &mut self.fish {
+            let wander = |channel: f32| {
+                value_noise(&Vec3::new(self.time * WANDER_RATE, channel, 0.5), fish.seed) * 2.0 - 1.0
+            };
+            let ahead = fish.position + fish.heading() * EDGE_MARGIN;
+            let near_side = ahead.x < swim.min.x || ahead.x > swim.max.x || ahead.z < swim.min.z || ahead.z > swim.max.z;
+            let turn = if near_side {
+                let toward_center = center - fish.position;
+                let wanted = (-toward_center.z).atan2(toward_center.x);
+                let difference = (wanted - fish.yaw + PI).rem_euclid(2.0 * PI) - PI;
+                difference.signum() * MAX_TURN_RATE
+            } else {
+                wander(0.5) * MAX_TURN_RATE
+            };
+            fish.yaw = (fish.yaw + turn * dt).rem_euclid(2.0 * PI);
+
+            let climb = if fish.position.y < swim.min.y + EDGE_MARGIN {
+                MAX_CLIMB_SPEED
+            } else if fish.position.y > swim.max.y - EDGE_MARGIN {
+                -MAX_CLIMB_SPEED
+            } else {
+                wander(1.5) * MAX_CLIMB_SPEED
+            };
+
+            let moved = fish.position + fish.heading() * (SWIM_SPEED * dt) + Vec3::new(0.0, climb * dt, 0.0);
+            fish.position = moved.sup(&swim.min).inf(&swim.max);
+        }
+    }
+
+    pub fn cuboids(&self, material: &Material, layers: u32) -> Vec<Cuboid> {
+        let [x, y, z] = FISH_HALF_EXTENTS;
+        self.fish
+            .iter()
+            .map(|fish| {
+                Cuboid::new(fish.position, Vec3::new(x, y, z), material.clone())
+                    .with_layers(layers)
+                    .with_yaw(fish.yaw)
+            })
+            .collect()
+    }
+
+    // Moves the boxes made by `cuboids` to where the fish are now.
+    pub fn place(&self, cuboids: &mut [Cuboid]) {
+        for (cuboid, fish) in cuboids.iter_mut().zip(&self.fish) {
+            cuboid.center = fish.position;
+            cuboid.yaw = fish.yaw;
+        }
+    }
+}
+
+// Where a fish's center may go so its whole body, turned any way, stays
+// in the water.
+fn swimmable(water: &Aabb) -> Aabb {
+    let [length, height, width] = FISH_HALF_EXTENTS;
+    let reach = length.hypot(width);
+    let inset = Vec3::new(reach, height, reach);
+    Aabb::new(water.min + inset, water.max - inset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pond() -> Aabb {
+        Aabb::new(Vec3::new(-0.15, -0.1, -0.05), Vec3::new(0.05, 0.0, 0.15))
+    }
+
+    #[test]
+    fn fish_never_leave_the_water() {
+        let mut school = School::new(pond(), 6, 7);
+        for _ in 0..2000 {
+            school.update(1.0 / 60.0);
+            for cuboid in school.cuboids(&Material::black(), 1) {
+                let bounds = cuboid.aabb();
+                assert!(bounds.min.x >= -0.15 - 1e-5 && bounds.max.x <= 0.05 + 1e-5, "{:?}", bounds);
+                assert!(bounds.min.y >= -0.1 - 1e-5 && bounds.max.y <= 0.0 + 1e-5, "{:?}", bounds);
+                assert!(bounds.min.z >= -0.05 - 1e-5 && bounds.max.z <= 0.15 + 1e-5, "{:?}", bounds);
+            }
+        }
+    }
+
+    #[test]
+    fn fish_swim_the_way_they_face() {
+        let mut school = School::new(pond(), 1, 3);
+        school.fish[0].position = swimmable(&pond()).center();
+        let before = school.fish[0].position;
+        school.update(0.01);
+        let fish = &school.fish[0];
+        let moved = fish.position - before;
+        let horizontal = Vec3::new(moved.x, 0.0, moved.z).normalize();
+        assert!(horizontal.dot(&fish.heading()) > 0.99, "moved {:?} facing {:?}", moved, fish.heading());
+
+        let mut boxes = school.cuboids(&Material::black(), 1);
+        school.update(0.5);
+        school.place(&mut boxes);
+        assert_eq!(boxes[0].center, school.fish[0].position);
+        assert_eq!(boxes[0].yaw, school.fish[0].yaw);
+    }
+
+    #[test]
+    fn fish_wander_instead_of_swimming_straight() {
+        let mut school = School::new(pond(), 4, 11);
+        let start: Vec<f32> = school.fish.iter().map(|fish| fish.yaw).collect();
+        for _ in 0..120 {
+            school.update(1.0 / 60.0);
+        }
+        assert!(school.fish.iter().zip(&start).any(|(fish, yaw)| (fish.yaw - yaw).abs() > 0.05));
+        let first = School::new(pond(), 4, 11);
+        assert!(first.fish.iter().zip(&start).all(|(fish, yaw)| fish.yaw == *yaw));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::framebuffer::Framebuffer;
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+const GLYPH_SPACING: usize = 1;
+
+// A 3x5 pixel font, just enough for status text drawn into the
+// framebuffer. Each row is three bits, leftmost pixel in the high bit.
+// Letters are uppercase only; anything without a glyph shows as '?'.
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT] {
+    match character.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        ';' => [0b000, 0b010, 0b000, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+pub fn text_width(text: &str, scale: usize) -> usize {
+    let count = text.chars().count();
+    (count * (GLYPH_WIDTH + GLYPH_SPACING)).saturating_sub(GLYPH_SPACING) * scale
+}
+
+// Draws in the framebuffer's current color with (x, y) at the top left;
+// whatever falls outside the framebuffer is dropped.
+pub fn draw_text(framebuffer: &mut Framebuffer, x: usize, y: usize, text: &str, scale: usize) {
+    for (index, character) in text.chars().enumerate() {
+        let left = x + index * (GLYPH_WIDTH + GLYPH_SPACING) * scale;
+        for (row, bits) in glyph(character).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        framebuffer.point(left + column * scale + dx, y + row * scale + dy);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Splits on spaces so each line fits in `width` pixels; a single word too
+// long for a line is cut.
+pub fn wrap(text: &str, width: usize, scale: usize) -> Vec<String> {
+    let per_line = ((width / scale + GLYPH_SPACING) / (GLYPH_WIDTH + GLYPH_SPACING)).max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        while word.len() > per_line {
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            lines.push(word.drain(..per_line).collect());
+        }
+        let needed = if line.is_empty() { word.len() } else { line.chars().count() + 1 + word.len() };
+        if needed > per_line {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.extend(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(framebuffer: &Framebuffer) -> Vec<(usize, usize)> {
+        (0..framebuffer.height)
+            .flat_map(|y| (0..framebuffer.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| framebuffer.buffer[y * framebuffer.width + x] != 0)
+            .collect()
+    }
+
+    #[test]
+    fn glyphs_are_drawn_scaled_at_their_spot() {
+        let mut framebuffer = Framebuffer::new(20, 12);
+        framebuffer.set_current_color(0xffffff);
+        draw_text(&mut framebuffer, 2, 1, "L", 2);
+        let pixels = lit(&framebuffer);
+        // Two columns of the stem over five rows, plus the rest of the foot.
+        assert_eq!(pixels.len(), 2 * 2 * 5 + 2 * 2 * 2);
+        assert!(pixels.iter().all(|&(x, y)| (2..8).contains(&x) && (1..11).contains(&y)));
+        assert_eq!(text_width("L", 2), 6);
+        assert_eq!(text_width("", 2), 0);
+    }
+
+    #[test]
+    fn lowercase_and_unknown_characters_still_draw() {
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('~'), glyph('?'));
+        let mut framebuffer = Framebuffer::new(8, 8);
+        framebuffer.set_current_color(0xffffff);
+        draw_text(&mut framebuffer, 6, 6, "MMM", 1);
+        assert!(!lit(&framebuffer).is_empty());
+    }
+
+    #[test]
+    fn wrapped_lines_fit_the_width() {
+        let lines = wrap("no se encontro el archivo assets/pasto_muy_largo.png", 40, 1);
+        assert!(lines.iter().all(|line| text_width(line, 1) <= 40), "{:?}", lines);
+        assert_eq!(lines.concat().replace(' ', ""), "noseencontroelarchivoassets/pasto_muy_largo.png");
+        assert_eq!(lines[0], "no se");
+    }
+}
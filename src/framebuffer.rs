@@ -3,6 +3,9 @@ pub struct Framebuffer {
     pub width: usize,
     pub height: usize,
     pub buffer: Vec<u32>,
+    // Distance along the camera's forward axis to what each pixel shows,
+    // infinite where it shows the sky. Only filled when a pass needs it.
+    pub depth: Vec<f32>,
     background_color: u32,
     current_color: u32,
 }
@@ -13,6 +16,7 @@ impl Framebuffer {
             width,
             height,
             buffer: vec![0; width * height],
+            depth: vec![f32::INFINITY; width * height],
             background_color: 0x000000,
             current_color: 0xFFFFFF
         }
@@ -22,6 +26,9 @@ impl Framebuffer {
         for pixel in self.buffer.iter_mut() {
             *pixel = self.background_color;
         }
+        for depth in self.depth.iter_mut() {
+            *depth = f32::INFINITY;
+        }
     }
 
     pub fn point(&mut self, x: usize, y: usize) {
@@ -30,6 +37,12 @@ impl Framebuffer {
         }
     }
 
+    pub fn set_depth(&mut self, x: usize, y: usize, depth: f32) {
+        if x < self.width && y < self.height {
+            self.depth[y * self.width + x] = depth;
+        }
+    }
+
     pub fn set_background_color(&mut self, color: u32) {
         self.background_color = color;
     }
@@ -0,0 +1,95 @@
+use nalgebra_glm::Vec3;
+use crate::cuboid::Cuboid;
+use crate::material::Material;
+
+pub const SKIRT_TIERS: usize = 4;
+pub const SKIRT_TIER_HEIGHT: f32 = 0.12;
+// How much narrower the last tier is than the ground, as a fraction of it.
+pub const SKIRT_TAPER: f32 = 0.75;
+pub const RIM_THICKNESS: f32 = 0.05;
+// The rim stays this far under the ground so its top doesn't z-fight with it.
+const RIM_GAP: f32 = 0.002;
+
+// The underside of a floating island, built to fit a square ground of the
+// given half size. A rim of four walls closes the sides from the ground
+// down to `bottom`, leaving room for anything sunk into the ground such as
+// the pond, and below it slabs narrow tier by tier into a stepped point.
+pub fn skirt(center: Vec3, half_size: f32, bottom: f32, dirt: &Material, stone: &Material) -> Vec<Cuboid> {
+    let top = center.y - RIM_GAP;
+    let bottom = bottom.min(top - RIM_THICKNESS);
+    let rim_height = (top - bottom) * 0.5;
+    let rim_y = bottom + rim_height;
+    let inner = half_size - RIM_THICKNESS * 0.5;
+
+    let mut cuboids: Vec<Cuboid> = [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)]
+        .into_iter()
+        .map(|(x, z)| {
+            let offset = Vec3::new(x * inner, rim_y - center.y, z * inner);
+            let half_extents = if x != 0.0 {
+                Vec3::new(RIM_THICKNESS * 0.5, rim_height, half_size)
+            } else {
+                Vec3::new(half_size - RIM_THICKNESS, rim_height, RIM_THICKNESS * 0.5)
+            };
+            Cuboid::new(center + offset, half_extents, dirt.clone())
+        })
+        .collect();
+
+    for tier in 0..SKIRT_TIERS {
+        let width = half_size * (1.0 - SKIRT_TAPER * tier as f32 / (SKIRT_TIERS - 1) as f32);
+        let y = bottom - SKIRT_TIER_HEIGHT * (tier as f32 + 0.5);
+        let material = if tier == 0 { dirt } else { stone };
+        cuboids.push(Cuboid::new(
+            Vec3::new(center.x, y, center.z),
+            Vec3::new(width, SKIRT_TIER_HEIGHT * 0.5, width),
+            material.clone(),
+        ));
+    }
+    cuboids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette;
+
+    fn skirt_under_ground(bottom: f32) -> Vec<Cuboid> {
+        let dirt = Material::new(palette::DIRT, 10.0, [0.9, 0.1, 0.0, 0.0], 1.0);
+        let stone = Material::new(palette::STONE, 10.0, [0.9, 0.1, 0.0, 0.0], 1.0);
+        skirt(Vec3::zeros(), 1.0, bottom, &dirt, &stone)
+    }
+
+    #[test]
+    fn skirt_stays_under_and_inside_the_ground() {
+        for cuboid in skirt_under_ground(-0.2) {
+            let aabb = cuboid.aabb();
+            assert!(aabb.max.y < 0.0, "{:?} pokes through the ground", aabb);
+            assert!(aabb.min.x >= -1.0 - 1e-6 && aabb.max.x <= 1.0 + 1e-6);
+            assert!(aabb.min.z >= -1.0 - 1e-6 && aabb.max.z <= 1.0 + 1e-6);
+        }
+    }
+
+    #[test]
+    fn rim_closes_the_sides_down_to_the_tiers() {
+        let cuboids = skirt_under_ground(-0.2);
+        let (rim, tiers) = cuboids.split_at(4);
+        for wall in rim {
+            assert!((wall.aabb().min.y + 0.2).abs() < 1e-6);
+            let outer = wall.aabb().min.x.abs().max(wall.aabb().max.x.abs()).max(wall.aabb().min.z.abs()).max(wall.aabb().max.z.abs());
+            assert!((outer - 1.0).abs() < 1e-6);
+        }
+        assert!((tiers[0].aabb().max.y + 0.2).abs() < 1e-6);
+        assert!((tiers[0].half_extents.x - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tiers_narrow_on_the_way_down() {
+        let cuboids = skirt_under_ground(-0.2);
+        let tiers = &cuboids[4..];
+        assert_eq!(tiers.len(), SKIRT_TIERS);
+        for pair in tiers.windows(2) {
+            assert!(pair[1].half_extents.x < pair[0].half_extents.x);
+            assert!(pair[1].center.y < pair[0].center.y);
+        }
+        assert!((tiers[SKIRT_TIERS - 1].half_extents.x - (1.0 - SKIRT_TAPER)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,27 @@
+// Objects carry a bitmask of the layers they belong to, and each kind of
+// ray only sees objects that share a bit with its own mask.
+pub const DEFAULT: u32 = 1 << 0;
+pub const ALL: u32 = u32::MAX;
+
+pub const NAMES: [&str; 4] = ["default", "trees", "water", "props"];
+
+pub fn named(name: &str) -> Option<u32> {
+    NAMES.iter().position(|layer| *layer == name).map(|bit| 1 << bit)
+}
+
+pub fn layer(name: &str) -> u32 {
+    named(name).unwrap_or_else(|| panic!("unknown layer {:?}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_map_to_distinct_bits() {
+        assert_eq!(named("default"), Some(DEFAULT));
+        let bits: Vec<u32> = NAMES.iter().map(|name| layer(name)).collect();
+        assert_eq!(bits.iter().fold(0, |mask, bit| mask | bit).count_ones() as usize, NAMES.len());
+        assert_eq!(named("clouds"), None);
+    }
+}
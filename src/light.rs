@@ -2,18 +2,186 @@
 use nalgebra_glm::Vec3;
 use crate::color::Color;
 
+// Directional lights have no position; shadow rays toward them stop this
+// far out, which is well past anything in the scene.
+const DIRECTIONAL_DISTANCE: f32 = 1.0e4;
+// Fill lights drop to half their intensity this far out.
+const FILL_RADIUS: f32 = 0.4;
+
+#[derive(Debug, Clone, Copy)]
+pub enum LightKind {
+    Point { position: Vec3 },
+    Directional { direction: Vec3 },
+    Spot { position: Vec3, direction: Vec3, inner_angle: f32, outer_angle: f32 },
+}
+
 pub struct Light {
-    pub position: Vec3,
+    pub kind: LightKind,
     pub color: Color,
     pub intensity: f32,
+    pub radius: f32,
+    pub area_radius: f32,
+    pub casts_shadows: bool,
 }
 
 impl Light {
     pub fn new(position: Vec3, color: Color, intensity: f32) -> Self {
         Light {
-            position,
+            kind: LightKind::Point { position },
+            color,
+            intensity,
+            radius: f32::INFINITY,
+            area_radius: 0.0,
+            casts_shadows: true,
+        }
+    }
+
+    // Fills fake bounce light and glows; they never trace shadow rays.
+    pub fn fill(position: Vec3, color: Color, intensity: f32) -> Self {
+        Light::new(position, color, intensity)
+            .with_radius(FILL_RADIUS)
+            .with_shadows(false)
+    }
+
+    pub fn directional(direction: Vec3, color: Color, intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Directional { direction: direction.normalize() },
+            color,
+            intensity,
+            radius: f32::INFINITY,
+            area_radius: 0.0,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+        color: Color,
+        intensity: f32,
+    ) -> Self {
+        Light {
+            kind: LightKind::Spot {
+                position,
+                direction: direction.normalize(),
+                inner_angle,
+                outer_angle: outer_angle.max(inner_angle),
+            },
             color,
             intensity,
+            radius: f32::INFINITY,
+            area_radius: 0.0,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_area_radius(mut self, area_radius: f32) -> Self {
+        self.area_radius = area_radius.max(0.0);
+        self
+    }
+
+    pub fn with_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        match self.kind {
+            LightKind::Point { .. } | LightKind::Spot { .. } => {
+                1.0 / (1.0 + (distance * distance) / (self.radius * self.radius))
+            }
+            LightKind::Directional { .. } => 1.0,
+        }
+    }
+
+    pub fn cone_factor(&self, light_dir: &Vec3) -> f32 {
+        match self.kind {
+            LightKind::Spot { direction, inner_angle, outer_angle, .. } => {
+                let cos_angle = direction.dot(&-light_dir);
+                let cos_inner = inner_angle.cos();
+                let cos_outer = outer_angle.cos();
+                if cos_inner - cos_outer <= f32::EPSILON {
+                    return if cos_angle >= cos_inner { 1.0 } else { 0.0 };
+                }
+                let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            }
+            LightKind::Point { .. } | LightKind::Directional { .. } => 1.0,
+        }
+    }
+
+    pub fn direction_from(&self, point: &Vec3) -> (Vec3, f32) {
+        match self.kind {
+            LightKind::Point { position } | LightKind::Spot { position, .. } => {
+                let to_light = position - point;
+                let distance = to_light.magnitude();
+                (to_light / distance, distance)
+            }
+            LightKind::Directional { direction } => (-direction, DIRECTIONAL_DISTANCE),
+        }
+    }
+
+    pub fn area_sample(&self, point: &Vec3, disc_x: f32, disc_y: f32) -> (Vec3, f32) {
+        let (light_dir, light_distance) = self.direction_from(point);
+        let helper = if light_dir.x.abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+        let tangent = light_dir.cross(&helper).normalize();
+        let bitangent = light_dir.cross(&tangent);
+        let offset = (tangent * disc_x + bitangent * disc_y) * self.area_radius;
+
+        match self.kind {
+            LightKind::Directional { .. } => ((light_dir + offset).normalize(), DIRECTIONAL_DISTANCE),
+            LightKind::Point { .. } | LightKind::Spot { .. } => {
+                let to_sample = light_dir * light_distance + offset;
+                let distance = to_sample.magnitude();
+                (to_sample / distance, distance)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_lights_do_not_fall_off() {
+        let lamp = Light::new(Vec3::zeros(), Color::new(255, 255, 255), 1.0);
+        assert_eq!(lamp.attenuation(100.0), 1.0);
+    }
+
+    #[test]
+    fn radius_halves_the_light_at_that_distance() {
+        let lamp = Light::new(Vec3::zeros(), Color::new(255, 255, 255), 1.0).with_radius(1.5);
+        assert_eq!(lamp.attenuation(0.0), 1.0);
+        assert!((lamp.attenuation(1.5) - 0.5).abs() < 1e-6);
+        assert!(lamp.attenuation(3.0) < lamp.attenuation(2.9));
+    }
+
+    #[test]
+    fn directional_lights_ignore_the_radius() {
+        let sun = Light::directional(Vec3::new(0.0, -1.0, 0.0), Color::new(255, 255, 255), 1.0).with_radius(1.5);
+        assert_eq!(sun.attenuation(50.0), 1.0);
+    }
+
+    #[test]
+    fn lights_cast_shadows_by_default() {
+        let lamp = Light::new(Vec3::zeros(), Color::new(255, 255, 255), 1.0);
+        assert!(lamp.casts_shadows);
+        assert!(!lamp.with_shadows(false).casts_shadows);
+    }
+
+    #[test]
+    fn fills_skip_shadows_and_fall_off() {
+        let fill = Light::fill(Vec3::zeros(), Color::new(255, 255, 255), 1.0);
+        assert!(!fill.casts_shadows);
+        assert!((fill.attenuation(FILL_RADIUS) - 0.5).abs() < 1e-6);
+        assert!(fill.attenuation(4.0 * FILL_RADIUS) < 0.06);
+    }
+}
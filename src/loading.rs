@@ -0,0 +1,235 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use crate::font;
+use crate::framebuffer::Framebuffer;
+
+const BACKGROUND: u32 = 0x101418;
+const TEXT: u32 = 0xe0e0e0;
+const BAR_TRACK: u32 = 0x303840;
+const BAR_FILL: u32 = 0x5a9e32;
+const BAR_SHINE: u32 = 0x8fd45c;
+const ERROR_TITLE: u32 = 0xd04848;
+const TEXT_SCALE: usize = 2;
+const BAR_MARGIN: usize = 40;
+const BAR_HEIGHT: usize = 12;
+// Width of the bright band that sweeps along the filled part and how many
+// frames it takes to cross the whole bar.
+const SHINE_WIDTH: usize = 24;
+const SHINE_FRAMES: usize = 90;
+const CANCELLED: &str = "cancelled";
+
+// What the worker sends back while it builds: progress as it goes, then
+// the result once.
+pub enum Update<T> {
+    Progress { fraction: f32, stage: String },
+    Done(Result<T, String>),
+}
+
+// Handed to the build so it can report how far along it is. Once the load
+// is cancelled a report comes back as an error, so a build that passes it
+// up with `?` stops at its next step.
+pub struct Progress<T> {
+    sender: Sender<Update<T>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<T> Progress<T> {
+    pub fn report(&self, fraction: f32, stage: &str) -> Result<(), String> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(CANCELLED.to_string());
+        }
+        let update = Update::Progress { fraction: fraction.clamp(0.0, 1.0), stage: stage.to_string() };
+        // Nobody is listening once the loader is gone, which is a cancel too.
+        self.sender.send(update).map_err(|_| CANCELLED.to_string())
+    }
+}
+
+pub enum Status<T> {
+    Loading,
+    Ready(T),
+    Failed(String),
+}
+
+// Runs a build on a background thread, the way TextureManager decodes
+// images, and lets the frame loop check on it without waiting.
+pub struct Loader<T> {
+    updates: Receiver<Update<T>>,
+    cancelled: Arc<AtomicBool>,
+    worker: JoinHandle<()>,
+    pub fraction: f32,
+    pub stage: String,
+}
+
+impl<T: Send + 'static> Loader<T> {
+    pub fn start(build: impl FnOnce(&Progress<T>) -> Result<T, String> + Send + 'static) -> Self {
+        let (sender, updates) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let progress = Progress { sender, cancelled: cancelled.clone() };
+        let worker = std::thread::spawn(move || {
+            let result = build(&progress);
+            let _ = progress.sender.send(Update::Done(result));
+        });
+        Loader { updates, cancelled, worker, fraction: 0.0, stage: String::new() }
+    }
+
+    // Takes in every update sent since the last call. A worker that died
+    // without sending a result (a panic in the build) counts as a failure.
+    pub fn poll(&mut self) -> Status<T> {
+        loop {
+            match self.updates.try_recv() {
+                Ok(Update::Progress { fraction, stage }) => {
+                    self.fraction = fraction;
+                    self.stage = stage;
+                }
+                Ok(Update::Done(Ok(value))) => return Status::Ready(value),
+                Ok(Update::Done(Err(err))) => return Status::Failed(err),
+                Err(TryRecvError::Empty) => return Status::Loading,
+                Err(TryRecvError::Disconnected) => {
+                    return Status::Failed("the scene build stopped without a result".to_string())
+                }
+            }
+        }
+    }
+
+    // Asks the build to stop at its next report and waits for the thread,
+    // so nothing is left running on the way out.
+    pub fn cancel(self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        let _ = self.worker.join();
+    }
+}
+
+fn fill(framebuffer: &mut Framebuffer, x: usize, y: usize, width: usize, height: usize, color: u32) {
+    framebuffer.set_current_color(color);
+    for row in y..y + height {
+        for column in x..x + width {
+            framebuffer.point(column, row);
+        }
+    }
+}
+
+fn centered_text(framebuffer: &mut Framebuffer, y: usize, text: &str, color: u32) {
+    framebuffer.set_current_color(color);
+    let x = framebuffer.width.saturating_sub(font::text_width(text, TEXT_SCALE)) / 2;
+    font::draw_text(framebuffer, x, y, text, TEXT_SCALE);
+}
+
+// The stage above a bar that fills with the build's progress. A bright
+// band keeps sweeping along the filled part, so the screen is visibly
+// alive even while one long step runs.
+pub fn draw_progress(framebuffer: &mut Framebuffer, fraction: f32, stage: &str, frame: usize) {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    fill(framebuffer, 0, 0, width, height, BACKGROUND);
+
+    let width = width.saturating_sub(2 * BAR_MARGIN);
+    let top = height / 2;
+    let filled = (width as f32 * fraction.clamp(0.0, 1.0)).round() as usize;
+    fill(framebuffer, BAR_MARGIN, top, width, BAR_HEIGHT, BAR_TRACK);
+    fill(framebuffer, BAR_MARGIN, top, filled, BAR_HEIGHT, BAR_FILL);
+    let shine = (frame % SHINE_FRAMES) * (width + SHINE_WIDTH) / SHINE_FRAMES;
+    let shine_start = shine.saturating_sub(SHINE_WIDTH).min(filled);
+    fill(framebuffer, BAR_MARGIN + shine_start, top, shine.min(filled) - shine_start, BAR_HEIGHT, BAR_SHINE);
+
+    let line_height = (font::GLYPH_HEIGHT + 3) * TEXT_SCALE;
+    centered_text(framebuffer, top.saturating_sub(2 * line_height), stage, TEXT);
+    let percent = format!("{:.0}%", fraction.clamp(0.0, 1.0) * 100.0);
+    centered_text(framebuffer, top + BAR_HEIGHT + line_height, &percent, TEXT);
+}
+
+pub fn draw_error(framebuffer: &mut Framebuffer, message: &str, prompt: &str) {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    fill(framebuffer, 0, 0, width, height, BACKGROUND);
+
+    let line_height = (font::GLYPH_HEIGHT + 3) * TEXT_SCALE;
+    let lines = font::wrap(message, width.saturating_sub(2 * BAR_MARGIN), TEXT_SCALE);
+    let mut y = (height / 2).saturating_sub((lines.len() + 3) * line_height / 2);
+    centered_text(framebuffer, y, "error", ERROR_TITLE);
+    for line in &lines {
+        y += line_height;
+        centered_text(framebuffer, y, line, TEXT);
+    }
+    centered_text(framebuffer, y + 2 * line_height, prompt, TEXT);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait<T: Send + 'static>(loader: &mut Loader<T>) -> Status<T> {
+        let start = Instant::now();
+        loop {
+            match loader.poll() {
+                Status::Loading if start.elapsed() < Duration::from_secs(5) => std::thread::sleep(Duration::from_millis(1)),
+                status => return status,
+            }
+        }
+    }
+
+    #[test]
+    fn progress_arrives_before_the_result() {
+        let mut loader = Loader::start(|progress| {
+            progress.report(0.25, "cubos")?;
+            progress.report(0.75, "sonda")?;
+            Ok(42)
+        });
+        match wait(&mut loader) {
+            Status::Ready(value) => assert_eq!(value, 42),
+            _ => panic!("the build should have finished"),
+        }
+        assert_eq!(loader.fraction, 0.75);
+        assert_eq!(loader.stage, "sonda");
+    }
+
+    #[test]
+    fn build_errors_and_panics_come_back_as_failures() {
+        let mut failing: Loader<()> = Loader::start(|_| Err("no such model".to_string()));
+        assert!(matches!(wait(&mut failing), Status::Failed(err) if err == "no such model"));
+
+        let mut panicking: Loader<()> = Loader::start(|_| panic!("broken build"));
+        assert!(matches!(wait(&mut panicking), Status::Failed(_)));
+    }
+
+    #[test]
+    fn cancelling_stops_the_build_at_its_next_report() {
+        let started = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (seen_start, seen_stop) = (started.clone(), stopped.clone());
+        let loader: Loader<()> = Loader::start(move |progress| {
+            seen_start.store(true, Ordering::Relaxed);
+            loop {
+                if let Err(err) = progress.report(0.5, "para siempre") {
+                    seen_stop.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+        while !started.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // Joins the worker, so this only returns once the build gave up.
+        loader.cancel();
+        assert!(stopped.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn the_bar_fills_with_the_progress() {
+        let mut framebuffer = Framebuffer::new(200, 100);
+        let filled_columns = |framebuffer: &Framebuffer| {
+            let row = framebuffer.height / 2 + BAR_HEIGHT / 2;
+            framebuffer.buffer[row * framebuffer.width..(row + 1) * framebuffer.width]
+                .iter()
+                .filter(|&&pixel| pixel == BAR_FILL || pixel == BAR_SHINE)
+                .count()
+        };
+        draw_progress(&mut framebuffer, 0.0, "", 10);
+        assert_eq!(filled_columns(&framebuffer), 0);
+        draw_progress(&mut framebuffer, 0.5, "", 10);
+        assert_eq!(filled_columns(&framebuffer), (200 - 2 * BAR_MARGIN) / 2);
+        draw_progress(&mut framebuffer, 1.0, "", 10);
+        assert_eq!(filled_columns(&framebuffer), 200 - 2 * BAR_MARGIN);
+    }
+}
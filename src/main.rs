@@ -1,72 +1,829 @@
 mod framebuffer;
+mod aabb;
+mod ray;
 mod ray_intersect;
 mod color;
 mod camera;
 mod light;
 mod material;
 mod cube; 
-
-use minifb::{ Window, WindowOptions, Key };
+mod palette;
+mod sdf;
+mod noise;
+mod pattern;
+mod settings;
+mod texture;
+mod demos;
+mod cuboid;
+mod sphere;
+mod csg;
+mod layers;
+mod decal;
+mod clouds;
+mod probe;
+mod island;
+mod waves;
+mod fish;
+mod math;
+mod font;
+mod loading;
+mod birds;
+
+use minifb::{ Window, WindowOptions, Key, KeyRepeat, MouseButton, MouseMode, Scale, ScaleMode };
 use nalgebra_glm::{Vec3, normalize};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::f32::consts::PI;
+use std::sync::Arc;
 
 use crate::color::Color;
+use crate::ray::{Ray, RayKind};
 use crate::ray_intersect::{Intersect, RayIntersect};
 use crate::framebuffer::Framebuffer;
 use crate::camera::Camera;
-use crate::light::Light;
+use crate::light::{Light, LightKind};
 use crate::material::Material;
-use crate::cube::Cube;
+use crate::cube::{Cube, FACE_FRONT};
+use crate::cuboid::Cuboid;
+use crate::aabb::Aabb;
+use crate::sdf::SdfPrimitive;
+use crate::csg::{arched_doorway, CsgNode, Solid};
+use crate::decal::{Decal, Decals};
+use crate::clouds::Clouds;
+use crate::birds::{Birds, Flock};
+use crate::probe::ReflectionProbe;
+use crate::waves::Waves;
+use crate::fish::School;
+use crate::loading::{Loader, Progress, Status};
+use crate::pattern::Pattern;
+use crate::texture::{Atlas, FilterMode, Texture, TextureHandle, TextureManager, WrapMode};
+use crate::settings::RenderSettings;
+
+const FIELD_OF_VIEW: f32 = PI / 3.0;
+const EYE_SEPARATION_STEP: f32 = 0.01;
+const MAX_EYE_SEPARATION: f32 = 1.0;
+const SURFACE_BIAS: f32 = 1e-3;
+const MAX_SHADOW_CROSSINGS: usize = 16;
+const MAX_BIAS_FRACTION: f32 = 0.1;
+const GOLDEN_ANGLE: f32 = 2.399_963;
+const MOVING_SHADOW_SAMPLES: u32 = 1;
+const IDLE_SHADOW_SAMPLES: u32 = 8;
+const MOVING_REFLECTION_SAMPLES: u32 = 1;
+const IDLE_REFLECTION_SAMPLES: u32 = 4;
+// The cave shows off bounced light unless --gi says otherwise.
+const CAVE_GI_SAMPLES: u32 = 8;
+const PROBE_RESOLUTION: usize = 32;
+// Above the highest the water bobs, so the probe never starts inside it.
+const PROBE_HEIGHT: f32 = 0.15;
+// How much the K key or --contact-shadows darkens creases.
+const CONTACT_SHADOW_STRENGTH: f32 = 0.5;
+const CONTACT_SHADOW_STEPS: usize = 12;
+// A step counts as blocked only when it is this far behind the depth
+// buffer, relative to its own depth, so a surface doesn't shadow itself...
+const CONTACT_SHADOW_BIAS: f32 = 0.002;
+// ...and no more than this, so it isn't just passing behind a far edge.
+const CONTACT_SHADOW_THICKNESS: f32 = 0.1;
+const SUN_AREA_RADIUS: f32 = 0.05;
+const SUN_INTENSITY: f32 = 1.0;
+const FARO_RADIUS: f32 = 1.5;
+const SHADOWED_TORCHES: usize = 2;
+const TORCH_INTENSITY: f32 = 0.8;
+const TORCH_POSITIONS: [[f32; 3]; 5] = [
+    [0.55, 0.15, 0.25],
+    [-0.55, 0.15, 0.55],
+    [0.25, 0.15, -0.75],
+    [-0.35, 0.15, -0.55],
+    [0.0, 0.15, 0.85],
+];
+// The four layers reachable from the number keys.
+const LAYER_KEY_MASK: u32 = 0b1111;
+const GROUND_FADE: f32 = 0.15;
+const SKY_FRESNEL_F0: f32 = 0.04;
+const SKY_OCCLUSION_DISTANCE: f32 = 4.0;
+const FISH_COUNT: usize = 5;
+const FISH_SEED: u32 = 42;
+// A long stall, like dragging the window, shouldn't fling the fish.
+const MAX_FISH_STEP: f32 = 0.1;
+// Picking through the scene stops after this many objects.
+const MAX_ALL_HITS: usize = 16;
+// Clicks this many pixels apart still count as the same spot.
+const PICK_TOLERANCE: usize = 3;
+// Occlusion rays only look this far, about two cubes, so only nearby
+// walls and overhangs darken the ambient light.
+const AO_DISTANCE: f32 = 0.4;
+// How far up a point looks for the water surface it may be under.
+const CAUSTIC_PROBE_DISTANCE: f32 = 1.0;
+const DEFAULT_AMBIENT_FACTOR: f32 = 0.27;
+const GROUND_TILE_SIZE: f32 = 0.2;
+// The ground plane is a square of this half size around the origin.
+const GROUND_HALF_SIZE: f32 = 1.0;
+// Pond wall tops sit just under the ground so they don't z-fight with it.
+const POND_WALL_Y: f32 = -0.052;
+const ATLAS_PATH: &str = "assets/atlas.png";
+const ATLAS_TILE_SIZE: usize = 16;
+const ATLAS_TILE_TRONCO: usize = 0;
+const ATLAS_TILE_HOJAS: usize = 1;
+const ATLAS_TILE_AGUA: usize = 2;
+const ATLAS_TILE_CESPED: usize = 3;
 
 fn reflect(incident: &Vec3, normal: &Vec3) -> Vec3 {
     incident - 2.0 * incident.dot(normal) * normal
 }
 
-pub fn cast_ray<T: RayIntersect>(
-    ray_origin: &Vec3,
-    ray_direction: &Vec3,
-    object: &T,  
+fn refract(incident: &Vec3, normal: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = -incident.dot(normal).clamp(-1.0, 1.0);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        return None;
+    }
+    Some(incident * eta + normal * (eta * cos_i - k.sqrt()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Occluder {
+    Plane,
+    Cube(usize),
+    Cuboid(usize),
+    Decoration(usize),
+    Solid(usize),
+}
+
+fn scene_intersect(ray: &Ray, scene: &Scene) -> Intersect {
+    scene_intersect_with_occluder(ray, scene).0
+}
+
+// Every object the ray can see, with where it meets each one. The plane
+// is left to the callers.
+fn object_hits<'a>(ray: &'a Ray, scene: &'a Scene) -> impl Iterator<Item = (Intersect, Occluder)> + 'a {
+    // Objects on layers the ray can't see are skipped before any
+    // intersection work.
+    let sees = |layers: u32| layers & ray.layers != 0;
+    let cube_hits = scene
+        .cubes
+        .iter()
+        .enumerate()
+        .filter(move |(_, cube)| sees(cube.layers))
+        .map(|(i, cube)| (cube.ray_intersect(ray), Occluder::Cube(i)));
+    let cuboid_hits = scene
+        .cuboids
+        .iter()
+        .enumerate()
+        .filter(move |(_, cuboid)| sees(cuboid.layers))
+        .map(|(i, cuboid)| (cuboid.ray_intersect(ray), Occluder::Cuboid(i)));
+    let decoration_hits = scene
+        .decorations
+        .iter()
+        .enumerate()
+        .filter(move |(_, decoration)| sees(decoration.layers))
+        .map(|(i, decoration)| (decoration.ray_intersect(ray), Occluder::Decoration(i)));
+    let solid_hits = scene
+        .solids
+        .iter()
+        .enumerate()
+        .filter(move |(_, solid)| sees(solid.layers))
+        .map(|(i, solid)| (solid.ray_intersect(ray), Occluder::Solid(i)));
+
+    cube_hits
+        .chain(cuboid_hits)
+        .chain(decoration_hits)
+        .chain(solid_hits)
+        .map(move |(intersect, object)| (intersect.facing(&ray.direction), object))
+}
+
+fn scene_intersect_with_occluder(ray: &Ray, scene: &Scene) -> (Intersect, Occluder) {
+    let mut nearest = scene.plane.ray_intersect(ray).facing(&ray.direction);
+    let mut occluder = Occluder::Plane;
+
+    for (intersect, object) in object_hits(ray, scene) {
+        if intersect.is_intersecting && (!nearest.is_intersecting || intersect.distance < nearest.distance) {
+            nearest = intersect;
+            occluder = object;
+        }
+    }
+
+    if let Occluder::Cube(i) = occluder {
+        let face = scene.cubes[i].face_at(&nearest.point);
+        nearest = nearest.with_cube_face(i, face);
+    }
+    (nearest, occluder)
+}
+
+fn blocks(ray: &Ray, scene: &Scene, occluder: Occluder) -> bool {
+    let layers = match occluder {
+        Occluder::Plane => layers::ALL,
+        Occluder::Cube(i) => scene.cubes.get(i).map_or(0, |cube| cube.layers),
+        Occluder::Cuboid(i) => scene.cuboids.get(i).map_or(0, |cuboid| cuboid.layers),
+        Occluder::Decoration(i) => scene.decorations.get(i).map_or(0, |decoration| decoration.layers),
+        Occluder::Solid(i) => scene.solids.get(i).map_or(0, |solid| solid.layers),
+    };
+    if layers & ray.layers == 0 {
+        return false;
+    }
+    let hit = match occluder {
+        Occluder::Plane => scene.plane.ray_intersect(ray),
+        Occluder::Cube(i) => match scene.cubes.get(i) {
+            Some(cube) => cube.ray_intersect(ray),
+            None => return false,
+        },
+        Occluder::Cuboid(i) => match scene.cuboids.get(i) {
+            Some(cuboid) => cuboid.ray_intersect(ray),
+            None => return false,
+        },
+        Occluder::Decoration(i) => match scene.decorations.get(i) {
+            Some(decoration) => decoration.ray_intersect(ray),
+            None => return false,
+        },
+        Occluder::Solid(i) => match scene.solids.get(i) {
+            Some(solid) => solid.ray_intersect(ray),
+            None => return false,
+        },
+    }
+    .facing(&ray.direction);
+    hit.is_intersecting && hit.material.albedo[3] <= 0.0
+}
+
+// Last opaque blocker per light along the current scanline. Neighbouring
+// pixels are usually shadowed by the same object, so it is tried first.
+#[derive(Debug, Default)]
+pub struct OccluderCache {
+    slots: Vec<Option<Occluder>>,
+}
+
+impl OccluderCache {
+    fn clear(&mut self) {
+        self.slots.clear();
+    }
+
+    fn slot(&mut self, light: usize) -> &mut Option<Occluder> {
+        if light >= self.slots.len() {
+            self.slots.resize(light + 1, None);
+        }
+        &mut self.slots[light]
+    }
+}
+
+// Grows with the hit's magnitude and distance to cover float error, but never
+// past a fraction of the primitive's own size so tiny cubes stay resolvable.
+fn surface_bias(intersect: &Intersect) -> f32 {
+    let scale = intersect.point.abs().max().max(intersect.distance);
+    (SURFACE_BIAS * scale).min(intersect.extent * MAX_BIAS_FRACTION)
+}
+
+fn offset_origin(intersect: &Intersect, direction: &Vec3) -> Vec3 {
+    let side = if direction.dot(&intersect.normal) < 0.0 { -1.0 } else { 1.0 };
+    intersect.point + intersect.normal * (surface_bias(intersect) * side)
+}
+
+fn continues_medium(scene: &Scene, material: &Material, point: &Vec3) -> bool {
+    scene
+        .cubes
+        .iter()
+        .any(|cube| cube.material == *material && cube.aabb().contains(point))
+}
+
+// A cached blocker that still sits on the ray settles it at once; otherwise
+// the full walk runs and its opaque blocker, if any, replaces the cache.
+fn cached_shadow_transmittance(ray: &Ray, scene: &Scene, cached: &mut Option<Occluder>, stats: &mut RenderStats) -> Vec3 {
+    stats.shadow_queries += 1;
+    if let Some(occluder) = *cached {
+        if blocks(ray, scene, occluder) {
+            stats.occluded_queries += 1;
+            stats.occluder_cache_hits += 1;
+            return Vec3::zeros();
+        }
+    }
+    let (transmittance, blocker) = shadow_transmittance(ray, scene);
+    if blocker.is_some() {
+        stats.occluded_queries += 1;
+    }
+    *cached = blocker;
+    transmittance
+}
+
+// Walks the shadow ray through every surface it crosses. Opaque hits block
+// the light; transparent ones let through their transparency once per medium
+// and Beer-Lambert absorption for the distance travelled inside it.
+// An opaque hit also reports which object blocked the light.
+fn shadow_transmittance(ray: &Ray, scene: &Scene) -> (Vec3, Option<Occluder>) {
+    let mut transmittance = Vec3::new(1.0, 1.0, 1.0);
+    let mut entered_at = ray.t_min;
+    let mut t_min = ray.t_min;
+
+    for _ in 0..MAX_SHADOW_CROSSINGS {
+        let (hit, occluder) = scene_intersect_with_occluder(&ray.clone().with_t_min(t_min), scene);
+        if !hit.is_intersecting {
+            return (transmittance, None);
+        }
+        let transparency = hit.material.albedo[3];
+        if transparency <= 0.0 {
+            return (Vec3::zeros(), Some(occluder));
+        }
+
+        // Touching cubes of the same material form one medium, so their
+        // shared faces neither filter the light nor restart the absorption.
+        let bias = surface_bias(&hit);
+        if hit.entering {
+            if !continues_medium(scene, &hit.material, &ray.point_at(hit.distance - bias)) {
+                transmittance *= transparency;
+                entered_at = hit.distance;
+            }
+        } else if !continues_medium(scene, &hit.material, &ray.point_at(hit.distance + bias)) {
+            let absorbed = (-hit.material.absorption * (hit.distance - entered_at)).map(math::exp);
+            transmittance.component_mul_assign(&absorbed);
+        }
+        t_min = hit.distance + bias;
+    }
+
+    (Vec3::zeros(), None)
+}
+
+fn point_hash(point: &Vec3) -> f32 {
+    (math::sin(point.x * 12.9898 + point.y * 78.233 + point.z * 37.719) * 43758.545).fract().abs()
+}
+
+fn shadow_visibility(
+    intersect: &Intersect,
     light: &Light,
-    depth: u32,
-    skybox: &Skybox,
+    settings: &RenderSettings,
+    scene: &Scene,
+    occluder: &mut Option<Occluder>,
+    stats: &mut RenderStats,
+) -> Vec3 {
+    let shadow_origin = offset_origin(intersect, &intersect.normal);
+    let shadow_samples = settings.shadow_samples;
+
+    if light.area_radius <= 0.0 || shadow_samples <= 1 {
+        let (light_dir, light_distance) = light.direction_from(&intersect.point);
+        let shadow_ray = Ray::shadow(shadow_origin, light_dir * light_distance).with_layers(settings.shadow_layers);
+        return cached_shadow_transmittance(&shadow_ray, scene, occluder, stats);
+    }
+
+    let rotation = point_hash(&intersect.point) * 2.0 * PI;
+    let mut visible = Vec3::zeros();
+    for i in 0..shadow_samples {
+        let r = ((i as f32 + 0.5) / shadow_samples as f32).sqrt();
+        let theta = i as f32 * GOLDEN_ANGLE + rotation;
+        let (sample_dir, sample_distance) = light.area_sample(&intersect.point, r * math::cos(theta), r * math::sin(theta));
+        let shadow_ray = Ray::shadow(shadow_origin, sample_dir * sample_distance).with_layers(settings.shadow_layers);
+        visible += cached_shadow_transmittance(&shadow_ray, scene, occluder, stats);
+    }
+
+    visible / shadow_samples as f32
+}
+
+fn glossy_direction(mirror_dir: &Vec3, normal: &Vec3, roughness: f32, disc_x: f32, disc_y: f32) -> Vec3 {
+    let helper = if mirror_dir.x.abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+    let tangent = mirror_dir.cross(&helper).normalize();
+    let bitangent = mirror_dir.cross(&tangent);
+    let direction = (mirror_dir + (tangent * disc_x + bitangent * disc_y) * roughness).normalize();
+
+    let below = direction.dot(normal);
+    if below < 0.0 {
+        (direction - normal * (2.0 * below)).normalize()
+    } else {
+        direction
+    }
+}
+
+// A point on the unit disc lifted onto the hemisphere around the normal,
+// which spreads directions by the cosine of their angle to it.
+fn hemisphere_direction(normal: &Vec3, disc_x: f32, disc_y: f32) -> Vec3 {
+    let helper = if normal.x.abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+    let lift = (1.0 - disc_x * disc_x - disc_y * disc_y).max(0.0).sqrt();
+    (tangent * disc_x + bitangent * disc_y + normal * lift).normalize()
+}
+
+// Diffuse light leaving the surface a bounce ray landed on, plus its glow.
+// Each light gets one hard shadow ray and no specular, which is plenty for
+// light that is about to be averaged over a hemisphere.
+fn bounced_light(hit: &Intersect, scene: &Scene, settings: &RenderSettings) -> Color {
+    let surface_point = hit.point - hit.normal * surface_bias(hit);
+    let surface_color = hit.material.diffuse_at(&surface_point, hit.uv);
+    let mut light_out = hit.material.emission_at(hit.uv);
+
+    for light in &scene.lights {
+        let (light_dir, light_distance) = light.direction_from(&hit.point);
+        let light_intensity = light.intensity * light.attenuation(light_distance) * light.cone_factor(&light_dir);
+        let diffuse_intensity = hit.normal.dot(&light_dir).clamp(0.0, 1.0);
+        if diffuse_intensity <= 0.0 || light_intensity <= 0.0 {
+            continue;
+        }
+        let visibility = if light.casts_shadows {
+            let shadow_ray = Ray::shadow(offset_origin(hit, &hit.normal), light_dir * light_distance)
+                .with_layers(settings.shadow_layers);
+            shadow_transmittance(&shadow_ray, scene).0
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        };
+        light_out = light_out + ((surface_color * light.color) * (hit.material.albedo[0] * diffuse_intensity * light_intensity))
+            .scale_channels(visibility.x, visibility.y, visibility.z);
+    }
+    light_out
+}
+
+// One bounce of diffuse light: the hemisphere rays are cosine distributed,
+// so their plain average is the light arriving at the surface.
+fn indirect_diffuse(ray: &Ray, intersect: &Intersect, normal: &Vec3, scene: &Scene, settings: &RenderSettings) -> Color {
+    let samples = settings.gi_samples.max(1);
+    let rotation = point_hash(&intersect.point) * 2.0 * PI;
+    let colors: Vec<Color> = (0..samples)
+        .map(|i| {
+            let radius = ((i as f32 + 0.5) / samples as f32).sqrt();
+            let theta = i as f32 * GOLDEN_ANGLE + rotation;
+            let direction = hemisphere_direction(normal, radius * math::cos(theta), radius * math::sin(theta));
+            let bounce_ray = Ray::secondary(ray, offset_origin(intersect, &direction), direction)
+                .with_layers(settings.reflection_layers);
+            let hit = scene_intersect(&bounce_ray, scene);
+            if hit.is_intersecting { bounced_light(&hit, scene, settings) } else { Color::black() }
+        })
+        .collect();
+
+    Color::average(&colors)
+}
+
+// Ambient occlusion from short hemisphere rays, along with the bent
+// normal: the average of the directions that stayed open. The ambient
+// light is looked up along it instead of the surface normal, so a face
+// under an overhang takes its sky light from the open side.
+fn bent_normal(intersect: &Intersect, normal: &Vec3, scene: &Scene, settings: &RenderSettings) -> (Vec3, f32) {
+    let samples = settings.ao_samples.max(1);
+    let rotation = point_hash(&intersect.point) * 2.0 * PI;
+    let mut open_sum = Vec3::zeros();
+    let mut openness = 0.0;
+    for i in 0..samples {
+        let radius = ((i as f32 + 0.5) / samples as f32).sqrt();
+        let theta = i as f32 * GOLDEN_ANGLE + rotation;
+        let direction = hemisphere_direction(normal, radius * math::cos(theta), radius * math::sin(theta));
+        let occlusion_ray = Ray::shadow(offset_origin(intersect, &direction), direction * AO_DISTANCE)
+            .with_layers(settings.shadow_layers);
+        let (open, _) = shadow_transmittance(&occlusion_ray, scene);
+        let open = (open.x + open.y + open.z) / 3.0;
+        open_sum += direction * open;
+        openness += open;
+    }
+
+    let bent = if open_sum.magnitude() > 1e-6 { open_sum.normalize() } else { *normal };
+    (bent, openness / samples as f32)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn trace_reflection(
+    ray: &Ray,
+    intersect: &Intersect,
+    mirror_dir: &Vec3,
+    scene: &Scene,
+    weight: f32,
+    settings: &RenderSettings,
+    stats: &mut RenderStats,
+    occluders: &mut OccluderCache,
+) -> Color {
+    let roughness = intersect.material.roughness;
+    if roughness <= 0.0 {
+        let reflect_ray = Ray::secondary(ray, offset_origin(intersect, mirror_dir), *mirror_dir)
+            .with_layers(settings.reflection_layers);
+        return cast_ray(&reflect_ray, scene, weight, settings, stats, occluders);
+    }
+
+    // Only the first bounce fans out; deeper glossy rays take a single
+    // sample so the ray count stays linear in depth.
+    let samples = if ray.kind == RayKind::Primary { settings.reflection_samples.max(1) } else { 1 };
+    let sample_weight = weight / samples as f32;
+    let rotation = point_hash(&intersect.point) * 2.0 * PI;
+    let colors: Vec<Color> = (0..samples)
+        .map(|i| {
+            let radius = ((i as f32 + 0.5) / samples as f32).sqrt();
+            let theta = i as f32 * GOLDEN_ANGLE + rotation;
+            let direction = glossy_direction(mirror_dir, &intersect.normal, roughness, radius * math::cos(theta), radius * math::sin(theta));
+            let reflect_ray = Ray::secondary(ray, offset_origin(intersect, &direction), direction)
+                .with_layers(settings.reflection_layers);
+            cast_ray(&reflect_ray, scene, sample_weight, settings, stats, occluders)
+        })
+        .collect();
+
+    Color::average(&colors)
+}
+
+pub fn cast_ray(
+    ray: &Ray,
+    scene: &Scene,
+    weight: f32,
+    settings: &RenderSettings,
+    stats: &mut RenderStats,
+    occluders: &mut OccluderCache,
 ) -> Color {
-    let mut intersect = object.ray_intersect(ray_origin, ray_direction);
+    if ray.depth > settings.max_depth {
+        return scene.skybox.sample(ray.direction);
+    }
+
+    let intersect = scene_intersect(ray, scene);
     if !intersect.is_intersecting {
-        return skybox.sample(*ray_direction);
+        return scene.skybox.sample(ray.direction);
+    }
+
+    let color = shade(ray, &intersect, scene, weight, settings, stats, occluders)
+        + intersect.material.emission_at(intersect.uv);
+    absorb(color, ray, &intersect)
+}
+
+fn shade(
+    ray: &Ray,
+    intersect: &Intersect,
+    scene: &Scene,
+    weight: f32,
+    settings: &RenderSettings,
+    stats: &mut RenderStats,
+    occluders: &mut OccluderCache,
+) -> Color {
+    let ray_direction = &ray.direction;
+
+    if !intersect.entering && intersect.material.albedo[3] > 0.0 {
+        let through_origin = offset_origin(intersect, ray_direction);
+        if continues_medium(scene, &intersect.material, &through_origin) {
+            let through_ray = Ray::secondary(ray, through_origin, *ray_direction);
+            return cast_ray(&through_ray, scene, weight, settings, stats, occluders);
+        }
+    }
+
+    let surface_point = intersect.point - intersect.normal * surface_bias(intersect);
+    let surface_color = intersect.material.diffuse_at(&surface_point, intersect.uv);
+    let surface_color = match intersect.cube_face {
+        Some((cube, face)) => scene.decals.composite(cube, face, intersect.uv, surface_color),
+        None => surface_color,
+    };
+
+    if intersect.material.metallic {
+        let reflect_dir = reflect(ray_direction, &intersect.normal).normalize();
+        let reflect_color = trace_reflection(ray, intersect, &reflect_dir, scene, weight, settings, stats, occluders);
+        return reflect_color * surface_color;
+    }
+
+    let normal = shading_normal(intersect);
+    let ambient = if ray.kind == RayKind::Primary && settings.ao_samples > 0 {
+        let (bent, openness) = bent_normal(intersect, &normal, scene, settings);
+        surface_color * (scene.skybox.ambient(&bent) * openness)
+    } else {
+        surface_color * scene.skybox.ambient(&normal)
+    };
+    let view_dir = -ray_direction;
+    // The bounce only leaves primary hits, so its cost stays per pixel.
+    let indirect = if ray.kind == RayKind::Primary && settings.gi_samples > 0 {
+        (surface_color * indirect_diffuse(ray, intersect, &normal, scene, settings)) * intersect.material.albedo[0]
+    } else {
+        Color::black()
+    };
+
+    let water = if settings.caustics { water_above(intersect, scene, settings) } else { None };
+
+    let mut diffuse = Color::black();
+    let mut specular = Color::black();
+    for (index, light) in scene.lights.iter().enumerate() {
+        let (light_dir, light_distance) = light.direction_from(&intersect.point);
+        let light_intensity = light.intensity * light.attenuation(light_distance) * light.cone_factor(&light_dir);
+        let diffuse_intensity = match water {
+            Some((surface, refractive_index)) => {
+                let (bent_dir, focus) = caustic_light(&surface, refractive_index, &intersect.point, &light_dir, &scene.waves);
+                normal.dot(&bent_dir).clamp(0.0, 1.0) * focus
+            }
+            None => normal.dot(&light_dir).clamp(0.0, 1.0),
+        };
+        if diffuse_intensity <= 0.0 || light_intensity <= 0.0 {
+            continue;
+        }
+
+        let visibility = if light.casts_shadows {
+            stats.shadowed_lights += 1;
+            // Only primary hits are coherent from pixel to pixel; bounced
+            // rays would just thrash the cache.
+            let mut uncached = None;
+            let occluder = if ray.kind == RayKind::Primary { occluders.slot(index) } else { &mut uncached };
+            shadow_visibility(intersect, light, settings, scene, occluder, stats)
+        } else {
+            stats.unshadowed_lights += 1;
+            Vec3::new(1.0, 1.0, 1.0)
+        };
+
+        let reflect_dir = reflect(&-light_dir, &normal).normalize();
+        let specular_intensity = view_dir.dot(&reflect_dir).clamp(0.0, 1.0);
+        let specular_intensity = math::powf(specular_intensity, intersect.material.specular);
+
+        diffuse = diffuse + ((surface_color * light.color) * (intersect.material.albedo[0] * diffuse_intensity * light_intensity))
+            .scale_channels(visibility.x, visibility.y, visibility.z);
+        specular = specular + (light.color * (intersect.material.albedo[1] * specular_intensity * light_intensity))
+            .scale_channels(visibility.x, visibility.y, visibility.z);
+    }
+
+    let reflectivity = intersect.material.albedo[2];
+    let transparency = intersect.material.albedo[3];
+    let secondary_weight = (reflectivity + transparency).max(1.0);
+    let reflect_weight = weight * reflectivity / secondary_weight;
+    let refract_weight = weight * transparency / secondary_weight;
+
+    let reflect_dir = reflect(ray_direction, &intersect.normal).normalize();
+    let probe = scene.probe.as_ref().filter(|_| settings.reflection_probes && intersect.material.probe_reflections);
+    let reflect_color = if let (true, Some(probe)) = (reflectivity > 0.0, probe) {
+        probe.sample(&reflect_dir)
+    } else if reflectivity > 0.0 && reflect_weight >= settings.min_contribution {
+        trace_reflection(ray, intersect, &reflect_dir, scene, reflect_weight, settings, stats, occluders)
+    } else {
+        Color::black()
+    };
+
+    let refract_color = if transparency > 0.0 && refract_weight >= settings.min_contribution {
+        let (refract_dir, medium) = transmitted_direction(ray, intersect, &reflect_dir);
+        let refract_ray = Ray::secondary(ray, offset_origin(intersect, &refract_dir), refract_dir)
+            .with_medium(medium)
+            .with_layers(settings.reflection_layers);
+        cast_ray(&refract_ray, scene, refract_weight, settings, stats, occluders)
+    } else {
+        Color::black()
+    };
+
+    diffuse + specular + ambient + indirect
+        + sky_specular(intersect, &normal, &view_dir, scene, settings)
+        + reflect_color * (reflectivity / secondary_weight)
+        + refract_color * (transparency / secondary_weight)
+}
+
+// The water surface straight above a point, and its refractive index, if
+// one ray up finds the point is under water.
+fn water_above(intersect: &Intersect, scene: &Scene, settings: &RenderSettings) -> Option<(Vec3, f32)> {
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let probe = Ray::shadow(offset_origin(intersect, &up), up * CAUSTIC_PROBE_DISTANCE).with_layers(settings.shadow_layers);
+    let (hit, occluder) = scene_intersect_with_occluder(&probe, scene);
+    let water = &hit.material;
+    match occluder {
+        Occluder::Cube(i) if hit.is_intersecting && water.albedo[3] > 0.0 && water.refractive_index > 1.0 => {
+            let surface = scene.cubes[i].aabb().max.y;
+            Some((Vec3::new(intersect.point.x, surface, intersect.point.z), water.refractive_index))
+        }
+        _ => None,
+    }
+}
+
+// Light under the water arrives bent by the ripple above the point and is
+// gathered or spread by the ripple's curvature, so the bright bands move
+// with the waves instead of following a fixed pattern.
+fn caustic_light(surface: &Vec3, refractive_index: f32, point: &Vec3, light_dir: &Vec3, waves: &Waves) -> (Vec3, f32) {
+    let normal = waves.normal(surface.x, surface.z);
+    let bent_dir = refract(&-light_dir, &normal, 1.0 / refractive_index).map_or(*light_dir, |direction| -direction.normalize());
+    let focus = waves.focus(surface.x, surface.z, surface.y - point.y, refractive_index);
+    (bent_dir, focus)
+}
+
+// Skylight mirrored off a diffuse surface, without recursing into the
+// scene: the reflected direction samples the sky, weighted by Schlick's
+// Fresnel, and one occlusion ray keeps covered surfaces from picking up sky
+// they can't see. See-through occluders let part of it through.
+fn sky_specular(intersect: &Intersect, normal: &Vec3, view_dir: &Vec3, scene: &Scene, settings: &RenderSettings) -> Color {
+    let strength = intersect.material.sky_specular;
+    if strength <= 0.0 {
+        return Color::black();
+    }
+
+    let sky_dir = reflect(&-view_dir, normal).normalize();
+    if sky_dir.dot(&intersect.normal) <= 0.0 {
+        return Color::black();
+    }
+
+    let cos_theta = view_dir.dot(normal).clamp(0.0, 1.0);
+    let fresnel = SKY_FRESNEL_F0 + (1.0 - SKY_FRESNEL_F0) * (1.0 - cos_theta).powi(5);
+    let occlusion_ray = Ray::shadow(offset_origin(intersect, &sky_dir), sky_dir * SKY_OCCLUSION_DISTANCE)
+        .with_layers(settings.shadow_layers);
+    let (open_sky, _) = shadow_transmittance(&occlusion_ray, scene);
+
+    (scene.skybox.sample(sky_dir) * (strength * fresnel)).scale_channels(open_sky.x, open_sky.y, open_sky.z)
+}
+
+// Total internal reflection bounces back into the medium the ray is already in.
+fn transmitted_direction(ray: &Ray, intersect: &Intersect, reflect_dir: &Vec3) -> (Vec3, Option<Material>) {
+    let refractive_index = intersect.material.refractive_index;
+    let eta = if intersect.entering { 1.0 / refractive_index } else { refractive_index };
+    match refract(&ray.direction, &intersect.normal, eta) {
+        Some(direction) => (direction.normalize(), if intersect.entering { Some(intersect.material.clone()) } else { None }),
+        None => (*reflect_dir, ray.medium.clone()),
     }
+}
 
-    let light_dir = (light.position - intersect.point).normalize();
-    let view_dir = (ray_origin - intersect.point).normalize();
-    let reflect_dir = reflect(&-light_dir, &intersect.normal).normalize();
+fn shading_normal(intersect: &Intersect) -> Vec3 {
+    let normal_map = match &intersect.material.normal_map {
+        Some(normal_map) => normal_map,
+        None => return intersect.normal,
+    };
 
-    let diffuse_intensity = intersect.normal.dot(&light_dir).max(0.0).min(1.0);
-    let diffuse = intersect.material.diffuse * intersect.material.albedo[0] * diffuse_intensity;
+    // Normal maps are loaded as data, so the texels are the stored values.
+    let [x, y, z] = normal_map.sample(intersect.uv.0, intersect.uv.1).to_unit_rgb();
+    let perturbed = intersect.tangent * (x * 2.0 - 1.0)
+        + intersect.bitangent * (y * 2.0 - 1.0)
+        + intersect.normal * (z * 2.0 - 1.0).max(0.0);
+    if perturbed.magnitude() < f32::EPSILON {
+        return intersect.normal;
+    }
+    perturbed.normalize()
+}
 
-    let specular_intensity = view_dir.dot(&reflect_dir).max(0.0).powf(intersect.material.specular);
-    let specular = light.color * intersect.material.albedo[1] * specular_intensity;
+fn absorb(color: Color, ray: &Ray, intersect: &Intersect) -> Color {
+    let absorption = match &ray.medium {
+        Some(medium) => medium.absorption,
+        None => return color,
+    };
+    if absorption == Vec3::zeros() {
+        return color;
+    }
+    let transmittance = (-absorption * intersect.distance).map(math::exp);
+    color.scale_channels(transmittance.x, transmittance.y, transmittance.z)
+}
 
-    let ambient = intersect.material.diffuse * 0.2; 
 
-    diffuse + specular + ambient
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RenderStats {
+    pub shadowed_lights: u64,
+    pub unshadowed_lights: u64,
+    pub shadow_queries: u64,
+    pub occluded_queries: u64,
+    pub occluder_cache_hits: u64,
 }
 
+impl RenderStats {
+    // Only blocked queries can be answered from the cache, so lit ones are
+    // left out of the rate.
+    pub fn occluder_hit_rate(&self) -> f32 {
+        if self.occluded_queries == 0 {
+            return 0.0;
+        }
+        self.occluder_cache_hits as f32 / self.occluded_queries as f32
+    }
+}
 
 pub fn render(
     framebuffer: &mut Framebuffer,
-    plane: &Plane,
-    cubes: &[Cube],  
+    scene: &Scene,
     camera: &Camera,
-    light: &Light,
-    skybox: &Skybox,
+    stereo: bool,
+    settings: &RenderSettings,
+) -> RenderStats {
+    let mut stats = RenderStats::default();
+    if stereo {
+        let half_width = framebuffer.width / 2;
+        let right_width = framebuffer.width - half_width;
+        let (left_eye, right_eye) = camera.stereo_pair(settings.eye_separation);
+        render_view(framebuffer, 0, half_width, scene, &left_eye, settings, &mut stats);
+        render_view(framebuffer, half_width, right_width, scene, &right_eye, settings, &mut stats);
+    } else {
+        let width = framebuffer.width;
+        render_view(framebuffer, 0, width, scene, camera, settings, &mut stats);
+    }
+    stats
+}
+
+fn clip_primary(ray: Ray, clip_plane: Option<(Vec3, Vec3)>) -> Option<Ray> {
+    let (point, normal) = match clip_plane {
+        Some(clip_plane) => clip_plane,
+        None => return Some(ray),
+    };
+
+    // Whatever lies on the eye's side of the plane is cut away, so the side
+    // is worked out per ray; the camera may have orbited across the plane
+    // since it was placed.
+    let side = (ray.origin - point).dot(&normal);
+    if side.abs() < f32::EPSILON {
+        return Some(ray);
+    }
+    let away_from_eye = if side > 0.0 { -normal } else { normal };
+    let approach = ray.direction.dot(&away_from_eye);
+    if approach < f32::EPSILON {
+        return None;
+    }
+    Some(ray.with_t_min(side.abs() / approach))
+}
+
+fn cut_through_solid(ray: &Ray, cubes: &[Cube]) -> bool {
+    if ray.t_min <= 0.0 {
+        return false;
+    }
+    let cut_point = ray.point_at(ray.t_min);
+    cubes
+        .iter()
+        .any(|cube| cube.layers & ray.layers != 0 && cube.material.albedo[3] == 0.0 && cube.aabb().contains(&cut_point))
+}
+
+fn render_view(
+    framebuffer: &mut Framebuffer,
+    x_offset: usize,
+    width: usize,
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    stats: &mut RenderStats,
 ) {
-    let aspect_ratio = framebuffer.width as f32 / framebuffer.height as f32;
-    let fov = PI / 3.0;
-    let perspective_scale = (fov * 0.5).tan();
+    let aspect_ratio = width as f32 / framebuffer.height as f32;
+    let perspective_scale = math::tan(FIELD_OF_VIEW * 0.5);
+    let mut occluders = OccluderCache::default();
 
     for y in 0..framebuffer.height {
-        for x in 0..framebuffer.width {
-            let screen_x = (2.0 * x as f32) / framebuffer.width as f32 - 1.0;
+        occluders.clear();
+        for x in 0..width {
+            let screen_x = (2.0 * x as f32) / width as f32 - 1.0;
             let screen_y = -(2.0 * y as f32) / framebuffer.height as f32 + 1.0;
 
             let screen_x = screen_x * aspect_ratio * perspective_scale;
@@ -75,55 +832,243 @@ pub fn render(
             let ray_direction = normalize(&Vec3::new(screen_x, screen_y, -1.0));
             let rotated_direction = camera.base_change(&ray_direction);
 
-            
-            let mut pixel_color = if plane.ray_intersect(&camera.eye, &rotated_direction).is_intersecting {
-                cast_ray(&camera.eye, &rotated_direction, plane, light, 0, skybox)
-            } else {
-                skybox.sample(rotated_direction)  
+            let primary_ray = Ray::primary(camera.eye, rotated_direction).with_layers(settings.visible_layers);
+            let clipped = clip_primary(primary_ray, settings.clip_plane);
+            let pixel_color = match &clipped {
+                Some(ray) if cut_through_solid(ray, &scene.cubes) => palette::CUTAWAY,
+                Some(ray) => cast_ray(ray, scene, 1.0, settings, stats, &mut occluders),
+                None => scene.skybox.sample(rotated_direction),
             };
 
-            
-            let mut nearest_intersection = f32::INFINITY;
-            for cube in cubes {
-                let intersect = cube.ray_intersect(&camera.eye, &rotated_direction);
-                if intersect.is_intersecting && intersect.distance < nearest_intersection {
-                    nearest_intersection = intersect.distance;
-                    pixel_color = cast_ray(&camera.eye, &rotated_direction, cube, light, 0, skybox);
+            framebuffer.set_current_color(pixel_color.to_hex());
+            framebuffer.point(x_offset + x, y);
+            if settings.contact_shadow_strength > 0.0 {
+                let depth = clipped
+                    .map(|ray| scene_intersect(&ray, scene))
+                    .filter(|hit| hit.is_intersecting)
+                    .map_or(f32::INFINITY, |hit| camera.view_space(&hit.point).z);
+                framebuffer.set_depth(x_offset + x, y, depth);
+            }
+        }
+    }
+
+    if settings.contact_shadow_strength > 0.0 {
+        contact_shadows(framebuffer, x_offset, width, scene, camera, settings);
+    }
+}
+
+// The world direction through a pixel of a view.
+fn pixel_direction(camera: &Camera, x: usize, y: usize, width: usize, height: usize) -> Vec3 {
+    let aspect_ratio = width as f32 / height as f32;
+    let perspective_scale = math::tan(FIELD_OF_VIEW * 0.5);
+    let screen_x = ((2.0 * x as f32) / width as f32 - 1.0) * aspect_ratio * perspective_scale;
+    let screen_y = (-(2.0 * y as f32) / height as f32 + 1.0) * perspective_scale;
+    camera.base_change(&Vec3::new(screen_x, screen_y, -1.0))
+}
+
+// Where a world point lands in a view, as a pixel, if it is in front of
+// the camera and inside the view.
+fn project_to_view(point: &Vec3, camera: &Camera, width: usize, height: usize) -> Option<(usize, usize, f32)> {
+    let view = camera.view_space(point);
+    if view.z <= 0.0 {
+        return None;
+    }
+    let perspective_scale = math::tan(FIELD_OF_VIEW * 0.5);
+    let aspect_ratio = width as f32 / height as f32;
+    let screen_x = view.x / (view.z * aspect_ratio * perspective_scale);
+    let screen_y = view.y / (view.z * perspective_scale);
+    let x = ((screen_x + 1.0) * 0.5 * width as f32).round();
+    let y = ((1.0 - screen_y) * 0.5 * height as f32).round();
+    let inside = (0.0..width as f32).contains(&x) && (0.0..height as f32).contains(&y);
+    inside.then_some((x as usize, y as usize, view.z))
+}
+
+// Screen-space contact shadows. From each pixel's surface a few steps are
+// taken toward every shadow-casting light; a step that ends up behind
+// something already in the depth buffer, but not so far behind that it
+// could be passing under it, means the surface is in a crease the light
+// does not reach. Cheap, and it catches the thin dark lines where cubes
+// meet the ground that soft traced shadows blur away.
+fn contact_shadows(
+    framebuffer: &mut Framebuffer,
+    x_offset: usize,
+    width: usize,
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+) {
+    let height = framebuffer.height;
+    let depth_at = |framebuffer: &Framebuffer, x: usize, y: usize| framebuffer.depth[y * framebuffer.width + x_offset + x];
+
+    for y in 0..height {
+        for x in 0..width {
+            let depth = depth_at(framebuffer, x, y);
+            if !depth.is_finite() {
+                continue;
+            }
+            let direction = pixel_direction(camera, x, y, width, height);
+            let forward = (camera.center - camera.eye).normalize();
+            let point = camera.eye + direction * (depth / direction.dot(&forward));
+
+            // How fast depth changes around this pixel. A step that lands
+            // near a pixel's edge is compared against the depth at its
+            // center, so on a sloped surface it can look up to this much
+            // behind the surface it is still above.
+            let slope = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)]
+                .into_iter()
+                .filter(|&(nx, ny)| nx < width && ny < height)
+                .map(|(nx, ny)| (depth_at(framebuffer, nx, ny) - depth).abs())
+                .filter(|difference| difference.is_finite())
+                .fold(0.0, f32::max);
+
+            let mut lit = 1.0;
+            for light in scene.lights.iter().filter(|light| light.casts_shadows) {
+                let (to_light, light_distance) = light.direction_from(&point);
+                let reach = settings.contact_shadow_distance.min(light_distance);
+                let occluded = (1..=CONTACT_SHADOW_STEPS).any(|step| {
+                    let sample = point + to_light * (reach * step as f32 / CONTACT_SHADOW_STEPS as f32);
+                    project_to_view(&sample, camera, width, height).is_some_and(|(sample_x, sample_y, sample_depth)| {
+                        let behind = sample_depth - depth_at(framebuffer, sample_x, sample_y);
+                        (sample_x, sample_y) != (x, y)
+                            && behind > CONTACT_SHADOW_BIAS * sample_depth + slope
+                            && behind < CONTACT_SHADOW_THICKNESS
+                    })
+                });
+                if occluded {
+                    lit *= 1.0 - settings.contact_shadow_strength;
                 }
             }
 
-            framebuffer.set_current_color(pixel_color.to_hex());
-            framebuffer.point(x, y);
+            if lit < 1.0 {
+                let index = y * framebuffer.width + x_offset + x;
+                framebuffer.buffer[index] = (Color::from_hex(framebuffer.buffer[index]) * lit).to_hex();
+            }
         }
     }
 }
 
 
 
+pub struct Scene {
+    pub plane: Plane,
+    pub cubes: Vec<Cube>,
+    pub cuboids: Vec<Cuboid>,
+    pub decorations: Vec<SdfPrimitive>,
+    pub solids: Vec<CsgNode>,
+    pub lights: Vec<Light>,
+    pub decals: Decals,
+    pub skybox: Skybox,
+    pub probe: Option<ReflectionProbe>,
+    pub waves: Waves,
+}
+
+impl Scene {
+    // The ground plane is left out on purpose: it would make every scene
+    // at least as wide as the ground and framing would ignore the objects.
+    pub fn bounds(&self) -> Aabb {
+        let cube_bounds = self.cubes.iter().map(|cube| cube.aabb());
+        let cuboid_bounds = self.cuboids.iter().map(|cuboid| cuboid.aabb());
+        let decoration_bounds = self.decorations.iter().map(|decoration| decoration.aabb());
+        let solid_bounds = self.solids.iter().map(|solid| solid.bounds());
+
+        cube_bounds
+            .chain(cuboid_bounds)
+            .chain(decoration_bounds)
+            .chain(solid_bounds)
+            .fold(Aabb::empty(), |bounds, aabb| bounds.union(&aabb))
+    }
+
+    // Everything along the ray, nearest first, each object once at the
+    // point the ray first meets it. Picking uses it to reach objects
+    // hidden behind leaves or glass.
+    pub fn all_hits(&self, ray: &Ray) -> Vec<(f32, Occluder)> {
+        let plane_hit = (self.plane.ray_intersect(ray), Occluder::Plane);
+        let mut hits: Vec<(f32, Occluder)> = std::iter::once(plane_hit)
+            .chain(object_hits(ray, self))
+            .filter(|(intersect, _)| intersect.is_intersecting)
+            .map(|(intersect, object)| (intersect.distance, object))
+            .collect();
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        hits.truncate(MAX_ALL_HITS);
+        hits
+    }
+
+    // Solids are built from several shapes and have no single material.
+    pub fn material_mut(&mut self, object: Occluder) -> Option<&mut Material> {
+        match object {
+            Occluder::Plane => Some(&mut self.plane.material),
+            Occluder::Cube(i) => self.cubes.get_mut(i).map(|cube| &mut cube.material),
+            Occluder::Cuboid(i) => self.cuboids.get_mut(i).map(|cuboid| &mut cuboid.material),
+            Occluder::Decoration(i) => self.decorations.get_mut(i).map(|decoration| &mut decoration.material),
+            Occluder::Solid(_) => None,
+        }
+    }
+}
+
 pub struct Plane {
     pub point: Vec3,  
     pub normal: Vec3, 
     pub material: Material,
+    pub hole: Option<(Vec3, Vec3)>,
+    pub tile_size: f32,
+}
+
+impl Plane {
+    pub fn try_new(
+        point: Vec3,
+        normal: Vec3,
+        material: Material,
+        hole: Option<(Vec3, Vec3)>,
+    ) -> Result<Self, String> {
+        if !point.iter().all(|c| c.is_finite()) {
+            return Err(format!("plane point must be finite, got {:?}", point));
+        }
+        let length = normal.magnitude();
+        if !length.is_finite() || length < 1e-6 {
+            return Err(format!("plane normal must be a non-zero vector, got {:?}", normal));
+        }
+        Ok(Plane {
+            point,
+            normal: normal / length,
+            material,
+            hole,
+            tile_size: 1.0,
+        })
+    }
+
+    pub fn with_tile_size(mut self, tile_size: f32) -> Self {
+        self.tile_size = tile_size.max(1e-3);
+        self
+    }
+
+    fn in_hole(&self, point: &Vec3) -> bool {
+        match self.hole {
+            Some((min, max)) => {
+                point.x > min.x && point.x < max.x && point.z > min.z && point.z < max.z
+            }
+            None => false,
+        }
+    }
 }
 
 impl RayIntersect for Plane {
-    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect {
-        let denom = self.normal.dot(ray_direction);
+    fn ray_intersect(&self, ray: &Ray) -> Intersect {
+        debug_assert!(self.normal.magnitude() > 1e-6, "degenerate plane with zero normal");
+
+        let denom = self.normal.dot(&ray.direction);
         
         
         if denom.abs() > 1e-6 {
-            let p0l0 = self.point - ray_origin;
+            let p0l0 = self.point - ray.origin;
             let t = p0l0.dot(&self.normal) / denom;
-            if t >= 0.0 {
-                let point = ray_origin + ray_direction * t;
+            if ray.accepts(t) {
+                let point = ray.point_at(t);
 
                 
-                if point.x.abs() <= 1.0 && point.z.abs() <= 1.0 {
-                    
-                    let normal = if denom < 0.0 { self.normal } else { -self.normal };
-                    
-                    
-                    return Intersect::new(point, normal, t, self.material);
+                if point.x.abs() <= GROUND_HALF_SIZE && point.z.abs() <= GROUND_HALF_SIZE && !self.in_hole(&point) {
+                    return Intersect::new(point, self.normal, t, self.material.clone())
+                        .with_uv((point.x / self.tile_size, point.z / self.tile_size))
+                        .with_tangents(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
                 }
             }
         }
@@ -134,394 +1079,1043 @@ impl RayIntersect for Plane {
 
 
 
+#[derive(Clone, Copy, Debug)]
+pub struct SkyGradient {
+    pub zenith: Color,
+    pub horizon: Color,
+    pub ground: Color,
+}
+
+impl SkyGradient {
+    pub const fn new(zenith: Color, horizon: Color, ground: Color) -> Self {
+        SkyGradient { zenith, horizon, ground }
+    }
+
+    pub fn sample(&self, direction: &Vec3) -> Color {
+        let length = direction.magnitude();
+        if length == 0.0 {
+            return self.horizon;
+        }
+        let height = (direction.y / length).clamp(-1.0, 1.0);
+        if height >= 0.0 {
+            // sqrt keeps the pale band thin without a visible seam at the horizon
+            self.horizon.lerp(self.zenith, height.sqrt())
+        } else {
+            let t = (-height / GROUND_FADE).min(1.0);
+            self.horizon.lerp(self.ground, t * t * (3.0 - 2.0 * t))
+        }
+    }
+}
+
 pub struct Skybox {
-    pub day_material: Material,    
-    pub night_material: Material,  
-    pub current_material: Material, 
+    pub day: SkyGradient,
+    pub night: SkyGradient,
+    pub current: SkyGradient,
+    pub ambient_factor: f32,
+    pub clouds: Clouds,
+    pub show_clouds: bool,
+    pub birds: Birds,
+    pub show_birds: bool,
+    // The flock in the sky this frame, worked out once in `update_birds`
+    // so rays pay nothing while none is flying.
+    pub flock: Option<Flock>,
 }
 
 impl Skybox {
-    pub fn new(day_material: Material, night_material: Material) -> Self {
-        Skybox { 
-            day_material,
-            night_material,
-            current_material: day_material, 
+    pub fn new(day: SkyGradient, night: SkyGradient) -> Self {
+        Skybox {
+            day,
+            night,
+            current: day,
+            ambient_factor: DEFAULT_AMBIENT_FACTOR,
+            clouds: Clouds::default(),
+            show_clouds: true,
+            birds: Birds::default(),
+            show_birds: true,
+            flock: None,
         }
     }
 
-    pub fn sample(&self, _direction: Vec3) -> Color {
-        
-        self.current_material.diffuse
+    // Ambient comes from the gradient alone, so drifting clouds don't
+    // flicker the shading of every upward face.
+    pub fn ambient(&self, normal: &Vec3) -> f32 {
+        self.current.sample(normal).luminance() * self.ambient_factor
+    }
+
+    pub fn sample(&self, direction: Vec3) -> Color {
+        let sky = self.current.sample(&direction);
+        let sky = if self.show_clouds {
+            self.clouds.over(sky, &direction)
+        } else {
+            sky
+        };
+        match &self.flock {
+            Some(flock) => flock.over(sky, &direction),
+            None => sky,
+        }
+    }
+
+    // Birds only fly by day, and `allowed` is the settings flag.
+    pub fn update_birds(&mut self, time: f32, allowed: bool) {
+        self.flock = if allowed && self.show_birds { self.birds.flock_at(time) } else { None };
     }
 
     pub fn set_day(&mut self) {
-        self.current_material = self.day_material.clone();
+        self.current = self.day;
+        self.show_clouds = true;
+        self.show_birds = true;
     }
 
     pub fn set_night(&mut self) {
-        self.current_material = self.night_material.clone();
+        self.current = self.night;
+        self.show_clouds = false;
+        self.show_birds = false;
+        self.flock = None;
     }
 }
 
 
-fn load_skybox() -> Skybox {
-    let day_material = Material::new(
-        Color::new(135, 206, 235),  
-        50.0,
-        [1.0, 0.0, 0.0, 0.0],       
-        1.0,
-    );
-
-    let night_material = Material::new(
-        Color::new(10, 10, 30),  
-        50.0,
-        [1.0, 0.0, 0.0, 0.0],    
-        1.0,
-    );
-    
+// Only the torches closest to the eye trace shadows; the far ones are fills,
+// since their shadows are too faint to notice at that distance.
+fn night_rig(eye: &Vec3) -> Vec<Light> {
+    let mut antorchas: Vec<Vec3> = TORCH_POSITIONS.iter().map(|&[x, y, z]| Vec3::new(x, y, z)).collect();
+    antorchas.sort_by(|a, b| (a - eye).magnitude().total_cmp(&(b - eye).magnitude()));
 
-    Skybox::new(day_material, night_material)
+    let luna = Light::directional(Vec3::new(-1.0, -1.0, -1.0), palette::MOONLIGHT, 0.35)
+        .with_area_radius(SUN_AREA_RADIUS);
+    std::iter::once(luna)
+        .chain(antorchas.iter().enumerate().map(|(i, position)| {
+            Light::fill(*position, palette::GLOWSTONE, TORCH_INTENSITY).with_shadows(i < SHADOWED_TORCHES)
+        }))
+        .collect()
 }
 
+fn load_skybox() -> Skybox {
+    let day = SkyGradient::new(palette::DAY_ZENITH, palette::DAY_HORIZON, palette::DAY_GROUND);
+    let night = SkyGradient::new(palette::NIGHT_ZENITH, palette::NIGHT_HORIZON, palette::NIGHT_GROUND);
 
+    Skybox::new(day, night)
+}
 
-fn main() {
-    let window_width = 800;
-    let window_height = 600;
-    let framebuffer_width = 400;
-    let framebuffer_height = 300;
-    let frame_delay = Duration::from_millis(16);
-    let mut is_day = true; 
-
-
-    let mut framebuffer = Framebuffer::new(framebuffer_width, framebuffer_height);
 
-    let mut window = Window::new(
-        "Refractor",
-        window_width,
-        window_height,
-        WindowOptions::default(),
-    ).unwrap();
 
-    let mut skybox = load_skybox();
+fn window_scale_from_args() -> Scale {
+    let args: Vec<String> = std::env::args().collect();
+    let value = match args.iter().position(|arg| arg == "--window-scale") {
+        Some(index) => args.get(index + 1).map(String::as_str),
+        None => return Scale::X2,
+    };
 
-    let plane_material = Material::new(
-        Color::new(34, 139, 34),  
-        50.0,
-        [1.0, 0.0, 0.0, 0.0],     
-        1.0,
-    );    
+    match value {
+        Some("1") => Scale::X1,
+        Some("2") => Scale::X2,
+        Some("4") => Scale::X4,
+        Some("fit") => Scale::FitScreen,
+        other => {
+            eprintln!("--window-scale expects 1, 2, 4 or fit, got {:?}; using 2", other);
+            Scale::X2
+        }
+    }
+}
 
-    let plane = Plane {
-        point: Vec3::new(0.0, 0.0, 0.0),
-        normal: Vec3::new(0.0, 1.0, 0.0),
-        material: plane_material,
+fn stress_from_args() -> Option<(usize, u64)> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|arg| arg == "--stress")?;
+    let count = match args.get(index + 1).map(|value| value.parse::<usize>()) {
+        Some(Ok(count)) => count,
+        other => {
+            eprintln!("--stress expects a cube count, got {:?}; ignoring it", other);
+            return None;
+        }
     };
+    let seed = args.get(index + 2).and_then(|value| value.parse().ok()).unwrap_or(1);
+    Some((count, seed))
+}
 
-    let tronco = Material::new(
-        Color::new(139, 69, 19),  
-        50.0,
-        [0.8, 0.2, 0.0, 0.0],     
-        1.0,
-    );    
+// The probe is low resolution and blurred by bilinear lookups, so soft
+// shadows and glossy fans would be wasted on it.
+fn probe_settings(settings: &RenderSettings) -> RenderSettings {
+    RenderSettings { shadow_samples: 1, reflection_samples: 1, ..*settings }
+}
 
-    let hojas = Material::new(
-        Color::new(0, 255, 0),  
-        50.0,
-        [0.8, 0.2, 0.0, 0.0],
-        1.0,
-    );
-    let agua = Material::new(
-        Color::new(0, 0, 255),  
-        50.0,
-        [0.5, 0.5, 0.0, 0.0],  
-        1.0,
-    );
-    let mut tiempo = 0.0;
+fn ground_color_from_args() -> Color {
+    let args: Vec<String> = std::env::args().collect();
+    let name = match args.iter().position(|arg| arg == "--ground-color") {
+        Some(index) => args.get(index + 1).map(String::as_str).unwrap_or(""),
+        None => return palette::GRASS,
+    };
 
-    
-    let mut cubos_agua = vec![
-        Cube::new(Vec3::new(0.0, 0.0, 0.0), 0.10, agua.clone()),
-        Cube::new(Vec3::new(-0.1, 0.0, 0.0), 0.10, agua.clone()),
-        Cube::new(Vec3::new(-0.1, 0.0, 0.1), 0.10, agua.clone()),
-        Cube::new(Vec3::new(0.0, 0.0, 0.1), 0.10, agua.clone()),
-    ];
+    palette::get(name).unwrap_or_else(|| {
+        let names: Vec<&str> = palette::NAMED.iter().map(|(name, _)| *name).collect();
+        eprintln!("--ground-color expects one of {}, got {:?}; using grass", names.join(", "), name);
+        palette::GRASS
+    })
+}
 
-    
+fn gi_samples_from_args() -> Option<u32> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|arg| arg == "--gi")?;
+    match args.get(index + 1).map(|value| value.parse::<u32>()) {
+        Some(Ok(samples)) => Some(samples),
+        other => {
+            eprintln!("--gi expects a sample count, got {:?}; ignoring it", other);
+            None
+        }
+    }
+}
 
-    let cubes = vec![
-        
-        Cube::new(Vec3::new(-0.8, 0.10, -0.8), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.8, 0.20, -0.8), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.8, 0.30, -0.8), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.8, 0.40, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.9, 0.40, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.7, 0.40, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.8, 0.50, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.8, 0.40, -0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.8, 0.40, -0.7), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(-0.5, 0.10, -0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.5, 0.20, -0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.5, 0.30, -0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.5, 0.40, -0.5), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.5, 0.50, -0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.5, 0.60, -0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.6, 0.50, -0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.4, 0.50, -0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.5, 0.50, -0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.5, 0.50, -0.4), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(-0.1, 0.10, -0.8), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.1, 0.20, -0.8), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.1, 0.30, -0.8), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.1, 0.40, -0.8), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.1, 0.50, -0.8), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.1, 0.60, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.1, 0.70, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.2, 0.60, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.0, 0.60, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.1, 0.60, -0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.1, 0.60, -0.7), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.6, 0.10, -0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.6, 0.20, -0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.6, 0.30, -0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.6, 0.40, -0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.6, 0.50, -0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.6, 0.60, -0.6), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.6, 0.70, -0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.6, 0.80, -0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.5, 0.70, -0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.7, 0.70, -0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.6, 0.70, -0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.6, 0.70, -0.5), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(-0.9, 0.10, 0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.9, 0.20, 0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.9, 0.30, 0.5), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.9, 0.40, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.9, 0.50, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-1.0, 0.40, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.8, 0.40, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.9, 0.50, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.9, 0.40, 0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.9, 0.40, 0.4), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.3, 0.10, 0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.3, 0.20, 0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.3, 0.30, 0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.3, 0.40, 0.9), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.3, 0.50, 0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.3, 0.60, 0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.2, 0.50, 0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.4, 0.50, 0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.3, 0.50, 1.0), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.3, 0.50, 0.8), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.8, 0.10, 0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.8, 0.20, 0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.8, 0.30, 0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.8, 0.40, 0.6), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.8, 0.50, 0.6), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.8, 0.60, 0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.8, 0.70, 0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.7, 0.60, 0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.9, 0.60, 0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.8, 0.60, 0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.8, 0.60, 0.5), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.4, 0.10, -0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.4, 0.20, -0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.4, 0.30, -0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.4, 0.40, -0.9), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.4, 0.50, -0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.3, 0.50, -0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.5, 0.50, -0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.4, 0.60, -0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.4, 0.50, -1.0), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.4, 0.50, -0.8), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.9, 0.10, 0.4), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.9, 0.20, 0.4), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.9, 0.30, 0.4), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.9, 0.40, 0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(1.0, 0.40, 0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.8, 0.40, 0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.9, 0.50, 0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.9, 0.40, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.9, 0.40, 0.3), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(-0.4, 0.10, 0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.4, 0.20, 0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.4, 0.30, 0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.4, 0.40, 0.9), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.4, 0.50, 0.9), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.4, 0.60, 0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.3, 0.60, 0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.5, 0.60, 0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.4, 0.70, 0.9), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.4, 0.60, 1.0), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.4, 0.60, 0.8), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.7, 0.10, 0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.7, 0.20, 0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.7, 0.30, 0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.7, 0.40, 0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.7, 0.50, 0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.7, 0.60, 0.7), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.7, 0.70, 0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.6, 0.70, 0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.8, 0.70, 0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.7, 0.80, 0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.7, 0.70, 0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.7, 0.70, 0.6), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(-0.6, 0.10, -0.4), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.6, 0.20, -0.4), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.6, 0.30, -0.4), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.6, 0.40, -0.4), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.6, 0.50, -0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.7, 0.50, -0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.5, 0.50, -0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.6, 0.60, -0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.6, 0.50, -0.3), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.6, 0.50, -0.5), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.3, 0.10, 0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.3, 0.20, 0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.3, 0.30, 0.5), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.3, 0.40, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.2, 0.40, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.4, 0.40, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.3, 0.50, 0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.3, 0.40, 0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.3, 0.40, 0.4), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(-0.2, 0.10, -0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.2, 0.20, -0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.2, 0.30, -0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.2, 0.40, -0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.2, 0.50, -0.2), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.2, 0.60, -0.2), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.3, 0.60, -0.2), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.1, 0.60, -0.2), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.2, 0.70, -0.2), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.2, 0.60, -0.3), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.2, 0.60, -0.1), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.8, 0.10, -0.3), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.8, 0.20, -0.3), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.8, 0.30, -0.3), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.8, 0.40, -0.3), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.7, 0.40, -0.3), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.9, 0.40, -0.3), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.8, 0.50, -0.3), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.8, 0.40, -0.4), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.8, 0.40, -0.2), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(-0.7, 0.10, 0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.7, 0.20, 0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.7, 0.30, 0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.7, 0.40, 0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.7, 0.50, 0.2), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.7, 0.60, 0.2), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.7, 0.70, 0.2), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.8, 0.70, 0.2), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.6, 0.70, 0.2), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.7, 0.80, 0.2), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.7, 0.70, 0.3), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.7, 0.70, 0.1), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(0.1, 0.10, -0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.1, 0.20, -0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.1, 0.30, -0.5), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(0.1, 0.40, -0.5), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(0.1, 0.50, -0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.0, 0.50, -0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.2, 0.50, -0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.1, 0.60, -0.5), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.1, 0.50, -0.6), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(0.1, 0.50, -0.4), 0.10, hojas.clone()),
-
-        
-        Cube::new(Vec3::new(-0.6, 0.10, -0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.6, 0.20, -0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.6, 0.30, -0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.6, 0.40, -0.7), 0.10, tronco.clone()),
-        Cube::new(Vec3::new(-0.6, 0.50, -0.7), 0.10, tronco.clone()),
-        
-        Cube::new(Vec3::new(-0.6, 0.60, -0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.7, 0.60, -0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.5, 0.60, -0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.6, 0.70, -0.7), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.6, 0.60, -0.8), 0.10, hojas.clone()),
-        Cube::new(Vec3::new(-0.6, 0.60, -0.6), 0.10, hojas.clone()),
+fn ao_samples_from_args() -> u32 {
+    let args: Vec<String> = std::env::args().collect();
+    let index = match args.iter().position(|arg| arg == "--ao") {
+        Some(index) => index,
+        None => return 0,
+    };
+    match args.get(index + 1).map(|value| value.parse::<u32>()) {
+        Some(Ok(samples)) => samples,
+        other => {
+            eprintln!("--ao expects a sample count, got {:?}; ignoring it", other);
+            0
+        }
+    }
+}
 
+// The framebuffer pixel under the mouse, going through the window's own
+// size so it still lines up after the window is resized.
+fn pixel_under_mouse(window: &Window, width: usize, height: usize) -> Option<(usize, usize)> {
+    let (mouse_x, mouse_y) = window.get_unscaled_mouse_pos(MouseMode::Discard)?;
+    let (window_width, window_height) = window.get_size();
+    let x = (mouse_x * width as f32 / window_width.max(1) as f32) as usize;
+    let y = (mouse_y * height as f32 / window_height.max(1) as f32) as usize;
+    (x < width && y < height).then_some((x, y))
+}
 
-    ];
+fn contact_shadows_from_args() -> bool {
+    std::env::args().any(|arg| arg == "--contact-shadows")
+}
+
+fn no_birds_from_args() -> bool {
+    std::env::args().any(|arg| arg == "--no-birds")
+}
+
+fn caustics_from_args() -> bool {
+    std::env::args().any(|arg| arg == "--caustics")
+}
+
+fn island_from_args() -> bool {
+    let args: Vec<String> = std::env::args().collect();
+    let value = match args.iter().position(|arg| arg == "--edge") {
+        Some(index) => args.get(index + 1).map(String::as_str),
+        None => return true,
+    };
+
+    match value {
+        Some("island") => true,
+        Some("none") => false,
+        other => {
+            eprintln!("--edge expects island or none, got {:?}; using island", other);
+            true
+        }
+    }
+}
+
+fn normalize_materials_from_args() -> bool {
+    std::env::args().any(|arg| arg == "--normalize-materials")
+}
+
+fn checked_material(name: &str, material: Material, normalize: bool) -> Material {
+    let energy = material.energy();
+    if energy <= 1.0 {
+        return material;
+    }
+
+    if normalize {
+        let normalized = material.normalized();
+        eprintln!("material {} weights sum to {:.2}; normalized to {:?}", name, energy, normalized.albedo);
+        normalized
+    } else {
+        eprintln!("material {} weights sum to {:.2}, over 1.0; pass --normalize-materials to scale them down", name, energy);
+        material
+    }
+}
+
+// Assets that aren't there keep the flat colors; ones that are present load
+// in the background and show the placeholder until they're ready.
+fn with_optional_asset(
+    material: Material,
+    path: &str,
+    attach: impl FnOnce(Material, &str) -> Material,
+) -> Material {
+    if !std::path::Path::new(path).exists() {
+        return material;
+    }
+    attach(material, path)
+}
+
+// Each cube face shows the whole texture once, so clamping keeps the far
+// edge from bleeding in under bilinear filtering.
+fn face_texture(texture: Texture) -> Texture {
+    texture.with_wrap(WrapMode::Clamp)
+}
+
+fn load_atlas() -> Option<Arc<Atlas>> {
+    if !std::path::Path::new(ATLAS_PATH).exists() {
+        return None;
+    }
+
+    match Atlas::load(ATLAS_PATH, ATLAS_TILE_SIZE, ATLAS_TILE_SIZE) {
+        Ok(atlas) => Some(atlas),
+        Err(err) => {
+            eprintln!("{}; using the flat colors instead", err);
+            None
+        }
+    }
+}
+
+fn reproducible_from_args() -> bool {
+    std::env::args().any(|arg| arg == "--reproducible")
+}
+
+fn cave_from_args() -> bool {
+    std::env::args().any(|arg| arg == "--cave")
+}
+
+fn mirror_lake_from_args() -> bool {
+    std::env::args().any(|arg| arg == "--mirror-lake")
+}
+
+// Everything the frame loop needs from a built scene. It is put together
+// on a worker thread and handed over whole.
+struct Diorama {
+    scene: Scene,
+    camera: Camera,
+    school: School,
+    // Where the bobbing water cubes and the moving fish start in the
+    // scene's lists.
+    water_start: usize,
+    fish_start: usize,
+    bounds: Aabb,
+}
+
+impl Diorama {
+    fn new(scene: Scene, camera: Camera, school: School, water_start: usize, fish_start: usize) -> Self {
+        // Measured once the scene is final, in build_diorama.
+        let bounds = Aabb::empty();
+        Diorama { scene, camera, school, water_start, fish_start, bounds }
+    }
+
+    fn from_demo((scene, camera): (Scene, Camera)) -> Self {
+        let (water_start, fish_start) = (scene.cubes.len(), scene.cuboids.len());
+        Diorama::new(scene, camera, School::new(Aabb::empty(), 0, FISH_SEED), water_start, fish_start)
+    }
+}
+
+// The diorama by the pond. `semilla_peces` seeds the fish, which is what
+// a regeneration changes here.
+fn build_pond(semilla_peces: u32, texturas: &mut TextureManager, progreso: &Progress<Diorama>) -> Result<Diorama, String> {
+    progreso.report(0.0, "materiales")?;
+    let skybox = load_skybox();
+
+    let color_pasto = ground_color_from_args();
+    let plane_material = Material::new(
+        color_pasto,  
+        50.0,
+        [1.0, 0.0, 0.0, 0.0],     
+        1.0,
+    ).with_sky_specular(0.3);
+    let normalize_materials = normalize_materials_from_args();
+    let plane_material = checked_material("pasto", plane_material, normalize_materials);
+    let plane_material = if std::env::args().any(|arg| arg == "--ground-checker") {
+        plane_material.with_pattern(Pattern::Checker {
+            color_a: color_pasto,
+            color_b: palette::darker(color_pasto, 0.25),
+            scale: 0.1,
+        })
+    } else {
+        plane_material
+    };
+
+    let plane_material = with_optional_asset(plane_material, "assets/pasto.png", |material, path| {
+        material.with_texture(texturas.load(path, |texture| texture.with_filter(FilterMode::Bilinear)))
+    });
+
+    let plane = Plane::try_new(
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        plane_material,
+        Some((Vec3::new(-0.15, 0.0, -0.05), Vec3::new(0.05, 0.0, 0.15))),
+    ).map_err(|err| format!("invalid ground plane: {}", err))?.with_tile_size(GROUND_TILE_SIZE);
+
+    let tronco = Material::new(
+        palette::OAK_LOG,  
+        50.0,
+        [0.8, 0.2, 0.0, 0.0],     
+        1.0,
+    ).with_bevel(0.05).with_two_sided(false);    
+
+    let hojas = Material::new(
+        palette::OAK_LEAVES,  
+        50.0,
+        [0.8, 0.2, 0.0, 0.0],
+        1.0,
+    ).with_bevel(0.05).with_two_sided(false).with_sky_specular(0.2).with_pattern(Pattern::Noise {
+        base: palette::OAK_LEAVES,
+        accent: palette::darker(palette::OAK_LEAVES, 0.35),
+        frequency: 30.0,
+        seed: 7,
+    });
+    let agua = Material::new(
+        palette::WATER,  
+        50.0,
+        [0.2, 0.2, 0.1, 0.5],
+        1.33,
+    ).with_absorption(Vec3::new(4.0, 1.5, 1.2))
+    .with_probe_reflections(true);
+    let arena = Material::new(
+        palette::SAND,
+        10.0,
+        [0.9, 0.1, 0.0, 0.0],
+        1.0,
+    ).with_two_sided(false);
+    let oro = Material::new(
+        palette::GOLD_BLOCK,
+        80.0,
+        [0.6, 0.4, 0.0, 0.0],
+        1.0,
+    ).with_two_sided(false);
+    let rubi = Material::new(
+        palette::RUBY_BLOCK,
+        80.0,
+        [0.6, 0.4, 0.0, 0.0],
+        1.0,
+    ).with_two_sided(false);
+    let tierra = Material::new(
+        palette::DIRT,
+        10.0,
+        [0.9, 0.1, 0.0, 0.0],
+        1.0,
+    ).with_two_sided(false);
+    let piedra = Material::new(
+        palette::STONE,
+        30.0,
+        [0.8, 0.2, 0.0, 0.0],
+        1.0,
+    );
+    let oro_pulido = Material::metal(palette::GOLD_BLOCK).with_two_sided(false);
+    let [tronco, hojas, agua, arena, oro, rubi, tierra, piedra] = [
+        ("tronco", tronco),
+        ("hojas", hojas),
+        ("agua", agua),
+        ("arena", arena),
+        ("oro", oro),
+        ("rubi", rubi),
+        ("tierra", tierra),
+        ("piedra", piedra),
+    ].map(|(name, material)| checked_material(name, material, normalize_materials));
+    let roca = Material::new(
+        palette::COBBLESTONE,
+        20.0,
+        [0.85, 0.15, 0.0, 0.0],
+        1.0,
+    ).with_bevel(0.03).with_two_sided(false).with_pattern(Pattern::Noise {
+        base: palette::STONE,
+        accent: palette::darker(palette::COBBLESTONE, 0.3),
+        frequency: 45.0,
+        seed: 21,
+    });
+    let lava = Material::new(
+        palette::darker(palette::LAVA, 0.4),
+        10.0,
+        [0.9, 0.1, 0.0, 0.0],
+        1.0,
+    ).with_two_sided(false);
+    let lava = with_optional_asset(lava, "assets/lava_emissive.png", |material, path| {
+        material.with_emissive_map(texturas.load(path, |texture| texture))
+    });
+    let cesped = Material::new(
+        palette::GRASS,
+        10.0,
+        [0.9, 0.1, 0.0, 0.0],
+        1.0,
+    ).with_two_sided(false).with_sky_specular(0.3);
+    let tronco = with_optional_asset(tronco, "assets/tronco.png", |material, path| {
+        material.with_texture(texturas.load(path, face_texture))
+    });
+    let tronco = with_optional_asset(tronco, "assets/tronco_normal.png", |material, path| {
+        material.with_normal_map(texturas.load_data(path, |texture| texture))
+    });
+    let hojas = with_optional_asset(hojas, "assets/hojas.png", |material, path| {
+        material.with_texture(texturas.load(path, face_texture))
+    });
+    let [tronco, hojas, agua, cesped] = match load_atlas() {
+        Some(atlas) => [
+            (tronco, ATLAS_TILE_TRONCO),
+            (hojas, ATLAS_TILE_HOJAS),
+            (agua, ATLAS_TILE_AGUA),
+            (cesped, ATLAS_TILE_CESPED),
+        ].map(|(material, tile)| material.with_atlas_tile(atlas.clone(), tile)),
+        None => [tronco, hojas, agua, cesped],
+    };
+    let bloque_pasto = [tierra.clone(), tierra.clone(), cesped, tierra.clone(), tierra.clone(), tierra.clone()];
+
+    progreso.report(0.1, "cubos")?;
+    let cubos_agua: Vec<Cube> = vec![
+        Cube::try_new(Vec3::new(0.0, 0.0, 0.0), 0.10, agua.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.0, 0.0), 0.10, agua.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.0, 0.1), 0.10, agua.clone()),
+        Cube::try_new(Vec3::new(0.0, 0.0, 0.1), 0.10, agua.clone()),
+    ]
+    .into_iter()
+    .map(|cubo| cubo.map(|cubo| cubo.with_layers(layers::layer("water"))))
+    .collect::<Result<_, _>>()
+    .map_err(|err| format!("invalid water cube: {}", err))?;
 
     
 
-    let mut camera = Camera::new(
+    let cubos_estanque: Vec<Cube> = vec![
+        
+        Cube::try_new(Vec3::new(0.0, -0.15, 0.0), 0.10, arena.clone()),
+        Cube::try_new(Vec3::new(-0.1, -0.15, 0.0), 0.10, oro.clone()),
+        Cube::try_new(Vec3::new(-0.1, -0.15, 0.1), 0.10, arena.clone()),
+        Cube::try_new(Vec3::new(0.0, -0.15, 0.1), 0.10, rubi.clone()),
+        Cube::try_with_face_materials(Vec3::new(-0.2, POND_WALL_Y, -0.1), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(-0.1, POND_WALL_Y, -0.1), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(0.0, POND_WALL_Y, -0.1), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(0.1, POND_WALL_Y, -0.1), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(-0.2, POND_WALL_Y, 0.2), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(-0.1, POND_WALL_Y, 0.2), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(0.0, POND_WALL_Y, 0.2), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(0.1, POND_WALL_Y, 0.2), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(-0.2, POND_WALL_Y, 0.0), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(-0.2, POND_WALL_Y, 0.1), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(0.1, POND_WALL_Y, 0.0), 0.10, bloque_pasto.clone()),
+        Cube::try_with_face_materials(Vec3::new(0.1, POND_WALL_Y, 0.1), 0.10, bloque_pasto.clone()),
+    ]
+    .into_iter()
+    .collect::<Result<_, _>>()
+    .map_err(|err| format!("invalid scene cube: {}", err))?;
+
+    let cubos_arboles: Vec<Cube> = vec![
+        Cube::try_new(Vec3::new(-0.8, 0.10, -0.8), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.8, 0.20, -0.8), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.8, 0.30, -0.8), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.8, 0.40, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.9, 0.40, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.40, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.8, 0.50, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.8, 0.40, -0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.8, 0.40, -0.7), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(-0.5, 0.10, -0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.20, -0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.30, -0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.40, -0.5), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.5, 0.50, -0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.60, -0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.50, -0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.4, 0.50, -0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.50, -0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.50, -0.4), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(-0.1, 0.10, -0.8), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.20, -0.8), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.30, -0.8), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.40, -0.8), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.50, -0.8), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.1, 0.60, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.70, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.2, 0.60, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.0, 0.60, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.60, -0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.60, -0.7), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.6, 0.10, -0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.20, -0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.30, -0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.40, -0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.50, -0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.60, -0.6), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.6, 0.70, -0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.80, -0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.5, 0.70, -0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.70, -0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.70, -0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.70, -0.5), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(-0.9, 0.10, 0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.9, 0.20, 0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.9, 0.30, 0.5), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.9, 0.40, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.9, 0.50, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-1.0, 0.40, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.8, 0.40, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.9, 0.50, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.9, 0.40, 0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.9, 0.40, 0.4), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.3, 0.10, 0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.20, 0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.30, 0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.40, 0.9), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.3, 0.50, 0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.60, 0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.2, 0.50, 0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.50, 0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.50, 1.0), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.50, 0.8), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.8, 0.10, 0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.20, 0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.30, 0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.40, 0.6), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.50, 0.6), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.8, 0.60, 0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.70, 0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.60, 0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.9, 0.60, 0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.60, 0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.60, 0.5), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.4, 0.10, -0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.20, -0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.30, -0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.40, -0.9), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.4, 0.50, -0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.50, -0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.5, 0.50, -0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.60, -0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.50, -1.0), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.50, -0.8), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.9, 0.10, 0.4), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.9, 0.20, 0.4), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.9, 0.30, 0.4), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.9, 0.40, 0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(1.0, 0.40, 0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.40, 0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.9, 0.50, 0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.9, 0.40, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.9, 0.40, 0.3), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(-0.4, 0.10, 0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.4, 0.20, 0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.4, 0.30, 0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.4, 0.40, 0.9), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.4, 0.50, 0.9), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.4, 0.60, 0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.3, 0.60, 0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.60, 0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.4, 0.70, 0.9), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.4, 0.60, 1.0), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.4, 0.60, 0.8), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.7, 0.10, 0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.20, 0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.30, 0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.40, 0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.50, 0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.60, 0.7), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.7, 0.70, 0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.6, 0.70, 0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.70, 0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.80, 0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.70, 0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.70, 0.6), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(-0.6, 0.10, -0.4), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.20, -0.4), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.30, -0.4), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.40, -0.4), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.6, 0.50, -0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.50, -0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.50, -0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.60, -0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.50, -0.3), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.50, -0.5), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.3, 0.10, 0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.20, 0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.30, 0.5), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.3, 0.40, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.2, 0.40, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.40, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.50, 0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.40, 0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.3, 0.40, 0.4), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(-0.2, 0.10, -0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.2, 0.20, -0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.2, 0.30, -0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.2, 0.40, -0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.2, 0.50, -0.2), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.2, 0.60, -0.2), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.3, 0.60, -0.2), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.1, 0.60, -0.2), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.2, 0.70, -0.2), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.2, 0.60, -0.3), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.2, 0.60, -0.1), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.8, 0.10, -0.3), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.20, -0.3), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.30, -0.3), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.8, 0.40, -0.3), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.7, 0.40, -0.3), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.9, 0.40, -0.3), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.50, -0.3), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.40, -0.4), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.8, 0.40, -0.2), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(-0.7, 0.10, 0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.20, 0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.30, 0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.40, 0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.50, 0.2), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.60, 0.2), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.7, 0.70, 0.2), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.8, 0.70, 0.2), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.70, 0.2), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.80, 0.2), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.70, 0.3), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.70, 0.1), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(0.1, 0.10, -0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.1, 0.20, -0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.1, 0.30, -0.5), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(0.1, 0.40, -0.5), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(0.1, 0.50, -0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.0, 0.50, -0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.2, 0.50, -0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.1, 0.60, -0.5), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.1, 0.50, -0.6), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(0.1, 0.50, -0.4), 0.10, hojas.clone()),
+
+        
+        Cube::try_new(Vec3::new(-0.6, 0.10, -0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.20, -0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.30, -0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.40, -0.7), 0.10, tronco.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.50, -0.7), 0.10, tronco.clone()),
+        
+        Cube::try_new(Vec3::new(-0.6, 0.60, -0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.7, 0.60, -0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.5, 0.60, -0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.70, -0.7), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.60, -0.8), 0.10, hojas.clone()),
+        Cube::try_new(Vec3::new(-0.6, 0.60, -0.6), 0.10, hojas.clone()),
+    ]
+    .into_iter()
+    .map(|cubo| cubo.map(|cubo| cubo.with_layers(layers::layer("trees"))))
+    .collect::<Result<_, _>>()
+    .map_err(|err| format!("invalid scene cube: {}", err))?;
+
+    let cubos_objetos: Vec<Cube> = vec![
+        Cube::try_new(Vec3::new(0.4, 0.05, 0.1), 0.10, oro_pulido.clone()),
+
+        Cube::try_new(Vec3::new(-0.4, 0.05, -0.1), 0.10, lava.clone()),
+
+        Cube::try_new(Vec3::new(0.5, 0.05, -0.2), 0.10, roca.clone()),
+        Cube::try_new(Vec3::new(0.4, 0.05, -0.2), 0.10, roca.clone()),
+        Cube::try_new(Vec3::new(0.5, 0.15, -0.2), 0.10, roca.clone()),
+    ]
+    .into_iter()
+    .map(|cubo| cubo.map(|cubo| cubo.with_layers(layers::layer("props"))))
+    .collect::<Result<_, _>>()
+    .map_err(|err| format!("invalid scene cube: {}", err))?;
+
+    let fondo_estanque = cubos_estanque.iter().map(|cubo| cubo.aabb().max.y).fold(f32::MIN, f32::max);
+    let mut cubes: Vec<Cube> = [cubos_estanque, cubos_arboles, cubos_objetos].concat();
+
+    // An arrow painted on the front of the bottom rock, if the image is there.
+    let mut calcomanias = Decals::default();
+    if std::path::Path::new("assets/flecha.png").exists() {
+        if let Some(roca_frente) = cubes.iter().position(|cubo| cubo.center == Vec3::new(0.5, 0.05, -0.2)) {
+            let flecha = texturas.load("assets/flecha.png", face_texture);
+            if let Err(err) = calcomanias.add(Decal::new(flecha, roca_frente, FACE_FRONT, (0.2, 0.2), (0.8, 0.8))) {
+                eprintln!("{}; skipping the arrow", err);
+            }
+        }
+    }
+
+    
+
+    let piedras = vec![
+        SdfPrimitive::rounded_box(Vec3::new(0.2, 0.02, 0.1), Vec3::new(0.04, 0.02, 0.03), 0.015, piedra.clone())
+            .with_layers(layers::layer("props")),
+        SdfPrimitive::rounded_box(Vec3::new(-0.3, 0.015, 0.05), Vec3::new(0.03, 0.015, 0.03), 0.012, piedra.clone())
+            .with_layers(layers::layer("props")),
+        SdfPrimitive::rounded_box(Vec3::new(-0.05, 0.02, 0.3), Vec3::new(0.035, 0.02, 0.025), 0.015, piedra.clone())
+            .with_layers(layers::layer("props")),
+    ];
+
+    let portal = arched_doorway(Vec3::new(0.0, 0.0, -0.95), Vec3::new(0.6, 0.45, 0.08), 0.2, 0.3, piedra.clone())
+        .with_layers(layers::layer("props"));
+
+    let camera = Camera::new(
         Vec3::new(0.0, 3.0, 5.0),
         Vec3::new(0.0, 0.0, 0.0),
         Vec3::new(0.0, 1.0, 0.0),
     );
 
-    let mut light = Light::new(
-        Vec3::new(5.0, 5.0, 5.0),  
-        Color::new(255, 255, 255),  
-        1.0,                        
+    let light = Light::directional(
+        Vec3::new(-1.0, -1.0, -1.0),
+        palette::SUNLIGHT,
+        SUN_INTENSITY,
+    ).with_area_radius(SUN_AREA_RADIUS);
+
+    let inicio_agua = cubes.len();
+    cubes.extend_from_slice(&cubos_agua);
+    let mut scene = Scene {
+        plane,
+        cubes,
+        cuboids: vec![],
+        decorations: piedras,
+        solids: vec![portal],
+        lights: vec![light],
+        decals: calcomanias,
+        skybox,
+        probe: None,
+        waves: Waves::default(),
+    };
+    if island_from_args() {
+        progreso.report(0.2, "isla")?;
+        // The skirt starts below whatever is sunk into the ground.
+        let fondo = scene.bounds().min.y.min(scene.plane.point.y);
+        scene.cuboids = island::skirt(Vec3::new(0.0, scene.plane.point.y, 0.0), GROUND_HALF_SIZE, fondo, &tierra, &piedra);
+    }
+    // Fish swim in the water at rest, down to the pond floor. They go
+    // last in the cuboids so they can be moved in place every frame.
+    let agua_quieta = cubos_agua.iter().fold(Aabb::empty(), |caja, cubo| caja.union(&cubo.aabb()));
+    let cardumen = School::new(
+        Aabb::new(Vec3::new(agua_quieta.min.x, fondo_estanque, agua_quieta.min.z), agua_quieta.max),
+        FISH_COUNT,
+        semilla_peces,
     );
+    let pez = Material::new(palette::GOLDFISH, 40.0, [0.8, 0.2, 0.0, 0.0], 1.0);
+    let inicio_peces = scene.cuboids.len();
+    scene.cuboids.extend(cardumen.cuboids(&pez, layers::layer("water")));
+    let centro_agua = cubos_agua.iter().map(|cubo| cubo.center).sum::<Vec3>() / cubos_agua.len() as f32;
+    let mut sonda = ReflectionProbe::new(centro_agua + Vec3::new(0.0, PROBE_HEIGHT, 0.0), PROBE_RESOLUTION);
+    // The probe is the slow part, so it reports each face.
+    for cara in 0..probe::FACE_COUNT {
+        let fraccion = 0.3 + 0.7 * cara as f32 / probe::FACE_COUNT as f32;
+        progreso.report(fraccion, &format!("sonda de reflejos {}/{}", cara + 1, probe::FACE_COUNT))?;
+        sonda.capture_face(cara, &scene, &probe_settings(&RenderSettings::default()));
+    }
+    scene.probe = Some(sonda);
 
-    
-    
 
+    Ok(Diorama::new(scene, camera, cardumen, inicio_agua, inicio_peces))
+}
+
+// Builds whichever scene the flags ask for. `generacion` counts the
+// regenerations asked for with G; each one reseeds the fish and the
+// stress cubes.
+fn build_diorama(generacion: u32, stress: Option<(usize, u64)>, progreso: &Progress<Diorama>) -> Result<Diorama, String> {
+    let mut texturas = TextureManager::default();
+    let mut diorama = if cave_from_args() {
+        progreso.report(0.5, "cueva")?;
+        Diorama::from_demo(demos::cave())
+    } else if mirror_lake_from_args() {
+        progreso.report(0.5, "lago espejo")?;
+        Diorama::from_demo(demos::mirror_lake())
+    } else {
+        build_pond(FISH_SEED.wrapping_add(generacion), &mut texturas, progreso)?
+    };
+
+    if let Some((count, seed)) = stress {
+        progreso.report(0.9, "cubos al azar")?;
+        let scene = &mut diorama.scene;
+        scene.cubes = demos::stress_scene(count, seed.wrapping_add(generacion as u64));
+        scene.cuboids.clear();
+        scene.decorations.clear();
+        scene.solids.clear();
+        scene.decals.clear();
+        scene.probe = None;
+        diorama.water_start = scene.cubes.len();
+        diorama.fish_start = 0;
+        // Keeps the first-frame timing from racing the texture loaders.
+        texturas.wait();
+    }
+    // There is no acceleration structure yet; the scene bounds are the only
+    // thing built up front, so that is what the stress run times.
+    let build_time = Instant::now();
+    diorama.bounds = diorama.scene.bounds();
+    if let Some((count, seed)) = stress {
+        let seed = seed.wrapping_add(generacion as u64);
+        println!("stress {} cubos (semilla {}): construccion {:.1} ms", count, seed, build_time.elapsed().as_secs_f32() * 1000.0);
+    }
+    Ok(diorama)
+}
+
+fn start_build(generacion: u32, stress: Option<(usize, u64)>) -> Loader<Diorama> {
+    Loader::start(move |progreso| build_diorama(generacion, stress, progreso))
+}
+
+fn main() {
+    if reproducible_from_args() {
+        if cfg!(feature = "reproducible") {
+            math::set_reproducible(true);
+        } else {
+            eprintln!("--reproducible needs a build with --features reproducible; ignoring it");
+        }
+    }
+    let framebuffer_width = 400;
+    let framebuffer_height = 300;
+    let frame_delay = Duration::from_millis(16);
+
+
+    let mut framebuffer = Framebuffer::new(framebuffer_width, framebuffer_height);
+
+    let mut window = Window::new(
+        "Refractor",
+        framebuffer_width,
+        framebuffer_height,
+        WindowOptions {
+            scale: window_scale_from_args(),
+            scale_mode: ScaleMode::AspectRatioStretch,
+            resize: true,
+            ..WindowOptions::default()
+        },
+    ).unwrap();
+
+    let mut tiempo = 0.0;
     let rotation_speed = PI / 10.0;
+    let cueva = cave_from_args();
+    let stress = stress_from_args();
+
+    // The window shows a loading screen while the scene is built on a
+    // worker thread. G builds a new one with the next seed, and the
+    // current scene keeps rendering until it is ready.
+    let mut generacion = 0;
+    let mut carga = Some(start_build(generacion, stress));
+    let mut diorama: Option<Diorama> = None;
+    let mut fallo: Option<String> = None;
+    let mut cuadro = 0;
+    let mut primer_cuadro = true;
+
+    let mut stereo = false;
+    // Ctrl + click picks through everything under the cursor: the first
+    // click takes the nearest object, and more clicks on the same spot
+    // step back through the ones behind it.
+    let mut rayos_x: Option<((usize, usize), Vec<Occluder>, usize)> = None;
+    let mut clic_previo = false;
+    let resaltado = Material::new(palette::CUTAWAY, 10.0, [0.6, 0.0, 0.0, 0.0], 1.0)
+        .with_emissive_map(TextureHandle::ready(Texture::from_texels(1, 1, vec![palette::CUTAWAY]).expect("1x1 texture")));
+    let mut faro = false;
+    let mut noche = false;
+    let mut settings = RenderSettings {
+        gi_samples: gi_samples_from_args().unwrap_or(if cueva { CAVE_GI_SAMPLES } else { 0 }),
+        ao_samples: ao_samples_from_args(),
+        caustics: caustics_from_args(),
+        contact_shadow_strength: if contact_shadows_from_args() { CONTACT_SHADOW_STRENGTH } else { 0.0 },
+        birds: !no_birds_from_args(),
+        ..RenderSettings::default()
+    };
+    let mut last_frame = Instant::now();
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
-        
+        let elapsed = last_frame.elapsed();
+        if elapsed < frame_delay {
+            std::thread::sleep(frame_delay - elapsed);
+        }
+        let paso = last_frame.elapsed().as_secs_f32().min(MAX_FISH_STEP);
+        last_frame = Instant::now();
+        cuadro += 1;
+
+        if let Some(cargador) = carga.as_mut() {
+            match cargador.poll() {
+                Status::Loading => {}
+                Status::Ready(nuevo) => {
+                    // A regenerated scene keeps the view the user had.
+                    let camara = diorama.take().map(|viejo| viejo.camera).unwrap_or(nuevo.camera);
+                    diorama = Some(Diorama { camera: camara, ..nuevo });
+                    carga = None;
+                    rayos_x = None;
+                    primer_cuadro = true;
+                }
+                Status::Failed(err) => {
+                    fallo = Some(err);
+                    carga = None;
+                }
+            }
+        }
+        if let Some(err) = &fallo {
+            loading::draw_error(&mut framebuffer, err, "R: reintentar   Esc: salir");
+            if window.is_key_pressed(Key::R, KeyRepeat::No) {
+                fallo = None;
+                carga = Some(start_build(generacion, stress));
+            }
+            window
+                .update_with_buffer(&framebuffer.buffer, framebuffer_width, framebuffer_height)
+                .unwrap();
+            continue;
+        }
+        let Some(Diorama {
+            scene,
+            camera,
+            school: cardumen,
+            water_start: inicio_agua,
+            fish_start: inicio_peces,
+            bounds,
+        }) = diorama.as_mut() else {
+            if let Some(cargador) = &carga {
+                loading::draw_progress(&mut framebuffer, cargador.fraction, &cargador.stage, cuadro);
+            }
+            window
+                .update_with_buffer(&framebuffer.buffer, framebuffer_width, framebuffer_height)
+                .unwrap();
+            continue;
+        };
+
+        cardumen.update(paso);
+        cardumen.place(&mut scene.cuboids[*inicio_peces..]);
+
         tiempo += 0.5;  
-        for (i, cubo) in cubos_agua.iter_mut().enumerate() {
+        for (i, cubo) in scene.cubes[*inicio_agua..].iter_mut().enumerate() {
             let desplazamiento = (tiempo + i as f32).sin() * 0.05;  
             cubo.center.y = 0.0 + desplazamiento;  
         }
-    
-        
+
+        let input_time = Instant::now();
+
+        let moving = [Key::Left, Key::Right, Key::Up, Key::Down, Key::W, Key::S]
+            .iter()
+            .any(|key| window.is_key_down(*key));
+        settings.shadow_samples = if moving { MOVING_SHADOW_SAMPLES } else { IDLE_SHADOW_SAMPLES };
+        settings.reflection_samples = if moving { MOVING_REFLECTION_SAMPLES } else { IDLE_REFLECTION_SAMPLES };
+
         if window.is_key_down(Key::Left) {
             camera.orbit(rotation_speed, 0.0); 
         }
@@ -540,32 +2134,1097 @@ fn main() {
         if window.is_key_down(Key::S) {
             camera.zoom(-0.1);
         }
+        if window.is_key_down(Key::Home) {
+            camera.frame_bounds(bounds, FIELD_OF_VIEW, 1.1);
+        }
+        if window.is_key_pressed(Key::E, KeyRepeat::No) {
+            stereo = !stereo;
+        }
+        if window.is_key_pressed(Key::G, KeyRepeat::No) && carga.is_none() {
+            generacion += 1;
+            carga = Some(start_build(generacion, stress));
+        }
+        if window.is_key_down(Key::LeftBracket) {
+            settings.eye_separation = (settings.eye_separation - EYE_SEPARATION_STEP).max(0.0);
+        }
+        if window.is_key_down(Key::RightBracket) {
+            settings.eye_separation = (settings.eye_separation + EYE_SEPARATION_STEP).min(MAX_EYE_SEPARATION);
+        }
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            settings.reflection_probes = !settings.reflection_probes;
+        }
+        if window.is_key_pressed(Key::K, KeyRepeat::No) {
+            settings.contact_shadow_strength = if settings.contact_shadow_strength > 0.0 { 0.0 } else { CONTACT_SHADOW_STRENGTH };
+        }
+        if window.is_key_pressed(Key::C, KeyRepeat::No) {
+            settings.clip_plane = match settings.clip_plane {
+                Some(_) => None,
+                None => {
+                    let toward_camera = Vec3::new(camera.eye.x - bounds.center().x, 0.0, camera.eye.z - bounds.center().z);
+                    Some((bounds.center(), toward_camera.normalize()))
+                }
+            };
+        }
+        if window.is_key_down(Key::LeftShift) {
+            if let (Some((point, normal)), Some((_, scroll))) = (settings.clip_plane, window.get_scroll_wheel()) {
+                settings.clip_plane = Some((point + normal * (scroll * 0.01), normal));
+            }
+        }
+        // Ctrl + number hides or shows a layer; Alt + number does the same
+        // for its shadows only.
+        let ctrl = window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl);
+        let alt = window.is_key_down(Key::LeftAlt) || window.is_key_down(Key::RightAlt);
+        for (bit, (key, depth)) in [(Key::Key1, 1), (Key::Key2, 2), (Key::Key3, 3), (Key::Key4, 4)].into_iter().enumerate() {
+            if !window.is_key_pressed(key, KeyRepeat::No) {
+                continue;
+            }
+            if ctrl {
+                settings.visible_layers ^= 1 << bit;
+            } else if alt {
+                settings.shadow_layers ^= 1 << bit;
+            } else {
+                settings.max_depth = depth;
+            }
+        }
+        let clic = window.get_mouse_down(MouseButton::Left);
+        if clic && !clic_previo && ctrl && !stereo {
+            if let Some((x, y)) = pixel_under_mouse(&window, framebuffer_width, framebuffer_height) {
+                let mismo_lugar = |(px, py): (usize, usize)| px.abs_diff(x) <= PICK_TOLERANCE && py.abs_diff(y) <= PICK_TOLERANCE;
+                rayos_x = match rayos_x.take() {
+                    Some((pixel, hits, index)) if mismo_lugar(pixel) => {
+                        let siguiente = (index + 1) % hits.len();
+                        Some((pixel, hits, siguiente))
+                    }
+                    _ => {
+                        let rayo = Ray::primary(camera.eye, pixel_direction(camera, x, y, framebuffer_width, framebuffer_height))
+                            .with_layers(settings.visible_layers);
+                        let hits: Vec<Occluder> = scene.all_hits(&rayo).into_iter().map(|(_, object)| object).collect();
+                        (!hits.is_empty()).then_some(((x, y), hits, 0))
+                    }
+                };
+                if let Some((_, hits, index)) = &rayos_x {
+                    println!("rayos x: {:?} ({} de {})", hits[*index], index + 1, hits.len());
+                }
+            }
+        }
+        clic_previo = clic;
         if window.is_key_down(Key::D) {
-            is_day = true;
-            skybox.set_day();
-            light.position = Vec3::new(5.0, 5.0, 5.0);
-            light.color = Color::new(255, 255, 255);
-            light.intensity = 1.0;
+            faro = false;
+            noche = false;
+            scene.skybox.set_day();
+            scene.lights = vec![Light::directional(Vec3::new(-1.0, -1.0, -1.0), palette::SUNLIGHT, SUN_INTENSITY)
+                .with_area_radius(SUN_AREA_RADIUS)];
         }
         if window.is_key_down(Key::N) {
-            is_day = false;
-            skybox.set_night();
-            light.position = Vec3::new(1.0, 1.0, 1.0);
-            light.color = Color::new(20, 20, 50);
-            light.intensity = 0.05;
+            faro = false;
+            noche = true;
+            scene.skybox.set_night();
+        }
+        if window.is_key_down(Key::F) {
+            faro = true;
+            noche = false;
+            scene.skybox.set_night();
+        }
+        if noche {
+            scene.lights = night_rig(&camera.eye);
+        }
+        if faro {
+            let angulo = tiempo * 0.1;
+            scene.lights = vec![Light::spot(
+                Vec3::new(0.0, 1.5, 0.0),
+                Vec3::new(angulo.cos(), -0.8, angulo.sin()),
+                PI / 12.0,
+                PI / 7.0,
+                palette::GLOWSTONE,
+                1.5,
+            )
+            .with_radius(FARO_RADIUS)];
+        }
+        scene.skybox.clouds.time = tiempo;
+        scene.skybox.update_birds(tiempo, settings.birds);
+        scene.waves.time = tiempo;
+        if !noche && !faro {
+            // Clouds passing in front of the sun dim it a little.
+            for luz in &mut scene.lights {
+                if let LightKind::Directional { direction } = luz.kind {
+                    luz.intensity = SUN_INTENSITY * scene.skybox.clouds.sun_factor(&-direction);
+                }
+            }
+        }
+        // One probe face per frame keeps the pond's reflection current
+        // without a spike whenever the scene changes.
+        if let Some(mut sonda) = scene.probe.take() {
+            sonda.update_next_face(scene, &probe_settings(&settings));
+            scene.probe = Some(sonda);
         }
     
-        
-        let mut todos_los_cubos = cubes.clone();  
-        todos_los_cubos.extend_from_slice(&cubos_agua);  
-    
-        render(&mut framebuffer, &plane, &todos_los_cubos, &camera, &light, &skybox);
+        let render_time = Instant::now();
+        // The picked object is drawn in the highlight material for this
+        // frame only.
+        let elegido = rayos_x.as_ref().map(|(_, hits, index)| hits[*index]);
+        let original = elegido
+            .and_then(|objeto| scene.material_mut(objeto))
+            .map(|material| std::mem::replace(material, resaltado.clone()));
+        let stats = render(&mut framebuffer, scene, camera, stereo, &settings);
+        if let (Some(objeto), Some(original)) = (elegido, original) {
+            if let Some(material) = scene.material_mut(objeto) {
+                *material = original;
+            }
+        }
+        if primer_cuadro && stress.is_some() {
+            println!("primer cuadro {:.1} ms", render_time.elapsed().as_secs_f32() * 1000.0);
+        }
+        primer_cuadro = false;
     
         window
             .update_with_buffer(&framebuffer.buffer, framebuffer_width, framebuffer_height)
             .unwrap();
-    
-        std::thread::sleep(frame_delay);
+
+        let latency = input_time.elapsed().as_secs_f32() * 1000.0;
+        window.set_title(&format!(
+            "Refractor - latencia {:.1} ms - profundidad {} - capas {:04b} sombras {:04b} - luces con sombra {} / sin sombra {} - cache de sombras {:.0}%{}",
+            latency,
+            settings.max_depth,
+            settings.visible_layers & LAYER_KEY_MASK,
+            settings.shadow_layers & LAYER_KEY_MASK,
+            stats.shadowed_lights,
+            stats.unshadowed_lights,
+            stats.occluder_hit_rate() * 100.0,
+            carga
+                .as_ref()
+                .map(|cargador| format!(" - generando {:.0}% ({})", cargador.fraction * 100.0, cargador.stage))
+                .unwrap_or_default(),
+        ));
     }    
+
+    // Escape during a load stops the worker before the window goes away.
+    if let Some(cargador) = carga {
+        cargador.cancel();
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ground() -> Plane {
+        let material = Material::new(palette::GRASS, 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        Plane::try_new(Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0), material, None).unwrap()
+    }
+
+    fn scene_with(cubes: Vec<Cube>, decorations: Vec<SdfPrimitive>) -> Scene {
+        Scene {
+            plane: ground(),
+            cubes,
+            cuboids: vec![],
+            decorations,
+            solids: vec![],
+            lights: vec![Light::directional(Vec3::new(-1.0, -1.0, -1.0), palette::SUNLIGHT, 1.0)],
+            decals: Decals::default(),
+            probe: None,
+            waves: Waves::default(),
+            skybox: load_skybox(),
+        }
+    }
+
+    fn stone() -> Material {
+        Material::new(palette::STONE, 10.0, [1.0, 0.0, 0.0, 0.0], 1.0)
+    }
+
+    #[test]
+    fn empty_scene_has_empty_bounds() {
+        assert!(scene_with(vec![], vec![]).bounds().is_empty());
+    }
+
+    #[test]
+    fn cuboids_are_framed_and_cast_shadows() {
+        let mut scene = scene_with(vec![], vec![]);
+        scene.cuboids.push(Cuboid::new(Vec3::new(0.3, 0.3, 0.3), Vec3::new(0.2, 0.1, 0.1), stone()));
+        let bounds = scene.bounds();
+        assert!((bounds.min - Vec3::new(0.1, 0.2, 0.2)).magnitude() < 1e-6);
+
+        let toward_sun = Ray::shadow(Vec3::new(0.0, 0.001, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        let (transmittance, occluder) = shadow_transmittance(&toward_sun, &scene);
+        assert_eq!(transmittance, Vec3::zeros());
+        assert_eq!(occluder, Some(Occluder::Cuboid(0)));
+
+        scene.cuboids[0] = scene.cuboids[0].clone().with_layers(layers::layer("props"));
+        let (transmittance, _) = shadow_transmittance(&toward_sun.with_layers(layers::DEFAULT), &scene);
+        assert_eq!(transmittance, Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn single_cube_bounds_match_the_cube() {
+        let scene = scene_with(vec![Cube::new(Vec3::new(0.5, 0.1, -0.2), 0.2, stone())], vec![]);
+        let bounds = scene.bounds();
+        assert!((bounds.min - Vec3::new(0.4, 0.0, -0.3)).magnitude() < 1e-6);
+        assert!((bounds.max - Vec3::new(0.6, 0.2, -0.1)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn ground_plane_is_left_out_of_the_bounds() {
+        let scene = scene_with(vec![], vec![]);
+        assert!(scene.bounds().is_empty());
+
+        let mut camera = Camera::new(Vec3::new(0.0, 3.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+        camera.frame_bounds(&scene.bounds(), FIELD_OF_VIEW, 1.1);
+        assert_eq!(camera.eye, Vec3::new(0.0, 3.0, 5.0));
+        assert_eq!(camera.center, Vec3::zeros());
+    }
+
+    #[test]
+    fn bounds_cover_cubes_and_decorations() {
+        let cube = Cube::new(Vec3::new(-1.0, 0.5, 0.0), 0.2, stone());
+        let pebble = SdfPrimitive::rounded_box(Vec3::new(2.0, 0.1, 0.0), Vec3::new(0.1, 0.1, 0.1), 0.02, stone());
+        let bounds = scene_with(vec![cube], vec![pebble]).bounds();
+        assert!((bounds.min - Vec3::new(-1.1, 0.0, -0.1)).magnitude() < 1e-6);
+        assert!((bounds.max - Vec3::new(2.1, 0.6, 0.1)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn framing_a_single_cube_centers_on_it() {
+        let scene = scene_with(vec![Cube::new(Vec3::new(0.3, 0.2, 0.1), 0.2, stone())], vec![]);
+        let mut camera = Camera::new(Vec3::new(0.0, 3.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+        camera.frame_bounds(&scene.bounds(), FIELD_OF_VIEW, 1.1);
+        assert!((camera.center - Vec3::new(0.3, 0.2, 0.1)).magnitude() < 1e-6);
+        assert!((camera.eye - camera.center).magnitude() > scene.bounds().radius());
+    }
+
+    #[test]
+    fn plane_respects_the_ray_t_range() {
+        let plane = ground();
+        let origin = Vec3::new(0.2, 1.0, 0.3);
+        let down = Vec3::new(0.0, -1.0, 0.0);
+
+        assert!(!plane.ray_intersect(&Ray::shadow(origin, down * 0.5)).is_intersecting);
+        assert!(plane.ray_intersect(&Ray::shadow(origin, down * 2.0)).is_intersecting);
+        assert!(!plane.ray_intersect(&Ray::primary(origin, down).with_t_min(1.5)).is_intersecting);
+
+        let hit = plane.ray_intersect(&Ray::primary(origin, down));
+        assert!((hit.distance - 1.0).abs() < 1e-6);
+    }
+
+    fn clip_at_origin() -> Option<(Vec3, Vec3)> {
+        Some((Vec3::zeros(), Vec3::new(0.0, 0.0, 1.0)))
+    }
+
+    #[test]
+    fn clip_starts_primary_rays_at_the_plane() {
+        let ray = Ray::primary(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        let clipped = clip_primary(ray.clone(), clip_at_origin()).unwrap();
+        assert!((clipped.t_min - 2.0).abs() < 1e-6);
+
+        let unclipped = clip_primary(ray, None).unwrap();
+        assert_eq!(unclipped.t_min, 0.0);
+    }
+
+    #[test]
+    fn clip_follows_the_camera_across_the_plane() {
+        let ray = Ray::primary(Vec3::new(0.0, 0.0, -2.0), Vec3::new(0.0, 0.6, 0.8));
+        let clipped = clip_primary(ray, clip_at_origin()).unwrap();
+        assert!((clipped.t_min - 2.5).abs() < 1e-6);
+        assert!(clipped.point_at(clipped.t_min).z.abs() < 1e-6);
+    }
+
+    #[test]
+    fn rays_that_never_reach_the_plane_see_nothing() {
+        for eye_z in [2.0, -2.0] {
+            let away = Ray::primary(Vec3::new(0.0, 0.0, eye_z), Vec3::new(0.0, 0.0, eye_z.signum()));
+            assert!(clip_primary(away, clip_at_origin()).is_none());
+
+            let parallel = Ray::primary(Vec3::new(0.0, 0.0, eye_z), Vec3::new(1.0, 0.0, 0.0));
+            assert!(clip_primary(parallel, clip_at_origin()).is_none());
+        }
+    }
+
+    fn unlit_scene(cubes: Vec<Cube>, light: Light) -> Scene {
+        let mut scene = scene_with(cubes, vec![]);
+        scene.lights = vec![light];
+        scene.skybox.ambient_factor = 0.0;
+        scene
+    }
+
+    fn trace(scene: &Scene, origin: Vec3, direction: Vec3) -> Color {
+        cast_ray(&Ray::primary(origin, direction.normalize()), scene, 1.0, &RenderSettings::default(), &mut RenderStats::default(), &mut OccluderCache::default())
+    }
+
+    #[test]
+    fn light_behind_a_face_leaves_no_highlight() {
+        let shiny = Material::new(palette::STONE, 1.0, [0.0, 1.0, 0.0, 0.0], 1.0);
+        let eye = Vec3::new(0.8, 0.5, 0.6);
+        let face_point = Vec3::new(0.0, 0.5, 0.1);
+        let view = (face_point - eye).normalize();
+        let hit = Intersect::new(face_point, Vec3::new(0.0, 0.0, 1.0), (face_point - eye).magnitude(), shiny);
+
+        let from_behind = Light::directional(-view, palette::SUNLIGHT, 1.0);
+        let scene = unlit_scene(vec![], from_behind);
+        let color = shade(&Ray::primary(eye, view), &hit, &scene, 1.0, &RenderSettings::default(), &mut RenderStats::default(), &mut OccluderCache::default());
+        assert_eq!(color, Color::black());
+    }
+
+    #[test]
+    fn lit_face_keeps_its_highlight() {
+        let shiny = Material::new(palette::STONE, 20.0, [0.0, 1.0, 0.0, 0.0], 1.0);
+        let cube = Cube::new(Vec3::new(0.0, 0.5, 0.0), 0.2, shiny);
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let in_front = Light::directional(forward, palette::SUNLIGHT, 1.0);
+        let color = trace(&unlit_scene(vec![cube], in_front), Vec3::new(0.0, 0.5, 1.0), forward);
+        assert!(color.luminance() > 0.9);
+    }
+
+    fn glass() -> Material {
+        Material::new(palette::SUNLIGHT, 10.0, [0.1, 0.0, 0.0, 0.9], 1.5)
+    }
+
+    #[test]
+    fn touching_glass_cubes_show_no_interface() {
+        let light = || Light::directional(Vec3::new(1.0, 0.0, 0.0), palette::SUNLIGHT, 1.0);
+        let pair = unlit_scene(
+            vec![
+                Cube::new(Vec3::new(0.0, 0.5, 0.0), 0.2, glass()),
+                Cube::new(Vec3::new(0.2, 0.5, 0.0), 0.2, glass()),
+            ],
+            light(),
+        );
+        let block = unlit_scene(vec![Cube::new(Vec3::new(0.1, 0.5, 0.0), 0.4, glass())], light());
+
+        for (dy, dz) in [(0.0, 0.0), (0.05, -0.03), (-0.08, 0.06)] {
+            let origin = Vec3::new(-1.0, 0.5 + dy, dz);
+            let direction = Vec3::new(1.0, 0.02, -0.01);
+            assert_eq!(trace(&pair, origin, direction), trace(&block, origin, direction));
+        }
+    }
+
+    #[test]
+    fn continued_ray_does_not_inherit_the_clip_distance() {
+        let light = Light::directional(Vec3::new(1.0, 0.0, 0.0), palette::SUNLIGHT, 1.0);
+        let mut scene = unlit_scene(
+            vec![
+                Cube::new(Vec3::new(0.0, 0.5, 0.0), 0.2, glass()),
+                Cube::new(Vec3::new(0.2, 0.5, 0.0), 0.2, glass()),
+            ],
+            light,
+        );
+        scene.skybox.ambient_factor = DEFAULT_AMBIENT_FACTOR;
+
+        let eye = Vec3::new(-1.0, 0.5, 0.0);
+        let direction = Vec3::new(1.0, 0.0, 0.0);
+        let clipped = Ray::primary(eye, direction).with_t_min(1.05);
+        let from_cut = Ray::primary(clipped.point_at(clipped.t_min), direction);
+        let settings = RenderSettings::default();
+        let mut stats = RenderStats::default();
+        assert_eq!(cast_ray(&clipped, &scene, 1.0, &settings, &mut stats, &mut OccluderCache::default()), cast_ray(&from_cut, &scene, 1.0, &settings, &mut stats, &mut OccluderCache::default()));
+    }
+
+    fn top_face_colors(scene: &Scene, center: Vec3, size: f32, eye_height: f32) -> Vec<Color> {
+        let offsets = [-0.4, -0.1, 0.0, 0.2, 0.35];
+        offsets
+            .iter()
+            .flat_map(|&dx| offsets.iter().map(move |&dz| (dx, dz)))
+            .map(|(dx, dz)| {
+                let origin = center + Vec3::new(dx * size, eye_height, dz * size);
+                trace(scene, origin, Vec3::new(0.0, -1.0, 0.0))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tiny_cube_offsets_stay_within_the_cube() {
+        let tiny = Cube::new(Vec3::new(0.3, 0.2, 0.1), 0.001, stone());
+        let eye = Vec3::new(0.3, 5.0, 0.1);
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let hit = tiny.ray_intersect(&Ray::primary(eye, down));
+        assert!(hit.is_intersecting);
+
+        let inside = offset_origin(&hit, &down);
+        assert!(tiny.aabb().contains(&inside));
+        let outside = offset_origin(&hit, &-down);
+        assert!(!tiny.aabb().contains(&outside));
+        assert!((outside - hit.point).magnitude() < 0.001);
+    }
+
+    #[test]
+    fn tiny_and_huge_cube_tops_are_evenly_lit() {
+        let tiny = Cube::new(Vec3::new(0.3, 0.2, 0.1), 0.001, stone());
+        let huge = Cube::new(Vec3::new(200.0, 50.0, 0.0), 100.0, stone());
+        let sun = Light::directional(Vec3::new(-0.3, -1.0, -0.2), palette::SUNLIGHT, 1.0);
+        let targets = [(tiny.center, tiny.size, 1.0), (huge.center, huge.size, 60.0)];
+        let scene = unlit_scene(vec![tiny, huge], sun);
+
+        for (center, size, eye_height) in targets {
+            let colors = top_face_colors(&scene, center, size, eye_height);
+            assert!(colors[0].to_hex() != 0, "top face should be lit");
+            assert!(colors.iter().all(|color| *color == colors[0]), "acne on cube of size {}", size);
+        }
+    }
+
+    fn murky_water() -> Material {
+        Material::new(palette::WATER, 10.0, [0.2, 0.0, 0.0, 0.5], 1.33).with_absorption(Vec3::new(4.0, 1.5, 1.2))
+    }
+
+    fn light_through(cubes: Vec<Cube>) -> Vec3 {
+        let scene = scene_with(cubes, vec![]);
+        let up = Ray::shadow(Vec3::new(0.0, 0.01, 0.0), Vec3::new(0.0, 10.0, 0.0));
+        shadow_transmittance(&up, &scene).0
+    }
+
+    #[test]
+    fn opaque_cubes_block_shadow_rays() {
+        assert_eq!(light_through(vec![Cube::new(Vec3::new(0.0, 0.5, 0.0), 0.2, stone())]), Vec3::zeros());
+        assert_eq!(light_through(vec![]), Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn glass_lets_its_transparency_through() {
+        let through = light_through(vec![Cube::new(Vec3::new(0.0, 0.5, 0.0), 0.2, glass())]);
+        assert!((through - Vec3::new(0.9, 0.9, 0.9)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn water_absorbs_along_the_shadow_ray() {
+        let water = murky_water();
+        let expected = (-water.absorption * 0.2).map(f32::exp) * 0.5;
+        let through = light_through(vec![Cube::new(Vec3::new(0.0, 0.5, 0.0), 0.2, water)]);
+        assert!((through - expected).magnitude() < 1e-4, "{:?} vs {:?}", through, expected);
+    }
+
+    #[test]
+    fn stacked_water_counts_as_one_medium() {
+        let water = murky_water();
+        let expected = (-water.absorption * 0.4).map(f32::exp) * 0.5;
+        let column = vec![
+            Cube::new(Vec3::new(0.0, 0.5, 0.0), 0.2, water.clone()),
+            Cube::new(Vec3::new(0.0, 0.7, 0.0), 0.2, water),
+        ];
+        let through = light_through(column);
+        assert!((through - expected).magnitude() < 1e-4, "{:?} vs {:?}", through, expected);
+    }
+
+    #[test]
+    fn total_internal_reflection_stays_in_the_medium() {
+        let water = murky_water();
+        let inside = Ray::primary(Vec3::zeros(), Vec3::new(0.95, 0.3122, 0.0).normalize()).with_medium(Some(water.clone()));
+        let surface = Intersect::exit(Vec3::new(0.3, 0.1, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.3, water.clone());
+        let mirror = reflect(&inside.direction, &surface.normal).normalize();
+
+        let (direction, medium) = transmitted_direction(&inside, &surface, &mirror);
+        assert_eq!(direction, mirror);
+        assert!(medium == Some(water.clone()));
+
+        let steep = Ray::primary(Vec3::zeros(), Vec3::new(0.1, 1.0, 0.0).normalize()).with_medium(Some(water));
+        let (_, medium) = transmitted_direction(&steep, &surface, &mirror);
+        assert!(medium.is_none());
+    }
+
+    #[test]
+    fn plane_rejects_degenerate_input() {
+        let material = stone();
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        assert!(Plane::try_new(Vec3::new(f32::NAN, 0.0, 0.0), up, material.clone(), None).is_err());
+        assert!(Plane::try_new(Vec3::new(0.0, f32::INFINITY, 0.0), up, material.clone(), None).is_err());
+        assert!(Plane::try_new(Vec3::zeros(), Vec3::zeros(), material.clone(), None).is_err());
+        assert!(Plane::try_new(Vec3::zeros(), Vec3::new(1e-9, 0.0, 0.0), material.clone(), None).is_err());
+        assert!(Plane::try_new(Vec3::zeros(), Vec3::new(0.0, f32::NAN, 0.0), material, None).is_err());
+    }
+
+    #[test]
+    fn plane_normal_is_normalized() {
+        let plane = Plane::try_new(Vec3::zeros(), Vec3::new(0.0, 3.0, 0.0), stone(), None).unwrap();
+        assert_eq!(plane.normal, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    fn stereo_halves(eye_separation: f32) -> (Vec<u32>, Vec<u32>) {
+        let scene = scene_with(vec![Cube::new(Vec3::new(0.0, 0.1, 0.0), 0.2, stone())], vec![]);
+        let camera = Camera::new(Vec3::new(0.0, 0.5, 1.0), Vec3::new(0.0, 0.1, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let settings = RenderSettings { eye_separation, ..RenderSettings::default() };
+        let mut framebuffer = Framebuffer::new(16, 8);
+        render(&mut framebuffer, &scene, &camera, true, &settings);
+
+        let rows = framebuffer.buffer.chunks(framebuffer.width);
+        let left = rows.clone().flat_map(|row| row[..8].to_vec()).collect();
+        let right = rows.flat_map(|row| row[8..].to_vec()).collect();
+        (left, right)
+    }
+
+    #[test]
+    fn zero_eye_separation_renders_matching_halves() {
+        let (left, right) = stereo_halves(0.0);
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn eye_separation_comes_from_the_settings() {
+        let (left, right) = stereo_halves(0.3);
+        assert_ne!(left, right);
+    }
+
+    fn lit_from_above(material: Material, color: Color, intensity: f32) -> Color {
+        let eye = Vec3::new(0.3, 2.0, 0.4);
+        let point = Vec3::new(0.0, 1.0, 0.0);
+        let hit = Intersect::new(point, Vec3::new(0.0, 1.0, 0.0), (point - eye).magnitude(), material);
+        let scene = unlit_scene(vec![], Light::directional(Vec3::new(0.0, -1.0, 0.0), color, intensity));
+        shade(&Ray::primary(eye, (point - eye).normalize()), &hit, &scene, 1.0, &RenderSettings::default(), &mut RenderStats::default(), &mut OccluderCache::default())
+    }
+
+    // Grey ground in the dark next to a glowing red cube; only bounced
+    // light can reach the spot the camera looks at.
+    fn beside_a_red_glow(gi_samples: u32) -> Color {
+        let glow = Texture::from_texels(1, 1, vec![Color::new(255, 0, 0)]).unwrap();
+        let red = Material::black().with_emissive_map(TextureHandle::ready(glow));
+        let mut scene = unlit_scene(vec![Cube::new(Vec3::new(0.15, 0.1, 0.0), 0.2, red)], Light::directional(Vec3::new(0.0, 1.0, 0.0), palette::SUNLIGHT, 1.0));
+        scene.plane.material = Material::new(Color::new(200, 200, 200), 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        let settings = RenderSettings { gi_samples, ..RenderSettings::default() };
+        let eye = Vec3::new(-0.2, 1.0, 0.0);
+        let ray = Ray::primary(eye, (Vec3::zeros() - eye).normalize());
+        cast_ray(&ray, &scene, 1.0, &settings, &mut RenderStats::default(), &mut OccluderCache::default())
+    }
+
+    fn under_the_pond(caustics: bool, time: f32, x: f32) -> Color {
+        let water = Material::new(palette::WATER, 50.0, [0.2, 0.2, 0.1, 0.5], 1.33);
+        let mut scene = unlit_scene(vec![Cube::new(Vec3::new(0.0, 0.1, 0.0), 0.2, water)], Light::directional(Vec3::new(0.0, -1.0, 0.0), palette::SUNLIGHT, 1.0));
+        scene.waves.time = time;
+        let settings = RenderSettings { caustics, ..RenderSettings::default() };
+        let hit = Intersect::new(Vec3::new(x, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 1.0, stone());
+        let ray = Ray::primary(Vec3::new(x, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        shade(&ray, &hit, &scene, 1.0, &settings, &mut RenderStats::default(), &mut OccluderCache::default())
+    }
+
+    #[test]
+    fn caustics_follow_the_waves_under_water_only() {
+        let plain = under_the_pond(false, 0.0, 0.02);
+        let now = under_the_pond(true, 0.0, 0.02);
+        let later = under_the_pond(true, 2.0, 0.02);
+        assert_ne!(now, plain);
+        assert_ne!(now, later);
+
+        let bands: Vec<f32> = (0..20).map(|i| under_the_pond(true, 0.0, -0.09 + i as f32 * 0.009).luminance()).collect();
+        let plain = plain.luminance();
+        assert!(bands.iter().any(|&band| band > plain * 1.1) && bands.iter().any(|&band| band < plain * 0.9));
+
+        // Beside the water nothing changes.
+        assert_eq!(under_the_pond(true, 0.0, 0.5), under_the_pond(false, 0.0, 0.5));
+    }
+
+    #[test]
+    fn bounce_light_picks_up_nearby_color() {
+        assert_eq!(beside_a_red_glow(0), Color::black());
+        let [r, g, b] = beside_a_red_glow(16).to_unit_rgb();
+        assert!(r > 0.02, "no bounce light: {:?}", [r, g, b]);
+        assert!(g < r * 0.05 && b < r * 0.05);
+    }
+
+    #[test]
+    fn hemisphere_directions_stay_above_the_surface() {
+        let normal = Vec3::new(0.3, 0.9, -0.2).normalize();
+        for i in 0..64 {
+            let radius = ((i as f32 + 0.5) / 64.0).sqrt();
+            let theta = i as f32 * GOLDEN_ANGLE;
+            let direction = hemisphere_direction(&normal, radius * math::cos(theta), radius * math::sin(theta));
+            assert!((direction.magnitude() - 1.0).abs() < 1e-4);
+            assert!(direction.dot(&normal) > 0.0);
+        }
+        assert!((hemisphere_direction(&normal, 0.0, 0.0) - normal).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn light_intensity_scales_diffuse_and_specular() {
+        let matte = Material::new(palette::STONE, 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        let full = lit_from_above(matte.clone(), palette::SUNLIGHT, 1.0).to_unit_rgb();
+        let half = lit_from_above(matte, palette::SUNLIGHT, 0.5).to_unit_rgb();
+        assert!(full[0] > 0.0);
+        assert!((half[0] - full[0] * 0.5).abs() < 2.0 / 255.0);
+
+        let glossy = Material::new(Color::black(), 2.0, [0.0, 1.0, 0.0, 0.0], 1.0);
+        let full = lit_from_above(glossy.clone(), palette::SUNLIGHT, 1.0).to_unit_rgb();
+        let half = lit_from_above(glossy, palette::SUNLIGHT, 0.5).to_unit_rgb();
+        assert!(full[0] > 0.0);
+        assert!((half[0] - full[0] * 0.5).abs() < 2.0 / 255.0);
+    }
+
+    #[test]
+    fn bright_lights_saturate_instead_of_wrapping() {
+        let matte = Material::new(palette::SUNLIGHT, 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        // Shading keeps the full value; the pixel written out is clipped.
+        let lit = lit_from_above(matte, palette::SUNLIGHT, 4.0);
+        assert!(lit.to_unit_rgb()[0] > 1.0);
+        assert_eq!(lit.to_hex(), palette::SUNLIGHT.to_hex());
+    }
+
+    #[test]
+    fn colored_lights_tint_diffuse_surfaces() {
+        let white = Material::new(palette::SUNLIGHT, 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        let [r, g, b] = lit_from_above(white.clone(), Color::new(255, 0, 0), 1.0).to_unit_rgb();
+        assert!(r > 0.5);
+        assert_eq!((g, b), (0.0, 0.0));
+
+        let moonlit = lit_from_above(white, palette::MOONLIGHT, 1.0).to_unit_rgb();
+        assert!(moonlit[2] > moonlit[0], "moonlight should read blue");
+    }
+
+    #[test]
+    fn checked_materials_only_change_when_asked() {
+        let hot = Material::new(palette::STONE, 10.0, [0.3, 0.5, 0.1, 0.6], 1.0);
+        assert_eq!(checked_material("test", hot.clone(), false).albedo, hot.albedo);
+        assert!((checked_material("test", hot, true).energy() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn over_unity_mirrors_do_not_add_energy() {
+        let mirror = Material::new(Color::black(), 10.0, [0.0, 0.0, 1.5, 0.0], 1.0);
+        let eye = Vec3::new(0.0, 2.0, 1.0);
+        let point = Vec3::new(0.0, 1.0, 0.0);
+        let view = (point - eye).normalize();
+        let hit = Intersect::new(point, Vec3::new(0.0, 1.0, 0.0), (point - eye).magnitude(), mirror);
+        let scene = unlit_scene(vec![], Light::directional(Vec3::new(0.0, -1.0, 0.0), Color::black(), 0.0));
+
+        let color = shade(&Ray::primary(eye, view), &hit, &scene, 1.0, &RenderSettings::default(), &mut RenderStats::default(), &mut OccluderCache::default());
+        let sky = scene.skybox.sample(reflect(&view, &hit.normal));
+        assert_eq!(color, sky);
+    }
+
+    #[test]
+    fn ground_uv_follows_world_position_in_tiles() {
+        let plane = ground().with_tile_size(0.2);
+        let hit = plane.ray_intersect(&Ray::primary(Vec3::new(0.3, 1.0, -0.5), Vec3::new(0.0, -1.0, 0.0)));
+        assert!((hit.uv.0 - 1.5).abs() < 1e-5);
+        assert!((hit.uv.1 + 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ground_texture_repeats_every_tile() {
+        let checker = Texture::from_texels(2, 2, vec![palette::GRASS, palette::DIRT, palette::DIRT, palette::GRASS]).unwrap();
+        let material = stone().with_loaded_texture(checker);
+        let plane = Plane::try_new(Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0), material, None)
+            .unwrap()
+            .with_tile_size(0.25);
+        let down = Vec3::new(0.0, -1.0, 0.0);
+
+        for (x, z) in [(-0.6, 0.1), (0.13, -0.37), (0.0, 0.0)] {
+            let here = plane.ray_intersect(&Ray::primary(Vec3::new(x, 1.0, z), down));
+            let next = plane.ray_intersect(&Ray::primary(Vec3::new(x + 0.25, 1.0, z + 0.25), down));
+            assert!((next.uv.0 - here.uv.0 - 1.0).abs() < 1e-5);
+            assert!((next.uv.1 - here.uv.1 - 1.0).abs() < 1e-5);
+            assert_eq!(
+                here.material.diffuse_at(&here.point, here.uv),
+                next.material.diffuse_at(&next.point, next.uv),
+            );
+        }
+    }
+
+    fn under_a_roof(light: Light) -> (Color, RenderStats) {
+        let roof = Cube::new(Vec3::new(0.0, 0.6, 0.0), 0.2, stone());
+        let scene = unlit_scene(vec![roof], light);
+        let mut stats = RenderStats::default();
+        let color = cast_ray(&Ray::primary(Vec3::new(0.2, 0.2, 0.0), Vec3::new(-1.0, -1.0, 0.0).normalize()), &scene, 1.0, &RenderSettings::default(), &mut stats, &mut OccluderCache::default());
+        (color, stats)
+    }
+
+    #[test]
+    fn fill_lights_ignore_occluders() {
+        let above = Vec3::new(0.0, 1.0, 0.0);
+        let (shadowed, shadowed_stats) = under_a_roof(Light::fill(above, palette::SUNLIGHT, 1.0).with_shadows(true));
+        let (filled, filled_stats) = under_a_roof(Light::fill(above, palette::SUNLIGHT, 1.0));
+
+        assert_eq!(shadowed, Color::black());
+        assert_ne!(filled, Color::black());
+        assert_eq!(shadowed_stats, RenderStats { shadowed_lights: 1, unshadowed_lights: 0, shadow_queries: 1, occluded_queries: 1, occluder_cache_hits: 0 });
+        assert_eq!(filled_stats, RenderStats { shadowed_lights: 0, unshadowed_lights: 1, shadow_queries: 0, occluded_queries: 0, occluder_cache_hits: 0 });
+    }
+
+    #[test]
+    fn night_rig_shadows_the_moon_and_the_nearest_torches() {
+        let eye = Vec3::new(0.0, 0.5, 3.0);
+        let rig = night_rig(&eye);
+        assert_eq!(rig.len(), 1 + TORCH_POSITIONS.len());
+        assert!(matches!(rig[0].kind, crate::light::LightKind::Directional { .. }));
+
+        let shadowed: Vec<&Light> = rig[1..].iter().filter(|light| light.casts_shadows).collect();
+        assert_eq!(shadowed.len(), SHADOWED_TORCHES);
+        let distance = |light: &Light| light.direction_from(&eye).1;
+        let nearest_fill = rig[1..].iter().filter(|light| !light.casts_shadows).map(distance).fold(f32::INFINITY, f32::min);
+        assert!(shadowed.iter().all(|light| distance(light) <= nearest_fill));
+    }
+
+    #[test]
+    fn occluder_cache_matches_the_full_query() {
+        // A row of points walking out from under a roof, past a glass block.
+        let rays: Vec<Ray> = (0..20)
+            .map(|i| Ray::shadow(Vec3::new(-0.5 + i as f32 * 0.05, 0.01, 0.0), Vec3::new(0.0, 2.0, 0.0)))
+            .collect();
+        let roof = Cube::new(Vec3::new(0.0, 0.6, 0.0), 0.4, stone());
+        let scene = scene_with(vec![roof, Cube::new(Vec3::new(0.4, 0.3, 0.0), 0.1, glass())], vec![]);
+        let mut cached = None;
+        let mut stats = RenderStats::default();
+
+        for ray in &rays {
+            let expected = shadow_transmittance(ray, &scene).0;
+            assert_eq!(cached_shadow_transmittance(ray, &scene, &mut cached, &mut stats), expected);
+        }
+        assert_eq!(stats.shadow_queries, rays.len() as u64);
+        assert!(stats.occluder_cache_hits > 0);
+        assert!(stats.occluder_cache_hits < stats.occluded_queries);
+        assert!(stats.occluded_queries < stats.shadow_queries);
+    }
+
+    #[test]
+    fn stale_occluders_fall_through_to_the_full_query() {
+        let scene = scene_with(vec![Cube::new(Vec3::new(2.0, 0.6, 0.0), 0.4, stone()), Cube::new(Vec3::new(0.0, 0.6, 0.0), 0.4, stone())], vec![]);
+        let up = Ray::shadow(Vec3::new(0.0, 0.01, 0.0), Vec3::new(0.0, 2.0, 0.0));
+        let mut cached = Some(Occluder::Cube(0));
+        let mut stats = RenderStats::default();
+
+        assert_eq!(cached_shadow_transmittance(&up, &scene, &mut cached, &mut stats), Vec3::zeros());
+        assert_eq!(stats.occluder_cache_hits, 0);
+        assert_eq!(cached, Some(Occluder::Cube(1)));
+    }
+
+    #[test]
+    fn see_through_objects_never_short_circuit() {
+        let scene = scene_with(vec![Cube::new(Vec3::new(0.0, 0.6, 0.0), 0.4, glass())], vec![]);
+        let up = Ray::shadow(Vec3::new(0.0, 0.01, 0.0), Vec3::new(0.0, 2.0, 0.0));
+        let mut cached = Some(Occluder::Cube(0));
+        let mut stats = RenderStats::default();
+
+        assert_ne!(cached_shadow_transmittance(&up, &scene, &mut cached, &mut stats), Vec3::zeros());
+        assert_eq!(cached, None);
+    }
+
+    fn look_down_through_roof(settings: &RenderSettings) -> Color {
+        let roof = Cube::new(Vec3::new(0.0, 0.6, 0.0), 0.2, stone()).with_layers(layers::layer("trees"));
+        let scene = unlit_scene(vec![roof], Light::directional(Vec3::new(0.0, -1.0, 0.0), palette::SUNLIGHT, 1.0));
+        let ray = Ray::primary(Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).with_layers(settings.visible_layers);
+        cast_ray(&ray, &scene, 1.0, settings, &mut RenderStats::default(), &mut OccluderCache::default())
+    }
+
+    #[test]
+    fn hidden_layers_can_keep_their_shadows() {
+        let trees = layers::layer("trees");
+        let shown = look_down_through_roof(&RenderSettings::default());
+        let hidden = look_down_through_roof(&RenderSettings { visible_layers: !trees, ..Default::default() });
+        let no_shadow = look_down_through_roof(&RenderSettings { visible_layers: !trees, shadow_layers: !trees, ..Default::default() });
+
+        assert_ne!(shown, hidden, "the roof should be hidden from the camera");
+        assert_eq!(hidden, Color::black(), "the hidden roof still shades the ground");
+        assert_ne!(no_shadow, Color::black());
+    }
+
+    #[test]
+    fn reflection_mask_hides_objects_from_mirrors() {
+        let mirror = Material::new(Color::black(), 10.0, [0.0, 0.0, 1.0, 0.0], 1.0);
+        let post = Cube::new(Vec3::new(0.0, 0.5, 0.0), 0.4, stone()).with_layers(layers::layer("props"));
+        let scene = unlit_scene(vec![post, Cube::new(Vec3::new(0.0, 0.5, 1.0), 0.2, mirror)], Light::directional(Vec3::new(0.0, -1.0, 0.0), palette::SUNLIGHT, 1.0));
+        let toward_mirror = Ray::primary(Vec3::new(0.0, 0.5, 0.5), Vec3::new(0.0, 0.0, 1.0));
+        let seen = |settings: &RenderSettings| cast_ray(&toward_mirror, &scene, 1.0, settings, &mut RenderStats::default(), &mut OccluderCache::default());
+
+        let with_post = seen(&RenderSettings::default());
+        let without_post = seen(&RenderSettings { reflection_layers: !layers::layer("props"), ..Default::default() });
+        assert_ne!(with_post, without_post);
+        assert_eq!(without_post, scene.skybox.sample(Vec3::new(0.0, 0.0, -1.0)));
+    }
+
+    #[test]
+    fn reflect_keeps_length_and_mirrors_across_the_normal() {
+        let normals = [
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.3, -0.8, 0.5).normalize(),
+        ];
+        let steps = [-1.0, -0.5, 0.0, 0.5, 1.0];
+        for normal in normals {
+            for x in steps {
+                for y in steps {
+                    for z in steps {
+                        let incident = Vec3::new(x, y, z);
+                        let reflected = reflect(&incident, &normal);
+                        assert!((reflected.magnitude() - incident.magnitude()).abs() < 1e-5);
+                        assert!((reflected.dot(&normal) + incident.dot(&normal)).abs() < 1e-5);
+                        assert!((reflected - incident).cross(&normal).magnitude() < 1e-5);
+                        assert!((reflect(&reflected, &normal) - incident).magnitude() < 1e-5);
+                    }
+                }
+            }
+        }
+    }
+
+    fn shows_placeholder(framebuffer: &Framebuffer) -> bool {
+        framebuffer.buffer.iter().any(|&pixel| {
+            let (r, g, b) = ((pixel >> 16) & 0xff, (pixel >> 8) & 0xff, pixel & 0xff);
+            r > 60 && b > 60 && g * 3 < r.min(b)
+        })
+    }
+
+    #[test]
+    fn bad_texture_paths_keep_rendering_the_placeholder() {
+        let corrupt = std::env::temp_dir().join(format!("main_test_{}_corrupt.png", std::process::id()));
+        std::fs::write(&corrupt, b"not an image").unwrap();
+
+        let mut textures = TextureManager::default();
+        let missing = textures.load("assets/no_such_texture.png", face_texture);
+        let broken = textures.load(&corrupt.to_string_lossy(), face_texture);
+        let scene = scene_with(
+            vec![
+                Cube::new(Vec3::new(-0.15, 0.1, 0.0), 0.2, stone().with_texture(missing)),
+                Cube::new(Vec3::new(0.15, 0.1, 0.0), 0.2, stone().with_texture(broken)),
+            ],
+            vec![],
+        );
+        let camera = Camera::new(Vec3::new(0.0, 0.1, 0.6), Vec3::new(0.0, 0.1, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        // One frame while the loaders may still be running, then more once
+        // they have given up.
+        for frame in 0..3 {
+            if frame == 1 {
+                textures.wait();
+            }
+            let mut framebuffer = Framebuffer::new(32, 24);
+            render(&mut framebuffer, &scene, &camera, false, &RenderSettings::default());
+            assert!(shows_placeholder(&framebuffer), "frame {} lost the placeholder", frame);
+        }
+        std::fs::remove_file(corrupt).unwrap();
+    }
+
+    // Unlit ground with no ambient, so only the sky specular term shows.
+    fn sky_lit_ground(strength: f32, cubes: Vec<Cube>) -> Scene {
+        let mut scene = scene_with(cubes, vec![]);
+        scene.lights.clear();
+        scene.skybox.ambient_factor = 0.0;
+        scene.plane.material = scene.plane.material.clone().with_sky_specular(strength);
+        scene
+    }
+
+    #[test]
+    fn sky_specular_is_stronger_at_grazing_angles() {
+        let grazing = (Vec3::new(0.0, 0.1, 0.9), Vec3::new(0.0, -0.1, -0.4));
+        let steep = (Vec3::new(0.0, 1.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+        let gain = |(origin, direction): (Vec3, Vec3)| {
+            trace(&sky_lit_ground(0.5, vec![]), origin, direction).luminance()
+                - trace(&sky_lit_ground(0.0, vec![]), origin, direction).luminance()
+        };
+
+        assert!(gain(steep) > 0.0);
+        assert!(gain(grazing) > 3.0 * gain(steep), "grazing {} vs steep {}", gain(grazing), gain(steep));
+    }
+
+    #[test]
+    fn covered_ground_gets_no_sky_specular() {
+        // The cube sits where the grazing view's mirror direction leaves the
+        // ground, clear of the view ray itself.
+        let cover = || vec![Cube::new(Vec3::new(0.0, 0.1, 0.1), 0.1, stone())];
+        let (origin, direction) = (Vec3::new(0.0, 0.1, 0.9), Vec3::new(0.0, -0.1, -0.4));
+
+        let covered = trace(&sky_lit_ground(0.5, cover()), origin, direction);
+        assert_eq!(covered, trace(&sky_lit_ground(0.0, cover()), origin, direction));
+        assert_ne!(covered, trace(&sky_lit_ground(0.5, vec![]), origin, direction));
+    }
+
+    fn red_pixels(framebuffer: &Framebuffer, columns: std::ops::Range<usize>) -> usize {
+        (0..framebuffer.height)
+            .flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .filter(|&(x, y)| {
+                let pixel = framebuffer.buffer[y * framebuffer.width + x];
+                let (r, g, b) = ((pixel >> 16) & 0xff, (pixel >> 8) & 0xff, pixel & 0xff);
+                r > 60 && r > 2 * (g + b)
+            })
+            .count()
+    }
+
+    #[test]
+    fn decals_mark_one_cube_even_when_the_material_is_shared() {
+        let mut scene = scene_with(
+            vec![
+                Cube::new(Vec3::new(-0.15, 0.1, 0.0), 0.2, stone()),
+                Cube::new(Vec3::new(0.15, 0.1, 0.0), 0.2, stone()),
+            ],
+            vec![],
+        );
+        let red = texture::TextureHandle::ready(Texture::from_texels(1, 1, vec![Color::new(255, 0, 0)]).unwrap());
+        scene.decals.add(Decal::new(red, 0, FACE_FRONT, (0.25, 0.25), (0.75, 0.75))).unwrap();
+        let camera = Camera::new(Vec3::new(0.0, 0.1, 0.6), Vec3::new(0.0, 0.1, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        let mut framebuffer = Framebuffer::new(32, 24);
+        render(&mut framebuffer, &scene, &camera, false, &RenderSettings::default());
+        assert!(red_pixels(&framebuffer, 0..16) > 0);
+        assert_eq!(red_pixels(&framebuffer, 16..32), 0);
+    }
+
+    #[test]
+    fn srgb_gray_texture_matches_linear_gray_on_screen() {
+        // Byte 188 is 50% linear gray once decoded.
+        let texture = Texture::from_texels(1, 1, vec![Color::new(188, 188, 188)]).unwrap();
+        let textured = Material::new(palette::SUNLIGHT, 10.0, [1.0, 0.0, 0.0, 0.0], 1.0).with_loaded_texture(texture);
+        let constant = Material::new(Color::from_linear(0.5, 0.5, 0.5), 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        let scene = unlit_scene(
+            vec![
+                Cube::new(Vec3::new(-0.15, 0.1, 0.0), 0.2, textured),
+                Cube::new(Vec3::new(0.15, 0.1, 0.0), 0.2, constant),
+            ],
+            Light::directional(Vec3::new(0.0, 0.0, -1.0), palette::SUNLIGHT, 0.8),
+        );
+        let camera = Camera::new(Vec3::new(0.0, 0.1, 0.6), Vec3::new(0.0, 0.1, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        let mut framebuffer = Framebuffer::new(32, 24);
+        render(&mut framebuffer, &scene, &camera, false, &RenderSettings::default());
+        let pixel = |x: usize| framebuffer.buffer[12 * framebuffer.width + x] & 0xff;
+        let (left, right) = (pixel(8), pixel(24));
+        assert!(left > 100, "the face should be lit, got {}", left);
+        assert!(left.abs_diff(right) <= 1, "textured {} vs constant {}", left, right);
+    }
+
+    #[test]
+    fn view_projection_finds_the_pixel_a_ray_came_from() {
+        let camera = Camera::new(Vec3::new(0.3, 0.4, 0.8), Vec3::new(0.0, 0.1, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let (width, height) = (40, 30);
+        for &(x, y) in &[(0, 0), (13, 7), (39, 29), (20, 15)] {
+            let point = camera.eye + pixel_direction(&camera, x, y, width, height) * 0.7;
+            let (found_x, found_y, depth) = project_to_view(&point, &camera, width, height).unwrap();
+            assert_eq!((found_x, found_y), (x, y));
+            assert!(depth > 0.0 && depth < 0.7);
+        }
+        let behind = camera.eye * 2.0 - camera.center;
+        assert!(project_to_view(&behind, &camera, width, height).is_none());
+    }
+
+    #[test]
+    fn contact_shadows_darken_next_to_occluders_only() {
+        let scene = scene_with(vec![Cube::new(Vec3::new(0.0, 0.1, 0.0), 0.2, stone())], vec![]);
+        // Looking at the side facing away from the light.
+        let camera = Camera::new(Vec3::new(-0.3, 0.35, -0.6), Vec3::new(0.0, 0.05, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let draw = |settings: &RenderSettings| {
+            let mut framebuffer = Framebuffer::new(48, 36);
+            render(&mut framebuffer, &scene, &camera, false, settings);
+            framebuffer
+        };
+
+        let plain = draw(&RenderSettings::default());
+        assert!(plain.depth.iter().all(|depth| depth.is_infinite()));
+        let shadowed = draw(&RenderSettings { contact_shadow_strength: 1.0, ..RenderSettings::default() });
+        assert!(shadowed.depth.iter().any(|depth| depth.is_finite()));
+
+        let mut darkened = 0;
+        for (index, (&before, &after)) in plain.buffer.iter().zip(&shadowed.buffer).enumerate() {
+            if before != after {
+                darkened += 1;
+                assert_eq!(after, 0, "pixel {} changed without going dark", index);
+                assert!(shadowed.depth[index].is_finite(), "sky pixel {} was shadowed", index);
+            }
+        }
+        assert!(darkened > 0 && darkened < plain.buffer.len() / 4, "{} pixels darkened", darkened);
+    }
+
+    // Ground beside a wall standing along x = 0.1, open to the -x side.
+    fn beside_a_wall() -> (Scene, Intersect) {
+        let wall = (0..4).map(|i| Cube::new(Vec3::new(0.2, 0.1, -0.3 + 0.2 * i as f32), 0.2, stone())).collect();
+        let scene = scene_with(wall, vec![]);
+        let hit = Intersect::new(Vec3::new(0.05, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 1.0, stone());
+        (scene, hit)
+    }
+
+    #[test]
+    fn bent_normals_lean_away_from_walls() {
+        let settings = RenderSettings { ao_samples: 64, ..RenderSettings::default() };
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let (scene, hit) = beside_a_wall();
+        let (bent, openness) = bent_normal(&hit, &up, &scene, &settings);
+        assert!(bent.x < -0.1 && bent.y > 0.0, "{:?} does not lean away from the wall", bent);
+        assert!(openness < 0.9 && openness > 0.3, "openness {}", openness);
+
+        let open_ground = scene_with(vec![], vec![]);
+        let (bent, openness) = bent_normal(&hit, &up, &open_ground, &settings);
+        assert!((bent - up).magnitude() < 0.05);
+        assert!((openness - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn occluded_ambient_is_darker_only_with_ao_on() {
+        let (mut scene, hit) = beside_a_wall();
+        scene.lights.clear();
+        let ray = Ray::primary(Vec3::new(0.05, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let ambient = |ao_samples: u32| {
+            let settings = RenderSettings { ao_samples, ..RenderSettings::default() };
+            shade(&ray, &hit, &scene, 1.0, &settings, &mut RenderStats::default(), &mut OccluderCache::default()).luminance()
+        };
+        assert!(ambient(16) < ambient(0) * 0.95, "{} vs {}", ambient(16), ambient(0));
+        assert!(ambient(16) > 0.0);
+    }
+
+    #[test]
+    fn all_hits_lists_overlapping_cubes_nearest_first() {
+        let far = Cube::new(Vec3::new(0.0, 0.1, -0.1), 0.2, stone());
+        let near = Cube::new(Vec3::new(0.0, 0.1, 0.0), 0.2, stone());
+        let scene = scene_with(vec![far, near], vec![]);
+        let ray = Ray::primary(Vec3::new(0.0, 0.15, 1.0), Vec3::new(0.0, -0.1, -1.0).normalize());
+
+        let hits = scene.all_hits(&ray);
+        let objects: Vec<Occluder> = hits.iter().map(|&(_, object)| object).collect();
+        assert_eq!(objects, vec![Occluder::Cube(1), Occluder::Cube(0), Occluder::Plane]);
+        assert!(hits.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(scene_intersect_with_occluder(&ray, &scene).1, objects[0]);
+
+        let hidden = Ray::primary(ray.origin, ray.direction).with_layers(0);
+        assert_eq!(scene.all_hits(&hidden), vec![(hits[2].0, Occluder::Plane)]);
+    }
+
+    #[test]
+    fn all_hits_is_capped() {
+        let row = (0..MAX_ALL_HITS + 4).map(|i| Cube::new(Vec3::new(0.0, 0.1, -(i as f32) * 0.05), 0.02, stone())).collect();
+        let scene = scene_with(row, vec![]);
+        let ray = Ray::primary(Vec3::new(0.0, 0.1, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let hits = scene.all_hits(&ray);
+        assert_eq!(hits.len(), MAX_ALL_HITS);
+        assert_eq!(hits[0].1, Occluder::Cube(0));
+    }
+
+    #[test]
+    fn flat_normal_map_texels_keep_the_surface_normal() {
+        let flat = Texture::from_texels(1, 1, vec![Color::from_linear(0.5, 0.5, 1.0)]).unwrap();
+        let material = stone().with_normal_map(TextureHandle::ready(flat));
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let hit = Intersect::new(Vec3::zeros(), up, 1.0, material)
+            .with_uv((0.5, 0.5))
+            .with_tangents(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((shading_normal(&hit) - up).magnitude() < 1e-5, "{:?}", shading_normal(&hit));
+    }
+
+    #[test]
+    fn scenes_are_built_on_a_worker_and_handed_over() {
+        let mut loader = Loader::start(|progreso| {
+            progreso.report(0.5, "lago espejo")?;
+            Ok(Diorama::from_demo(demos::mirror_lake()))
+        });
+        let start = Instant::now();
+        let diorama = loop {
+            match loader.poll() {
+                Status::Ready(diorama) => break diorama,
+                Status::Failed(err) => panic!("{}", err),
+                Status::Loading => {
+                    assert!(start.elapsed() < Duration::from_secs(10), "the build never finished");
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        };
+        assert_eq!(loader.stage, "lago espejo");
+        assert_eq!(diorama.water_start, diorama.scene.cubes.len());
+        assert!(diorama.school.fish.is_empty());
+    }
+
+    #[test]
+    fn birds_fly_by_day_only_and_when_allowed() {
+        let mut skybox = load_skybox();
+        let time = (0..20_000).map(|i| i as f32 * 5.0).find(|&time| skybox.birds.flock_at(time).is_some()).expect("no flight");
+        assert!(skybox.flock.is_none());
+
+        skybox.update_birds(time, true);
+        assert!(skybox.flock.is_some());
+        skybox.update_birds(time, false);
+        assert!(skybox.flock.is_none());
+
+        skybox.update_birds(time, true);
+        skybox.set_night();
+        assert!(skybox.flock.is_none());
+        skybox.update_birds(time, true);
+        assert!(skybox.flock.is_none());
+        skybox.set_day();
+        skybox.update_birds(time, true);
+        assert!(skybox.flock.is_some());
+    }
+}
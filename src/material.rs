@@ -1,12 +1,28 @@
 
+use std::sync::Arc;
+use nalgebra_glm::Vec3;
 use crate::color::Color;
+use crate::pattern::Pattern;
+use crate::texture::{Atlas, Texture, TextureHandle};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Material {
   pub diffuse: Color,
   pub specular: f32,
   pub albedo: [f32; 4],
   pub refractive_index: f32,
+  pub bevel: f32,
+  pub two_sided: bool,
+  pub absorption: Vec3,
+  pub roughness: f32,
+  pub metallic: bool,
+  pub texture: Option<TextureHandle>,
+  pub pattern: Pattern,
+  pub atlas_tile: Option<(Arc<Atlas>, usize)>,
+  pub normal_map: Option<TextureHandle>,
+  pub emissive_map: Option<TextureHandle>,
+  pub sky_specular: f32,
+  pub probe_reflections: bool,
 }
 
 impl Material {
@@ -21,15 +37,167 @@ impl Material {
       specular,
       albedo,
       refractive_index,
+      bevel: 0.0,
+      two_sided: true,
+      absorption: Vec3::zeros(),
+      roughness: 0.0,
+      metallic: false,
+      texture: None,
+      pattern: Pattern::Solid,
+      atlas_tile: None,
+      normal_map: None,
+      emissive_map: None,
+      sky_specular: 0.0,
+      probe_reflections: false,
     }
   }
 
+  pub fn metal(color: Color) -> Self {
+    Material {
+      metallic: true,
+      ..Material::new(color, 0.0, [0.0, 0.0, 1.0, 0.0], 1.0)
+    }
+  }
+
+  pub fn with_loaded_texture(self, texture: Texture) -> Self {
+    self.with_texture(TextureHandle::ready(texture))
+  }
+
+  pub fn with_texture(mut self, texture: TextureHandle) -> Self {
+    self.texture = Some(texture);
+    self
+  }
+
+  pub fn with_pattern(mut self, pattern: Pattern) -> Self {
+    self.pattern = pattern;
+    self
+  }
+
+  pub fn with_normal_map(mut self, normal_map: TextureHandle) -> Self {
+    self.normal_map = Some(normal_map);
+    self
+  }
+
+  pub fn with_emissive_map(mut self, emissive_map: TextureHandle) -> Self {
+    self.emissive_map = Some(emissive_map);
+    self
+  }
+
+  pub fn emission_at(&self, uv: (f32, f32)) -> Color {
+    match &self.emissive_map {
+      Some(emissive_map) => emissive_map.sample(uv.0, uv.1),
+      None => Color::black(),
+    }
+  }
+
+  pub fn with_atlas_tile(mut self, atlas: Arc<Atlas>, tile: usize) -> Self {
+    self.atlas_tile = Some((atlas, tile));
+    self
+  }
+
+  pub fn diffuse_at(&self, point: &Vec3, uv: (f32, f32)) -> Color {
+    if let Some((atlas, tile)) = &self.atlas_tile {
+      return atlas.sample(*tile, uv.0, uv.1);
+    }
+    match &self.texture {
+      Some(texture) => texture.sample(uv.0, uv.1),
+      None => self.pattern.color_at(self.diffuse, point),
+    }
+  }
+
+  pub fn with_bevel(mut self, width: f32) -> Self {
+    self.bevel = width.clamp(0.0, 0.5);
+    self
+  }
+
+  pub fn with_two_sided(mut self, two_sided: bool) -> Self {
+    self.two_sided = two_sided;
+    self
+  }
+
+  pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+    self.absorption = absorption.sup(&Vec3::zeros());
+    self
+  }
+
+  pub fn with_roughness(mut self, roughness: f32) -> Self {
+    self.roughness = roughness.clamp(0.0, 1.0);
+    self
+  }
+
+  // How much of the sky a rough, non-mirror surface picks up at grazing
+  // angles; grass and leaves want a little so they don't look plastic.
+  pub fn with_sky_specular(mut self, strength: f32) -> Self {
+    self.sky_specular = strength.clamp(0.0, 1.0);
+    self
+  }
+
+  // Sharp reflections come from the scene's reflection probe, when it has
+  // one, instead of a traced ray.
+  pub fn with_probe_reflections(mut self, probe_reflections: bool) -> Self {
+    self.probe_reflections = probe_reflections;
+    self
+  }
+
+  pub fn energy(&self) -> f32 {
+    self.albedo.iter().sum()
+  }
+
+  pub fn normalized(mut self) -> Self {
+    let energy = self.energy();
+    if energy > 1.0 {
+      for weight in self.albedo.iter_mut() {
+        *weight /= energy;
+      }
+    }
+    self
+  }
+
   pub fn black() -> Self {
     Material {
       diffuse: Color::new(0, 0, 0),
       specular: 0.0,
       albedo: [0.0, 0.0, 0.0, 0.0],
       refractive_index: 0.0,
+      bevel: 0.0,
+      two_sided: true,
+      absorption: Vec3::zeros(),
+      roughness: 0.0,
+      metallic: false,
+      texture: None,
+      pattern: Pattern::Solid,
+      atlas_tile: None,
+      normal_map: None,
+      emissive_map: None,
+      sky_specular: 0.0,
+      probe_reflections: false,
     }
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn with_albedo(albedo: [f32; 4]) -> Material {
+    Material::new(Color::new(200, 200, 200), 10.0, albedo, 1.0)
+  }
+
+  #[test]
+  fn energy_is_the_sum_of_the_weights() {
+    assert!((with_albedo([0.2, 0.2, 0.1, 0.5]).energy() - 1.0).abs() < 1e-6);
+    assert!((with_albedo([0.3, 0.5, 0.1, 0.6]).energy() - 1.5).abs() < 1e-6);
+  }
+
+  #[test]
+  fn normalizing_scales_over_unity_weights_to_one() {
+    let normalized = with_albedo([0.3, 0.5, 0.1, 0.6]).normalized();
+    assert!((normalized.energy() - 1.0).abs() < 1e-6);
+    assert!((normalized.albedo[1] - 0.5 / 1.5).abs() < 1e-6);
+  }
+
+  #[test]
+  fn normalizing_leaves_balanced_weights_alone() {
+    let albedo = [0.6, 0.3, 0.0, 0.0];
+    assert_eq!(with_albedo(albedo).normalized().albedo, albedo);
+  }
+}
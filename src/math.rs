@@ -0,0 +1,171 @@
+use std::f32::consts::{LN_2, PI};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// The standard library's powf, sin, cos, tan and exp come from the
+// platform's libm, which rounds differently on different machines, so the
+// same scene can render a few pixels apart on x86 and ARM. Built with the
+// `reproducible` feature and switched on at run time, the shading path
+// uses the plain polynomials below instead, which only use operations
+// IEEE 754 rounds the same everywhere. Rust never fuses a multiply and add
+// on its own, so keeping the operations in order is enough. Without the
+// feature every function here is just the std one.
+static REPRODUCIBLE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_reproducible(on: bool) {
+    REPRODUCIBLE.store(on, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn reproducible() -> bool {
+    cfg!(feature = "reproducible") && REPRODUCIBLE.load(Ordering::Relaxed)
+}
+
+#[inline]
+pub fn sin(x: f32) -> f32 {
+    if reproducible() { portable_sin(x) } else { x.sin() }
+}
+
+#[inline]
+pub fn cos(x: f32) -> f32 {
+    if reproducible() { portable_sin(x + PI * 0.5) } else { x.cos() }
+}
+
+#[inline]
+pub fn tan(x: f32) -> f32 {
+    if reproducible() { portable_sin(x) / portable_sin(x + PI * 0.5) } else { x.tan() }
+}
+
+#[inline]
+pub fn powf(base: f32, exponent: f32) -> f32 {
+    if reproducible() { portable_powf(base, exponent) } else { base.powf(exponent) }
+}
+
+#[inline]
+pub fn exp(x: f32) -> f32 {
+    if reproducible() { portable_exp2(x / LN_2) } else { x.exp() }
+}
+
+// Reduced to [-pi/2, pi/2] by symmetry, then a Taylor series to x^11,
+// good to about 1e-6 there.
+fn portable_sin(x: f32) -> f32 {
+    if !x.is_finite() {
+        return f32::NAN;
+    }
+    let turns = (x / (2.0 * PI)).round();
+    let mut x = x - turns * (2.0 * PI);
+    if x > PI * 0.5 {
+        x = PI - x;
+    } else if x < -PI * 0.5 {
+        x = -PI - x;
+    }
+    let x2 = x * x;
+    let series = 1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0 * (1.0 - x2 / 110.0))));
+    x * series
+}
+
+// 2^x as a power of two built from the exponent bits, times a series for
+// the fractional part.
+fn portable_exp2(x: f32) -> f32 {
+    if x.is_nan() {
+        return f32::NAN;
+    }
+    if x >= 128.0 {
+        return f32::INFINITY;
+    }
+    if x <= -126.0 {
+        return 0.0;
+    }
+    let whole = x.floor();
+    let fraction = (x - whole) * LN_2;
+    let tail = 1.0 + fraction / 5.0 * (1.0 + fraction / 6.0 * (1.0 + fraction / 7.0 * (1.0 + fraction / 8.0)));
+    let series = 1.0 + fraction * (1.0 + fraction / 2.0 * (1.0 + fraction / 3.0 * (1.0 + fraction / 4.0 * tail)));
+    f32::from_bits(((whole as i32 + 127) as u32) << 23) * series
+}
+
+// log2 from the exponent bits plus a series for the mantissa, which is
+// moved into [0.75, 1.5) so the series converges fast.
+fn portable_log2(x: f32) -> f32 {
+    let bits = x.to_bits();
+    let mut exponent = ((bits >> 23) & 0xff) as i32 - 127;
+    let mut mantissa = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000);
+    if mantissa > 1.5 {
+        mantissa *= 0.5;
+        exponent += 1;
+    }
+    let t = (mantissa - 1.0) / (mantissa + 1.0);
+    let t2 = t * t;
+    let ln = 2.0 * t * (1.0 + t2 * (1.0 / 3.0 + t2 * (1.0 / 5.0 + t2 * (1.0 / 7.0 + t2 / 9.0))));
+    exponent as f32 + ln / LN_2
+}
+
+// Only the cases shading needs: a non-negative base. Subnormal bases are
+// treated as zero.
+fn portable_powf(base: f32, exponent: f32) -> f32 {
+    if exponent == 0.0 {
+        return 1.0;
+    }
+    if base.is_nan() || exponent.is_nan() || base < 0.0 {
+        return f32::NAN;
+    }
+    if base < f32::MIN_POSITIVE {
+        return if exponent > 0.0 { 0.0 } else { f32::INFINITY };
+    }
+    portable_exp2(exponent * portable_log2(base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(from: f32, to: f32, count: usize) -> impl Iterator<Item = f32> {
+        (0..=count).map(move |i| from + (to - from) * i as f32 / count as f32)
+    }
+
+    #[test]
+    fn sine_and_cosine_match_std() {
+        for x in samples(-40.0, 40.0, 4000) {
+            assert!((portable_sin(x) - x.sin()).abs() < 2e-5, "sin({})", x);
+            assert!((portable_sin(x + PI * 0.5) - x.cos()).abs() < 2e-5, "cos({})", x);
+        }
+        assert_eq!(portable_sin(0.0), 0.0);
+        assert!(portable_sin(f32::INFINITY).is_nan());
+    }
+
+    #[test]
+    fn powers_match_std() {
+        for base in samples(0.0, 1.0, 200) {
+            for exponent in [0.5, 1.0 / 2.4, 2.0, 5.0, 32.0, 80.0] {
+                let expected = base.powf(exponent);
+                let error = (portable_powf(base, exponent) - expected).abs();
+                assert!(error <= 1e-5 + expected * 2e-5, "{}^{}", base, exponent);
+            }
+        }
+        assert_eq!(portable_powf(0.0, 3.0), 0.0);
+        assert_eq!(portable_powf(0.3, 0.0), 1.0);
+        assert_eq!(portable_powf(1.0, 50.0), 1.0);
+        assert!(portable_powf(-1.0, 0.5).is_nan());
+    }
+
+    #[test]
+    fn exponentials_match_std() {
+        // Dividing by ln 2 rounds the argument, which costs a little
+        // relative precision the further x is from zero.
+        for x in samples(-20.0, 5.0, 500) {
+            let expected = x.exp();
+            assert!((portable_exp2(x / LN_2) - expected).abs() <= expected * 2e-6 * x.abs().max(1.0), "exp({})", x);
+        }
+        assert_eq!(portable_exp2(0.0), 1.0);
+        assert_eq!(portable_exp2(-200.0), 0.0);
+    }
+
+    // Without the feature the flag can't change anything, so flipping it
+    // here doesn't race the other tests.
+    #[cfg(not(feature = "reproducible"))]
+    #[test]
+    fn the_flag_needs_the_feature() {
+        set_reproducible(true);
+        assert!(!reproducible());
+        assert_eq!(sin(1.234), 1.234f32.sin());
+        set_reproducible(false);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::color::Color;
+
+pub const GRASS: Color = Color::new(34, 139, 34);
+pub const DIRT: Color = Color::new(121, 85, 58);
+pub const SAND: Color = Color::new(194, 178, 128);
+pub const GRAVEL: Color = Color::new(136, 126, 126);
+pub const CLAY: Color = Color::new(160, 166, 179);
+pub const STONE: Color = Color::new(125, 125, 125);
+pub const COBBLESTONE: Color = Color::new(110, 110, 110);
+pub const OAK_LOG: Color = Color::new(139, 69, 19);
+pub const OAK_PLANKS: Color = Color::new(162, 130, 78);
+pub const OAK_LEAVES: Color = Color::new(0, 255, 0);
+pub const BIRCH_LOG: Color = Color::new(216, 215, 210);
+pub const BIRCH_LEAVES: Color = Color::new(128, 167, 85);
+pub const SPRUCE_LOG: Color = Color::new(58, 37, 16);
+pub const SPRUCE_LEAVES: Color = Color::new(97, 153, 97);
+pub const WATER: Color = Color::new(0, 0, 255);
+pub const ICE: Color = Color::new(145, 183, 253);
+pub const SNOW: Color = Color::new(249, 254, 254);
+pub const LAVA: Color = Color::new(207, 92, 15);
+pub const GLOWSTONE: Color = Color::new(255, 188, 94);
+pub const BRICK: Color = Color::new(150, 97, 83);
+pub const COAL_BLOCK: Color = Color::new(16, 16, 16);
+pub const IRON_BLOCK: Color = Color::new(220, 220, 220);
+pub const GOLD_BLOCK: Color = Color::new(255, 215, 0);
+pub const RUBY_BLOCK: Color = Color::new(224, 17, 95);
+pub const EMERALD_BLOCK: Color = Color::new(42, 203, 87);
+pub const DIAMOND_BLOCK: Color = Color::new(98, 237, 228);
+pub const DAY_ZENITH: Color = Color::new(38, 86, 184);
+pub const DAY_HORIZON: Color = Color::new(196, 226, 243);
+pub const DAY_GROUND: Color = Color::new(104, 112, 108);
+pub const NIGHT_ZENITH: Color = Color::new(3, 4, 16);
+pub const NIGHT_HORIZON: Color = Color::new(26, 30, 58);
+pub const NIGHT_GROUND: Color = Color::new(8, 8, 12);
+pub const CLOUD_TOP: Color = Color::new(250, 250, 252);
+pub const CLOUD_BASE: Color = Color::new(150, 155, 165);
+pub const SUNLIGHT: Color = Color::new(255, 255, 255);
+pub const MOONLIGHT: Color = Color::new(110, 125, 200);
+pub const CUTAWAY: Color = Color::new(255, 90, 200);
+pub const GOLDFISH: Color = Color::new(255, 120, 30);
+pub const BIRD: Color = Color::new(38, 36, 44);
+
+// Names for the block colors, kind first and variant second, for places
+// that pick a color by name instead of in code.
+pub const NAMED: [(&str, Color); 26] = [
+    ("grass", GRASS),
+    ("dirt", DIRT),
+    ("sand", SAND),
+    ("gravel", GRAVEL),
+    ("clay", CLAY),
+    ("stone", STONE),
+    ("cobblestone", COBBLESTONE),
+    ("log_oak", OAK_LOG),
+    ("planks_oak", OAK_PLANKS),
+    ("leaves_oak", OAK_LEAVES),
+    ("log_birch", BIRCH_LOG),
+    ("leaves_birch", BIRCH_LEAVES),
+    ("log_spruce", SPRUCE_LOG),
+    ("leaves_spruce", SPRUCE_LEAVES),
+    ("water", WATER),
+    ("ice", ICE),
+    ("snow", SNOW),
+    ("lava", LAVA),
+    ("glowstone", GLOWSTONE),
+    ("brick", BRICK),
+    ("block_coal", COAL_BLOCK),
+    ("block_iron", IRON_BLOCK),
+    ("block_gold", GOLD_BLOCK),
+    ("block_ruby", RUBY_BLOCK),
+    ("block_emerald", EMERALD_BLOCK),
+    ("block_diamond", DIAMOND_BLOCK),
+];
+
+pub fn get(name: &str) -> Option<Color> {
+    NAMED.iter().find(|(named, _)| *named == name).map(|&(_, color)| color)
+}
+
+pub fn lighter(color: Color, amount: f32) -> Color {
+    let (h, s, v) = color.to_hsv();
+    Color::from_hsv(h, s, v + amount)
+}
+
+pub fn darker(color: Color, amount: f32) -> Color {
+    lighter(color, -amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_find_their_colors() {
+        assert_eq!(get("leaves_oak"), Some(OAK_LEAVES));
+        assert_eq!(get("log_oak"), Some(OAK_LOG));
+        assert_eq!(get("block_diamond"), Some(DIAMOND_BLOCK));
+    }
+
+    #[test]
+    fn unknown_names_find_nothing() {
+        assert_eq!(get("oak_leaves"), None);
+        assert_eq!(get(""), None);
+        assert_eq!(get("Leaves_Oak"), None);
+    }
+
+    #[test]
+    fn names_are_unique() {
+        for (i, (name, _)) in NAMED.iter().enumerate() {
+            assert!(NAMED[i + 1..].iter().all(|(other, _)| other != name), "{} is listed twice", name);
+        }
+    }
+}
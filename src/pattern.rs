@@ -0,0 +1,92 @@
+use nalgebra_glm::Vec3;
+use crate::color::Color;
+use crate::noise::fractal_noise;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Solid,
+    Checker { color_a: Color, color_b: Color, scale: f32 },
+    Noise { base: Color, accent: Color, frequency: f32, seed: u32 },
+}
+
+impl Pattern {
+    pub fn color_at(&self, base: Color, point: &Vec3) -> Color {
+        match *self {
+            Pattern::Solid => base,
+            Pattern::Checker { color_a, color_b, scale } => {
+                let cell = (point / scale).map(f32::floor);
+                if (cell.x + cell.y + cell.z).rem_euclid(2.0) < 1.0 {
+                    color_a
+                } else {
+                    color_b
+                }
+            }
+            Pattern::Noise { base, accent, frequency, seed } => {
+                let t = fractal_noise(&(point * frequency), seed);
+                base * (1.0 - t) + accent * t
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette;
+
+    fn leaves() -> Pattern {
+        Pattern::Noise {
+            base: palette::OAK_LEAVES,
+            accent: palette::darker(palette::OAK_LEAVES, 0.35),
+            frequency: 30.0,
+            seed: 7,
+        }
+    }
+
+    fn stone() -> Pattern {
+        Pattern::Noise {
+            base: palette::STONE,
+            accent: palette::darker(palette::COBBLESTONE, 0.3),
+            frequency: 45.0,
+            seed: 21,
+        }
+    }
+
+    #[test]
+    fn checker_cells_alternate() {
+        let checker = Pattern::Checker { color_a: Color::new(255, 255, 255), color_b: Color::black(), scale: 0.1 };
+        assert_eq!(checker.color_at(palette::GRASS, &Vec3::new(0.05, 0.0, 0.05)), Color::new(255, 255, 255));
+        assert_eq!(checker.color_at(palette::GRASS, &Vec3::new(0.15, 0.0, 0.05)), Color::black());
+        assert_eq!(checker.color_at(palette::GRASS, &Vec3::new(-0.05, 0.0, 0.05)), Color::black());
+        assert_eq!(Pattern::Solid.color_at(palette::GRASS, &Vec3::zeros()), palette::GRASS);
+    }
+
+    #[test]
+    fn the_same_seed_paints_the_same_color() {
+        let point = Vec3::new(0.05, 0.45, -0.8);
+        assert_eq!(leaves().color_at(Color::black(), &point), leaves().color_at(Color::black(), &point));
+        let reseeded = Pattern::Noise { base: palette::OAK_LEAVES, accent: palette::darker(palette::OAK_LEAVES, 0.35), frequency: 30.0, seed: 8 };
+        assert_ne!(reseeded.color_at(Color::black(), &point), leaves().color_at(Color::black(), &point));
+    }
+
+    // The leaf and stone materials from main, at a few points in the
+    // diorama; the golden images depend on these.
+    #[test]
+    fn leaf_and_stone_colors_are_pinned() {
+        let points = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.05, 0.45, -0.8), Vec3::new(-0.5, 0.6, -0.45)];
+        let hex = |pattern: Pattern| -> Vec<u32> { points.iter().map(|point| pattern.color_at(Color::black(), point).to_hex()).collect() };
+        assert_eq!(hex(leaves()), [0x00cd00, 0x00d000, 0x00e100]);
+        assert_eq!(hex(stone()), [0x6e6e6e, 0x464646, 0x5f5f5f]);
+    }
+
+    // The pattern comes from the world position, so two cubes side by side
+    // show no seam where they meet.
+    #[test]
+    fn noise_runs_on_across_cube_faces() {
+        let seam = Vec3::new(0.05, 0.43, -0.8);
+        let step = Vec3::new(1e-4, 0.0, 0.0);
+        let left = leaves().color_at(Color::black(), &(seam - step)).to_unit_rgb();
+        let right = leaves().color_at(Color::black(), &(seam + step)).to_unit_rgb();
+        assert!((0..3).all(|channel| (left[channel] - right[channel]).abs() < 0.01), "{:?} {:?}", left, right);
+    }
+}
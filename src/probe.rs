@@ -0,0 +1,195 @@
+use nalgebra_glm::Vec3;
+use crate::color::Color;
+use crate::ray::Ray;
+use crate::settings::RenderSettings;
+use crate::texture::{FilterMode, Texture, WrapMode};
+use crate::{cast_ray, OccluderCache, RenderStats, Scene};
+
+pub const FACE_COUNT: usize = 6;
+
+// Forward, right and up for each cubemap face: +x, -x, +y, -y, +z, -z.
+const FACES: [[[f32; 3]; 3]; FACE_COUNT] = [
+    [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]],
+    [[-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+    [[0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]],
+    [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+    [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+    [[0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+];
+
+fn basis(face: usize) -> [Vec3; 3] {
+    FACES[face].map(|[x, y, z]| Vec3::new(x, y, z))
+}
+
+// A low resolution cubemap of the scene as seen from one point. Materials
+// that opt in look their reflections up here instead of tracing them, which
+// is close enough for broad flat surfaces like the pond.
+#[derive(Debug)]
+pub struct ReflectionProbe {
+    pub position: Vec3,
+    pub resolution: usize,
+    faces: Vec<Texture>,
+    next_face: usize,
+}
+
+impl ReflectionProbe {
+    pub fn new(position: Vec3, resolution: usize) -> Self {
+        let resolution = resolution.max(1);
+        let blank = || {
+            Texture::from_texels(resolution, resolution, vec![Color::black(); resolution * resolution])
+                .expect("probe face size")
+                .with_filter(FilterMode::Bilinear)
+                .with_wrap(WrapMode::Clamp)
+        };
+        ReflectionProbe { position, resolution, faces: (0..FACE_COUNT).map(|_| blank()).collect(), next_face: 0 }
+    }
+
+    // The probe is rendered with the scene's own renderer. It should not be
+    // in the scene while it renders, or opted-in surfaces it sees would
+    // sample the faces being replaced; they trace instead.
+    pub fn capture_face(&mut self, face: usize, scene: &Scene, settings: &RenderSettings) {
+        let [forward, right, up] = basis(face);
+        let size = self.resolution;
+        let mut stats = RenderStats::default();
+        let mut occluders = OccluderCache::default();
+
+        let texels = (0..size * size)
+            .map(|index| {
+                let s = 2.0 * ((index % size) as f32 + 0.5) / size as f32 - 1.0;
+                let t = 1.0 - 2.0 * ((index / size) as f32 + 0.5) / size as f32;
+                let direction = (forward + right * s + up * t).normalize();
+                let ray = Ray::primary(self.position, direction).with_layers(settings.reflection_layers);
+                cast_ray(&ray, scene, 1.0, settings, &mut stats, &mut occluders)
+            })
+            .collect();
+        self.faces[face] = Texture::from_texels(size, size, texels)
+            .expect("probe face size")
+            .with_filter(FilterMode::Bilinear)
+            .with_wrap(WrapMode::Clamp);
+    }
+
+    pub fn capture(&mut self, scene: &Scene, settings: &RenderSettings) {
+        for face in 0..FACE_COUNT {
+            self.capture_face(face, scene, settings);
+        }
+    }
+
+    // Re-renders a single face, cycling through all six, so keeping the
+    // probe current costs a fixed slice of every frame.
+    pub fn update_next_face(&mut self, scene: &Scene, settings: &RenderSettings) {
+        self.capture_face(self.next_face, scene, settings);
+        self.next_face = (self.next_face + 1) % FACE_COUNT;
+    }
+
+    pub fn sample(&self, direction: &Vec3) -> Color {
+        let magnitude = direction.abs();
+        let face = if magnitude.x >= magnitude.y && magnitude.x >= magnitude.z {
+            if direction.x > 0.0 { 0 } else { 1 }
+        } else if magnitude.y >= magnitude.z {
+            if direction.y > 0.0 { 2 } else { 3 }
+        } else if direction.z > 0.0 {
+            4
+        } else {
+            5
+        };
+
+        let [forward, right, up] = basis(face);
+        let depth = direction.dot(&forward);
+        let s = direction.dot(&right) / depth;
+        let t = direction.dot(&up) / depth;
+        self.faces[face].sample((s + 1.0) * 0.5, (t + 1.0) * 0.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::Cube;
+    use crate::decal::Decals;
+    use crate::light::Light;
+    use crate::material::Material;
+    use crate::palette;
+    use crate::waves::Waves;
+    use crate::{load_skybox, Plane};
+
+    fn glowing(color: Color) -> Material {
+        let glow = Texture::from_texels(1, 1, vec![color]).unwrap();
+        Material::new(Color::black(), 10.0, [0.0, 0.0, 0.0, 0.0], 1.0)
+            .with_emissive_map(crate::texture::TextureHandle::ready(glow))
+    }
+
+    // A red cube to the east and a blue one to the north of the probe.
+    fn two_cubes() -> Scene {
+        let mut skybox = load_skybox();
+        skybox.show_clouds = false;
+        let ground = Material::new(palette::STONE, 10.0, [0.9, 0.1, 0.0, 0.0], 1.0);
+        Scene {
+            plane: Plane::try_new(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0), ground, None).unwrap(),
+            cubes: vec![
+                Cube::new(Vec3::new(1.0, 0.0, 0.0), 0.5, glowing(Color::new(255, 0, 0))),
+                Cube::new(Vec3::new(0.0, 0.0, -1.0), 0.5, glowing(Color::new(0, 0, 255))),
+            ],
+            cuboids: vec![],
+            decorations: vec![],
+            solids: vec![],
+            lights: vec![Light::directional(Vec3::new(0.0, -1.0, 0.0), palette::SUNLIGHT, 1.0)],
+            decals: Decals::default(),
+            skybox,
+            probe: None,
+            waves: Waves::default(),
+        }
+    }
+
+    #[test]
+    fn faces_see_what_lies_in_their_direction() {
+        let scene = two_cubes();
+        let mut probe = ReflectionProbe::new(Vec3::zeros(), 8);
+        probe.capture(&scene, &RenderSettings::default());
+
+        let east = probe.sample(&Vec3::new(1.0, 0.0, 0.0));
+        let north = probe.sample(&Vec3::new(0.0, 0.0, -1.0));
+        let up_west = Vec3::new(-1.0, 0.4, 0.0);
+        let west = probe.sample(&up_west);
+        assert!(east.to_unit_rgb()[0] > 0.5 && east.to_unit_rgb()[2] < 0.1);
+        assert!(north.to_unit_rgb()[2] > 0.5 && north.to_unit_rgb()[0] < 0.1);
+        let sky = scene.skybox.sample(up_west).to_unit_rgb();
+        assert!((0..3).all(|c| (west.to_unit_rgb()[c] - sky[c]).abs() < 0.02), "{:?} is not the sky", west);
+    }
+
+    #[test]
+    fn updates_render_one_face_at_a_time() {
+        let scene = two_cubes();
+        let mut probe = ReflectionProbe::new(Vec3::zeros(), 8);
+        let east = Vec3::new(1.0, 0.0, 0.0);
+        let north = Vec3::new(0.0, 0.0, -1.0);
+
+        probe.update_next_face(&scene, &RenderSettings::default());
+        assert!(probe.sample(&east).to_unit_rgb()[0] > 0.5);
+        assert_eq!(probe.sample(&north), Color::black());
+
+        for _ in 1..FACE_COUNT {
+            probe.update_next_face(&scene, &RenderSettings::default());
+        }
+        assert!(probe.sample(&north).to_unit_rgb()[2] > 0.5);
+    }
+
+    #[test]
+    fn flagged_materials_reflect_the_probe_instead_of_the_scene() {
+        let mirror = Material::new(Color::black(), 10.0, [0.0, 0.0, 1.0, 0.0], 1.0);
+        let mut scene = two_cubes();
+        scene.cubes.push(Cube::new(Vec3::new(0.0, 0.0, 1.0), 0.5, mirror.clone().with_probe_reflections(true)));
+        // Never captured, so every face is still black.
+        scene.probe = Some(ReflectionProbe::new(Vec3::zeros(), 8));
+
+        let toward_mirror = Ray::primary(Vec3::new(0.0, 0.1, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let reflect = |scene: &Scene, settings: &RenderSettings| {
+            cast_ray(&toward_mirror, scene, 1.0, settings, &mut RenderStats::default(), &mut OccluderCache::default())
+        };
+        assert_eq!(reflect(&scene, &RenderSettings::default()), Color::black());
+
+        let traced = RenderSettings { reflection_probes: false, ..RenderSettings::default() };
+        assert_ne!(reflect(&scene, &traced), Color::black());
+        scene.cubes[2].material = mirror;
+        assert_ne!(reflect(&scene, &RenderSettings::default()), Color::black());
+    }
+}
@@ -0,0 +1,130 @@
+use nalgebra_glm::Vec3;
+use crate::material::Material;
+use crate::layers;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RayKind {
+    Primary,
+    Shadow,
+    Secondary,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub t_min: f32,
+    pub t_max: f32,
+    pub kind: RayKind,
+    pub depth: u32,
+    pub medium: Option<Material>,
+    pub layers: u32,
+}
+
+impl Ray {
+    pub fn primary(origin: Vec3, direction: Vec3) -> Self {
+        Ray {
+            origin,
+            direction,
+            t_min: 0.0,
+            t_max: f32::INFINITY,
+            kind: RayKind::Primary,
+            depth: 0,
+            medium: None,
+            layers: layers::ALL,
+        }
+    }
+
+    pub fn shadow(from: Vec3, to_light: Vec3) -> Self {
+        let distance = to_light.magnitude();
+        Ray {
+            t_max: distance,
+            kind: RayKind::Shadow,
+            ..Ray::primary(from, to_light / distance)
+        }
+    }
+
+    pub fn secondary(parent: &Ray, origin: Vec3, direction: Vec3) -> Self {
+        Ray {
+            kind: RayKind::Secondary,
+            depth: parent.depth + 1,
+            medium: parent.medium.clone(),
+            layers: parent.layers,
+            ..Ray::primary(origin, direction)
+        }
+    }
+
+    pub fn with_t_min(mut self, t_min: f32) -> Self {
+        self.t_min = t_min;
+        self
+    }
+
+    pub fn with_layers(mut self, layers: u32) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn with_medium(mut self, medium: Option<Material>) -> Self {
+        self.medium = medium;
+        self
+    }
+
+    pub fn point_at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    pub fn accepts(&self, t: f32) -> bool {
+        t >= self.t_min && t <= self.t_max
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn primary_rays_accept_any_forward_distance() {
+        let ray = Ray::primary(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(ray.kind, RayKind::Primary);
+        assert_eq!(ray.depth, 0);
+        assert!(ray.medium.is_none());
+        assert!(ray.accepts(0.0));
+        assert!(ray.accepts(1.0e6));
+        assert!(!ray.accepts(-1.0e-3));
+    }
+
+    #[test]
+    fn shadow_rays_stop_at_the_light() {
+        let ray = Ray::shadow(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 3.0, 4.0));
+        assert_eq!(ray.kind, RayKind::Shadow);
+        assert!((ray.direction - Vec3::new(0.0, 0.6, 0.8)).magnitude() < 1e-6);
+        assert!((ray.t_max - 5.0).abs() < 1e-6);
+        assert!(ray.accepts(4.99));
+        assert!(!ray.accepts(5.01));
+        assert!((ray.point_at(ray.t_max) - Vec3::new(1.0, 3.0, 4.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn secondary_rays_inherit_depth_and_medium() {
+        let water = Material::new(Color::new(0, 0, 255), 10.0, [0.0, 0.0, 0.0, 1.0], 1.33);
+        let parent = Ray::primary(Vec3::zeros(), Vec3::new(1.0, 0.0, 0.0))
+            .with_t_min(2.0)
+            .with_medium(Some(water.clone()));
+        let child = Ray::secondary(&parent, Vec3::new(3.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        assert_eq!(child.kind, RayKind::Secondary);
+        assert_eq!(child.depth, 1);
+        assert_eq!(child.medium, Some(water));
+        assert_eq!(child.t_min, 0.0);
+        assert_eq!(child.t_max, f32::INFINITY);
+        assert_eq!(Ray::secondary(&child, child.origin, child.direction).depth, 2);
+    }
+
+    #[test]
+    fn t_min_moves_the_start_of_the_range() {
+        let ray = Ray::primary(Vec3::zeros(), Vec3::new(1.0, 0.0, 0.0)).with_t_min(1.5);
+        assert!(!ray.accepts(1.0));
+        assert!(ray.accepts(1.5));
+        assert_eq!(ray.kind, RayKind::Primary);
+    }
+}
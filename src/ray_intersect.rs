@@ -1,15 +1,22 @@
 
 use nalgebra_glm::Vec3;
 use crate::material::Material;
+use crate::ray::Ray;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct Intersect {
     pub point: Vec3,
     pub normal: Vec3,
     pub distance: f32,
     pub is_intersecting: bool,
+    pub entering: bool,
+    pub uv: (f32, f32),
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub extent: f32,
     pub material: Material,
+    pub cube_face: Option<(usize, usize)>,
 }
 
 impl Intersect {
@@ -19,7 +26,20 @@ impl Intersect {
             normal,
             distance,
             is_intersecting: true,
+            entering: true,
+            uv: (0.0, 0.0),
+            tangent: Vec3::zeros(),
+            bitangent: Vec3::zeros(),
+            extent: f32::INFINITY,
             material,
+            cube_face: None,
+        }
+    }
+
+    pub fn exit(point: Vec3, normal: Vec3, distance: f32, material: Material) -> Self {
+        Intersect {
+            entering: false,
+            ..Intersect::new(point, normal, distance, material)
         }
     }
 
@@ -29,11 +49,197 @@ impl Intersect {
             normal: Vec3::zeros(),
             distance: 0.0,
             is_intersecting: false,
+            entering: true,
+            uv: (0.0, 0.0),
+            tangent: Vec3::zeros(),
+            bitangent: Vec3::zeros(),
+            extent: f32::INFINITY,
             material: Material::black(),
+            cube_face: None,
         }
     }
+
+    pub fn with_uv(mut self, uv: (f32, f32)) -> Intersect {
+        self.uv = uv;
+        self
+    }
+
+    pub fn with_tangents(mut self, tangent: Vec3, bitangent: Vec3) -> Intersect {
+        self.tangent = tangent;
+        self.bitangent = bitangent;
+        self
+    }
+
+    pub fn with_extent(mut self, extent: f32) -> Intersect {
+        self.extent = extent;
+        self
+    }
+
+    // Which scene cube and face this is, so scene-level data like decals
+    // can find it.
+    pub fn with_cube_face(mut self, cube: usize, face: usize) -> Intersect {
+        self.cube_face = Some((cube, face));
+        self
+    }
+
+    // Turns an outward-facing surface hit into the hit of a ray leaving
+    // the solid there.
+    pub fn into_exit(mut self) -> Intersect {
+        self.entering = false;
+        self.normal = -self.normal;
+        self
+    }
+
+    pub fn facing(mut self, ray_direction: &Vec3) -> Intersect {
+        let back_facing = self.normal.dot(ray_direction) > 0.0;
+        if !self.is_intersecting || (self.entering && !back_facing) {
+            return self;
+        }
+        if !self.material.two_sided {
+            return Intersect::empty();
+        }
+        if back_facing {
+            self.normal = -self.normal;
+            self.entering = false;
+        }
+        self
+    }
 }
 
 pub trait RayIntersect {
-    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect;
-}
\ No newline at end of file
+    fn ray_intersect(&self, ray: &Ray) -> Intersect;
+}
+
+pub fn hit_interval(min: &Vec3, max: &Vec3, ray_origin: &Vec3, ray_direction: &Vec3) -> Option<(f32, f32)> {
+    let mut t_near = f32::NEG_INFINITY;
+    let mut t_far = f32::INFINITY;
+
+    for axis in 0..3 {
+        let origin = ray_origin[axis];
+        let direction = ray_direction[axis];
+
+        if direction.abs() < f32::EPSILON {
+            if origin < min[axis] || origin > max[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let t1 = (min[axis] - origin) / direction;
+        let t2 = (max[axis] - origin) / direction;
+        t_near = t_near.max(t1.min(t2));
+        t_far = t_far.min(t1.max(t2));
+
+        if t_near > t_far {
+            return None;
+        }
+    }
+
+    if t_far < 0.0 {
+        return None;
+    }
+
+    Some((t_near, t_far))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: Vec3 = Vec3::new(-1.0, -1.0, -1.0);
+    const MAX: Vec3 = Vec3::new(1.0, 1.0, 1.0);
+
+    fn interval(origin: Vec3, direction: Vec3) -> Option<(f32, f32)> {
+        hit_interval(&MIN, &MAX, &origin, &direction)
+    }
+
+    #[test]
+    fn ray_from_outside_enters_and_exits() {
+        let (t_near, t_far) = interval(Vec3::new(-3.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!((t_near - 2.0).abs() < 1e-6);
+        assert!((t_far - 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ray_from_inside_has_negative_entry() {
+        let (t_near, t_far) = interval(Vec3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!((t_near + 1.5).abs() < 1e-6);
+        assert!((t_far - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn box_behind_the_origin_is_missed() {
+        assert_eq!(interval(Vec3::new(3.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn ray_sliding_along_a_face_grazes_it() {
+        let (t_near, t_far) = interval(Vec3::new(-3.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!((t_near - 2.0).abs() < 1e-6);
+        assert!((t_far - 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ray_touching_a_corner_has_a_single_point_interval() {
+        let (t_near, t_far) = interval(Vec3::new(-2.0, 0.0, 1.0), Vec3::new(1.0, 1.0, 0.0)).unwrap();
+        assert!((t_near - 1.0).abs() < 1e-6);
+        assert!((t_far - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn axis_parallel_rays_depend_on_the_slab() {
+        assert!(interval(Vec3::new(0.5, -3.0, 0.5), Vec3::new(0.0, 1.0, 0.0)).is_some());
+        assert_eq!(interval(Vec3::new(1.5, -3.0, 0.5), Vec3::new(0.0, 1.0, 0.0)), None);
+        assert_eq!(interval(Vec3::new(0.5, -3.0, -1.5), Vec3::new(0.0, 1.0, 0.0)), None);
+    }
+
+    #[test]
+    fn zero_direction_reports_inside_or_miss() {
+        assert!(interval(Vec3::zeros(), Vec3::zeros()).is_some());
+        assert_eq!(interval(Vec3::new(2.0, 0.0, 0.0), Vec3::zeros()), None);
+    }
+
+    fn face_hit(two_sided: bool) -> Intersect {
+        let material = Material::black().with_two_sided(two_sided);
+        Intersect::new(Vec3::zeros(), Vec3::new(0.0, 0.0, 1.0), 1.0, material)
+    }
+
+    const TOWARD_FACE: Vec3 = Vec3::new(0.0, 0.0, -1.0);
+    const FROM_BEHIND: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+
+    #[test]
+    fn front_faces_pass_through_unchanged() {
+        for two_sided in [false, true] {
+            let hit = face_hit(two_sided).facing(&TOWARD_FACE);
+            assert!(hit.is_intersecting && hit.entering);
+            assert_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn back_faces_of_single_sided_materials_are_culled() {
+        assert!(!face_hit(false).facing(&FROM_BEHIND).is_intersecting);
+    }
+
+    #[test]
+    fn two_sided_back_faces_turn_toward_the_ray() {
+        let hit = face_hit(true).facing(&FROM_BEHIND);
+        assert!(hit.is_intersecting);
+        assert!(!hit.entering);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn exits_from_single_sided_solids_are_culled() {
+        let inside_face = Intersect::exit(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0), 1.0, Material::black().with_two_sided(false));
+        assert!(!inside_face.facing(&FROM_BEHIND).is_intersecting);
+
+        let two_sided = Intersect::exit(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0), 1.0, Material::black());
+        assert!(two_sided.facing(&FROM_BEHIND).is_intersecting);
+    }
+
+    #[test]
+    fn misses_stay_misses() {
+        assert!(!Intersect::empty().facing(&TOWARD_FACE).is_intersecting);
+    }
+}
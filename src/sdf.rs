@@ -0,0 +1,221 @@
+use nalgebra_glm::Vec3;
+use crate::aabb::Aabb;
+use crate::cube::{box_face_uv, face_tangents};
+use crate::layers;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::ray_intersect::{hit_interval, Intersect, RayIntersect};
+
+// Grazing rays can creep along a surface without converging, so the march
+// gives up after MAX_STEPS and reports a miss.
+const MAX_STEPS: u32 = 64;
+const HIT_EPSILON: f32 = 1e-4;
+const NORMAL_EPSILON: f32 = 1e-4;
+
+#[derive(Clone, Copy, Debug)]
+pub enum SdfShape {
+    RoundedBox { half_extents: Vec3, radius: f32 },
+}
+
+impl SdfShape {
+    fn distance(&self, p: &Vec3) -> f32 {
+        match *self {
+            SdfShape::RoundedBox { half_extents, radius } => {
+                let q = p.abs() - (half_extents - Vec3::new(radius, radius, radius));
+                let outside = q.sup(&Vec3::zeros()).magnitude();
+                let inside = q.x.max(q.y.max(q.z)).min(0.0);
+                outside + inside - radius
+            }
+        }
+    }
+
+    fn half_extents(&self) -> Vec3 {
+        match *self {
+            SdfShape::RoundedBox { half_extents, .. } => half_extents,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SdfPrimitive {
+    pub center: Vec3,
+    pub shape: SdfShape,
+    pub material: Material,
+    pub layers: u32,
+}
+
+impl SdfPrimitive {
+    pub fn rounded_box(center: Vec3, half_extents: Vec3, radius: f32, material: Material) -> Self {
+        let radius = radius.clamp(0.0, half_extents.min());
+        SdfPrimitive {
+            center,
+            shape: SdfShape::RoundedBox { half_extents, radius },
+            material,
+            layers: layers::DEFAULT,
+        }
+    }
+
+    pub fn with_layers(mut self, layers: u32) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let half = self.shape.half_extents();
+        Aabb::new(self.center - half, self.center + half)
+    }
+
+    fn distance(&self, point: &Vec3) -> f32 {
+        self.shape.distance(&(point - self.center))
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let dx = Vec3::new(NORMAL_EPSILON, 0.0, 0.0);
+        let dy = Vec3::new(0.0, NORMAL_EPSILON, 0.0);
+        let dz = Vec3::new(0.0, 0.0, NORMAL_EPSILON);
+
+        Vec3::new(
+            self.distance(&(point + dx)) - self.distance(&(point - dx)),
+            self.distance(&(point + dy)) - self.distance(&(point - dy)),
+            self.distance(&(point + dz)) - self.distance(&(point - dz)),
+        )
+        .normalize()
+    }
+
+    // UVs and tangents are box-projected along the normal's dominant axis,
+    // in world units like a cuboid's, so flat sides texture the same way a
+    // cuboid would and the rounded edges switch projection at the diagonal.
+    // The tangents are then bent to lie flat on the curved surface.
+    fn surface_at(&self, point: &Vec3, t: f32) -> Intersect {
+        let normal = self.normal(point);
+        let axis = normal.iamax();
+        let mut axis_normal = Vec3::zeros();
+        axis_normal[axis] = normal[axis].signum();
+
+        let local = point - (self.center - self.shape.half_extents());
+        let uv = box_face_uv(&local, &(self.shape.half_extents() * 2.0), &axis_normal);
+        let (tangent, bitangent) = face_tangents(&axis_normal);
+        let tangent = (tangent - normal * normal.dot(&tangent)).normalize();
+        let bitangent = (bitangent - normal * normal.dot(&bitangent) - tangent * tangent.dot(&bitangent)).normalize();
+
+        Intersect::new(*point, normal, t, self.material.clone())
+            .with_uv(uv)
+            .with_tangents(tangent, bitangent)
+            .with_extent(2.0 * self.shape.half_extents().min())
+    }
+}
+
+impl RayIntersect for SdfPrimitive {
+    fn ray_intersect(&self, ray: &Ray) -> Intersect {
+        let aabb = self.aabb();
+        let (t_near, t_far) = match hit_interval(&aabb.min, &aabb.max, &ray.origin, &ray.direction) {
+            Some(interval) => interval,
+            None => return Intersect::empty(),
+        };
+        let (t_enter, t_exit) = (t_near.max(ray.t_min), t_far.min(ray.t_max));
+        if t_enter > t_exit {
+            return Intersect::empty();
+        }
+
+        // Rays that start inside, like refracted ones, march out to the exit
+        // on the negated distance instead.
+        let direction_length = ray.direction.magnitude();
+        let mut t = t_enter;
+        let inside = t_near < ray.t_min && self.distance(&ray.point_at(t)) < 0.0;
+        for _ in 0..MAX_STEPS {
+            let point = ray.point_at(t);
+            let distance = if inside { -self.distance(&point) } else { self.distance(&point) };
+            if distance < HIT_EPSILON {
+                if !ray.accepts(t) {
+                    break;
+                }
+                let hit = self.surface_at(&point, t);
+                return if inside { hit.into_exit() } else { hit };
+            }
+            t += distance / direction_length;
+            if t > t_exit {
+                break;
+            }
+        }
+
+        Intersect::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn pebble() -> SdfPrimitive {
+        let material = Material::new(Color::new(125, 125, 125), 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        SdfPrimitive::rounded_box(Vec3::zeros(), Vec3::new(0.5, 0.5, 0.5), 0.1, material)
+    }
+
+    #[test]
+    fn hits_are_limited_to_the_ray_t_range() {
+        let pebble = pebble();
+        let origin = Vec3::new(-3.0, 0.0, 0.0);
+        let direction = Vec3::new(1.0, 0.0, 0.0);
+
+        let short_shadow = Ray::shadow(origin, direction * 2.0);
+        assert!(!pebble.ray_intersect(&short_shadow).is_intersecting);
+
+        let hit = pebble.ray_intersect(&Ray::shadow(origin, direction * 10.0));
+        assert!(hit.is_intersecting);
+        assert!((hit.distance - 2.5).abs() < 1e-3);
+
+        let clipped = Ray::primary(origin, direction).with_t_min(3.6);
+        assert!(!pebble.ray_intersect(&clipped).is_intersecting);
+    }
+
+    #[test]
+    fn march_gives_up_on_a_grazing_approach() {
+        // Runs 2e-4 off the rounded top-right edge and closes that gap over
+        // the edge's whole length, so every step is about as long as the
+        // remaining distance and the march cannot converge in MAX_STEPS.
+        let pebble = pebble();
+        let gap = 2e-4;
+        let offset = 0.4 + (0.1 + gap) / 2.0_f32.sqrt();
+        let drift = gap / 0.8 / 2.0_f32.sqrt();
+        let grazing = Ray::primary(Vec3::new(offset, offset, -0.4), Vec3::new(-drift, -drift, 1.0).normalize());
+        assert!(pebble.distance(&grazing.origin) > HIT_EPSILON);
+        assert!(!pebble.ray_intersect(&grazing).is_intersecting);
+
+        let head_on = Ray::primary(Vec3::new(offset, offset, -0.4), Vec3::new(-1.0, -1.0, 0.0).normalize());
+        assert!(pebble.ray_intersect(&head_on).is_intersecting);
+    }
+
+    #[test]
+    fn rays_from_inside_report_the_exit() {
+        let pebble = pebble();
+        let hit = pebble.ray_intersect(&Ray::primary(Vec3::new(0.0, 0.1, 0.0), Vec3::new(1.0, 0.0, 0.0)));
+        assert!(hit.is_intersecting && !hit.entering);
+        assert!((hit.distance - 0.5).abs() < 1e-3);
+        assert!((hit.normal - Vec3::new(-1.0, 0.0, 0.0)).magnitude() < 1e-3);
+
+        let entry = pebble.ray_intersect(&Ray::primary(Vec3::new(-3.0, 0.1, 0.0), Vec3::new(1.0, 0.0, 0.0)));
+        assert!(entry.entering);
+    }
+
+    #[test]
+    fn flat_sides_map_like_a_cuboid() {
+        let pebble = pebble();
+        let hit = pebble.ray_intersect(&Ray::primary(Vec3::new(0.1, 3.0, -0.2), Vec3::new(0.0, -1.0, 0.0)));
+        assert!((hit.uv.0 - 0.4).abs() < 1e-3 && (hit.uv.1 - 0.3).abs() < 1e-3);
+        assert!((hit.tangent - Vec3::new(-1.0, 0.0, 0.0)).magnitude() < 1e-3);
+        assert!((hit.bitangent - Vec3::new(0.0, 0.0, 1.0)).magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn tangents_follow_the_rounded_edges() {
+        let pebble = pebble();
+        let corner = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let hit = pebble.ray_intersect(&Ray::primary(corner * 3.0 + Vec3::new(0.0, 0.01, 0.0), -corner));
+        assert!(hit.is_intersecting);
+        assert!(hit.normal.dot(&hit.tangent).abs() < 1e-3);
+        assert!(hit.normal.dot(&hit.bitangent).abs() < 1e-3);
+        assert!(hit.tangent.dot(&hit.bitangent).abs() < 1e-3);
+        assert!((hit.tangent.magnitude() - 1.0).abs() < 1e-3);
+    }
+}
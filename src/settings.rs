@@ -0,0 +1,62 @@
+use nalgebra_glm::Vec3;
+use crate::layers;
+
+pub const DEFAULT_MAX_DEPTH: u32 = 3;
+// Secondary rays contributing less than about 2/255 to the pixel are skipped.
+pub const DEFAULT_MIN_CONTRIBUTION: f32 = 0.01;
+pub const DEFAULT_EYE_SEPARATION: f32 = 0.2;
+// About half a cube: contact shadows only fill the creases traced shadows
+// leave soft or miss, never whole shadows.
+pub const DEFAULT_CONTACT_SHADOW_DISTANCE: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub max_depth: u32,
+    pub min_contribution: f32,
+    pub shadow_samples: u32,
+    pub reflection_samples: u32,
+    // Hemisphere rays per primary hit for one bounce of diffuse light;
+    // zero turns the bounce off.
+    pub gi_samples: u32,
+    // Short hemisphere rays per primary hit for ambient occlusion; the
+    // ambient term is looked up along the directions they find open.
+    pub ao_samples: u32,
+    pub clip_plane: Option<(Vec3, Vec3)>,
+    pub eye_separation: f32,
+    pub visible_layers: u32,
+    pub shadow_layers: u32,
+    pub reflection_layers: u32,
+    pub reflection_probes: bool,
+    // Light under the water follows the ripples above it.
+    pub caustics: bool,
+    // How much a screen-space contact shadow darkens a pixel; zero skips
+    // the pass. The distance is how far toward each light it looks.
+    pub contact_shadow_strength: f32,
+    pub contact_shadow_distance: f32,
+    // Lets flocks cross the day sky; off for runs that must not change
+    // from frame to frame.
+    pub birds: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            max_depth: DEFAULT_MAX_DEPTH,
+            min_contribution: DEFAULT_MIN_CONTRIBUTION,
+            shadow_samples: 1,
+            reflection_samples: 1,
+            gi_samples: 0,
+            ao_samples: 0,
+            clip_plane: None,
+            eye_separation: DEFAULT_EYE_SEPARATION,
+            visible_layers: layers::ALL,
+            shadow_layers: layers::ALL,
+            reflection_layers: layers::ALL,
+            reflection_probes: true,
+            caustics: false,
+            contact_shadow_strength: 0.0,
+            contact_shadow_distance: DEFAULT_CONTACT_SHADOW_DISTANCE,
+            birds: true,
+        }
+    }
+}
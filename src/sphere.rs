@@ -1,35 +1,108 @@
-
-use nalgebra_glm::{Vec3, dot};
-use crate::ray_intersect::{RayIntersect, Intersect};
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+use crate::aabb::Aabb;
 use crate::material::Material;
+use crate::ray::Ray;
+use crate::ray_intersect::{Intersect, RayIntersect};
 
+#[derive(Clone, Debug)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
     pub material: Material,
 }
 
-impl RayIntersect for Sphere {
-    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect {
-        let oc = ray_origin - self.center;
+impl Sphere {
+    pub fn new(center: Vec3, radius: f32, material: Material) -> Self {
+        Sphere { center, radius, material }
+    }
 
-        let a = dot(ray_direction, ray_direction);
-        let b = 2.0 * dot(&oc, ray_direction);
-        let c = dot(&oc, &oc) - self.radius * self.radius;
+    pub fn aabb(&self) -> Aabb {
+        let half = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - half, self.center + half)
+    }
 
-        let discriminant = b * b - 4.0 * a * c;
+    // Both roots of the ray/sphere quadratic, or None when the ray misses or
+    // the sphere lies entirely behind it.
+    pub fn hit_interval(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let offset = ray.origin - self.center;
+        let a = ray.direction.dot(&ray.direction);
+        let half_b = offset.dot(&ray.direction);
+        let c = offset.dot(&offset) - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
 
-        if discriminant > 0.0 {
-            let t = (-b - discriminant.sqrt()) / (2.0 * a);
-            if t > 0.0 {
-                let point = ray_origin + ray_direction * t;
-                let normal = (point - self.center).normalize();
-                let distance = t;
+        let root = discriminant.sqrt();
+        let t_far = (-half_b + root) / a;
+        if t_far < 0.0 {
+            return None;
+        }
+        Some(((-half_b - root) / a, t_far))
+    }
+
+    pub fn surface_at(&self, ray: &Ray, t: f32) -> Intersect {
+        let point = ray.point_at(t);
+        let normal = (point - self.center) / self.radius;
+        let uv = (
+            0.5 + normal.z.atan2(normal.x) / (2.0 * PI),
+            0.5 + normal.y.clamp(-1.0, 1.0).asin() / PI,
+        );
+        let helper = if normal.y.abs() < 0.99 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let tangent = helper.cross(&normal).normalize();
+        let bitangent = normal.cross(&tangent);
+
+        Intersect::new(point, normal, t, self.material.clone())
+            .with_uv(uv)
+            .with_tangents(tangent, bitangent)
+            .with_extent(2.0 * self.radius)
+    }
+}
 
-                return Intersect::new(point, normal, distance, self.material);
-            }
+impl RayIntersect for Sphere {
+    fn ray_intersect(&self, ray: &Ray) -> Intersect {
+        let (t_near, t_far) = match self.hit_interval(ray) {
+            Some(interval) => interval,
+            None => return Intersect::empty(),
+        };
+        let entering = t_near >= ray.t_min;
+        let t = if entering { t_near } else { t_far };
+        if !ray.accepts(t) {
+            return Intersect::empty();
         }
 
-        Intersect::empty()
+        let hit = self.surface_at(ray, t);
+        if entering { hit } else { hit.into_exit() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn ball() -> Sphere {
+        let material = Material::new(Color::new(125, 125, 125), 10.0, [1.0, 0.0, 0.0, 0.0], 1.0);
+        Sphere::new(Vec3::new(0.0, 1.0, 0.0), 0.5, material)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn interval_covers_the_diameter() {
+        let ray = Ray::primary(Vec3::new(-2.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(ball().hit_interval(&ray), Some((1.5, 2.5)));
+
+        let hit = ball().ray_intersect(&ray);
+        assert!(hit.entering);
+        assert_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn misses_and_spheres_behind_the_ray_report_nothing() {
+        let above = Ray::primary(Vec3::new(-2.0, 1.6, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let behind = Ray::primary(Vec3::new(2.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(ball().hit_interval(&above), None);
+        assert_eq!(ball().hit_interval(&behind), None);
+        assert!(!ball().ray_intersect(&above).is_intersecting);
+    }
+}
@@ -0,0 +1,491 @@
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+use crate::color::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FilterMode {
+    #[default]
+    Nearest,
+    Bilinear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+}
+
+// How the bytes of an image file become texels. Pictures are sRGB and get
+// decoded to linear light; data such as normal maps is already linear and
+// is kept as stored, so filtering blends the actual values.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+const PLACEHOLDER_SIZE: usize = 8;
+const PLACEHOLDER_COLORS: [Color; 2] = [Color::new(255, 0, 255), Color::new(0, 0, 0)];
+
+#[derive(Debug)]
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub filter: FilterMode,
+    pub wrap: WrapMode,
+    texels: Vec<Color>,
+    alpha: Option<Vec<u8>>,
+}
+
+impl Texture {
+    pub fn load(path: &str) -> Result<Self, String> {
+        Texture::load_as(path, ColorSpace::Srgb)
+    }
+
+    pub fn load_as(path: &str, color_space: ColorSpace) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|err| format!("failed to load texture {}: {}", path, err))?
+            .to_rgba8();
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return Err(format!("texture {} is empty", path));
+        }
+
+        let texels = image
+            .pixels()
+            .map(|pixel| match color_space {
+                ColorSpace::Srgb => Color::new(pixel[0], pixel[1], pixel[2]),
+                ColorSpace::Linear => {
+                    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|channel| channel as f32 / 255.0);
+                    Color::from_linear(r, g, b)
+                }
+            })
+            .collect();
+        let texture = Texture::from_texels(width as usize, height as usize, texels)?;
+        // Fully opaque images skip the alpha channel so sampling stays cheap.
+        if image.pixels().all(|pixel| pixel[3] == u8::MAX) {
+            return Ok(texture);
+        }
+        texture.with_alpha(image.pixels().map(|pixel| pixel[3]).collect())
+    }
+
+    pub fn from_texels(width: usize, height: usize, texels: Vec<Color>) -> Result<Self, String> {
+        if width == 0 || height == 0 || texels.len() != width * height {
+            return Err(format!("{} texels do not fill a {}x{} texture", texels.len(), width, height));
+        }
+        Ok(Texture {
+            width,
+            height,
+            filter: FilterMode::default(),
+            wrap: WrapMode::default(),
+            texels,
+            alpha: None,
+        })
+    }
+
+    pub fn with_alpha(mut self, alpha: Vec<u8>) -> Result<Self, String> {
+        if alpha.len() != self.texels.len() {
+            return Err(format!("{} alpha values do not fill a {}x{} texture", alpha.len(), self.width, self.height));
+        }
+        self.alpha = Some(alpha);
+        Ok(self)
+    }
+
+    // Magenta and black checker shown while a texture loads, or for good if
+    // it never does.
+    pub fn placeholder() -> Self {
+        let texels = (0..PLACEHOLDER_SIZE * PLACEHOLDER_SIZE)
+            .map(|i| PLACEHOLDER_COLORS[(i % PLACEHOLDER_SIZE + i / PLACEHOLDER_SIZE) % 2])
+            .collect();
+        Texture::from_texels(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, texels).expect("placeholder fills its size")
+    }
+
+    pub fn with_filter(mut self, filter: FilterMode) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    // Texel coordinates run with v, so y = 0 is the bottom row of the image.
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        self.sample_with(u, v, |index| self.texels[index])
+    }
+
+    // Coverage in [0, 1], filtered and wrapped like the color. Textures
+    // without an alpha channel are opaque everywhere.
+    pub fn sample_alpha(&self, u: f32, v: f32) -> f32 {
+        match &self.alpha {
+            Some(alpha) => self.sample_with(u, v, |index| coverage(alpha[index])).to_unit_rgb()[0],
+            None => 1.0,
+        }
+    }
+
+    fn sample_with(&self, u: f32, v: f32, fetch: impl Fn(usize) -> Color) -> Color {
+        let (u, v) = match self.wrap {
+            WrapMode::Repeat => (u.rem_euclid(1.0), v.rem_euclid(1.0)),
+            WrapMode::Clamp => (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0)),
+        };
+        let x = u * self.width as f32;
+        let y = v * self.height as f32;
+
+        self.filtered_with(x, y, fetch, |x, y| {
+            let (x, y) = match self.wrap {
+                WrapMode::Repeat => (
+                    x.rem_euclid(self.width as i32) as usize,
+                    y.rem_euclid(self.height as i32) as usize,
+                ),
+                WrapMode::Clamp => (
+                    x.clamp(0, self.width as i32 - 1) as usize,
+                    y.clamp(0, self.height as i32 - 1) as usize,
+                ),
+            };
+            (x, self.height - 1 - y)
+        })
+    }
+
+    fn filtered(&self, x: f32, y: f32, resolve: impl Fn(i32, i32) -> (usize, usize)) -> Color {
+        self.filtered_with(x, y, |index| self.texels[index], resolve)
+    }
+
+    fn filtered_with(
+        &self,
+        x: f32,
+        y: f32,
+        fetch: impl Fn(usize) -> Color,
+        resolve: impl Fn(i32, i32) -> (usize, usize),
+    ) -> Color {
+        let texel = |x: i32, y: i32| {
+            let (x, y) = resolve(x, y);
+            fetch(y * self.width + x)
+        };
+
+        match self.filter {
+            FilterMode::Nearest => texel(x.floor() as i32, y.floor() as i32),
+            FilterMode::Bilinear => {
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i32, y0 as i32);
+
+                let top = texel(x0, y0).lerp(texel(x0 + 1, y0), fx);
+                let bottom = texel(x0, y0 + 1).lerp(texel(x0 + 1, y0 + 1), fx);
+                top.lerp(bottom, fy)
+            }
+        }
+    }
+}
+
+// Alpha is stored as a linear gray so filtering blends it evenly.
+fn coverage(alpha: u8) -> Color {
+    let alpha = alpha as f32 / 255.0;
+    Color::from_linear(alpha, alpha, alpha)
+}
+
+impl PartialEq for Texture {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Shared, swappable texture. Materials hold handles, so a texture that
+// finishes loading shows up on the next frame without touching the scene.
+#[derive(Debug, Clone)]
+pub struct TextureHandle {
+    current: Arc<RwLock<Arc<Texture>>>,
+}
+
+impl TextureHandle {
+    pub fn ready(texture: Texture) -> Self {
+        TextureHandle { current: Arc::new(RwLock::new(Arc::new(texture))) }
+    }
+
+    pub fn get(&self) -> Arc<Texture> {
+        self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    pub fn replace(&self, texture: Texture) {
+        *self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(texture);
+    }
+
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        self.get().sample(u, v)
+    }
+}
+
+impl PartialEq for TextureHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.current, &other.current)
+    }
+}
+
+// Hands out placeholder-backed handles right away and decodes the files on
+// background threads. A file that fails to load is reported and keeps its
+// placeholder, so a bad path never takes the app down.
+#[derive(Debug, Default)]
+pub struct TextureManager {
+    pending: Vec<JoinHandle<()>>,
+}
+
+impl TextureManager {
+    // `configure` sets filtering and wrapping; it is applied to the
+    // placeholder too so both sample the same way.
+    pub fn load(&mut self, path: &str, configure: fn(Texture) -> Texture) -> TextureHandle {
+        self.load_as(path, ColorSpace::Srgb, configure)
+    }
+
+    // For textures holding data rather than color, like normal maps.
+    pub fn load_data(&mut self, path: &str, configure: fn(Texture) -> Texture) -> TextureHandle {
+        self.load_as(path, ColorSpace::Linear, configure)
+    }
+
+    fn load_as(&mut self, path: &str, color_space: ColorSpace, configure: fn(Texture) -> Texture) -> TextureHandle {
+        self.pending.retain(|loader| !loader.is_finished());
+
+        let handle = TextureHandle::ready(configure(Texture::placeholder()));
+        let target = handle.clone();
+        let path = path.to_string();
+        self.pending.push(std::thread::spawn(move || match Texture::load_as(&path, color_space) {
+            Ok(texture) => target.replace(configure(texture)),
+            Err(err) => eprintln!("{}; keeping the placeholder", err),
+        }));
+        handle
+    }
+
+    pub fn wait(&mut self) {
+        for loader in self.pending.drain(..) {
+            let _ = loader.join();
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Atlas {
+    pub texture: Texture,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl Atlas {
+    pub fn load(path: &str, tile_width: usize, tile_height: usize) -> Result<Arc<Atlas>, String> {
+        if tile_width == 0 || tile_height == 0 {
+            return Err(format!("atlas {} needs a non-zero tile size", path));
+        }
+        let texture = Texture::load(path)?;
+        let columns = texture.width / tile_width;
+        let rows = texture.height / tile_height;
+        if columns == 0 || rows == 0 {
+            return Err(format!(
+                "atlas {} ({}x{}) is smaller than one {}x{} tile",
+                path, texture.width, texture.height, tile_width, tile_height
+            ));
+        }
+
+        Ok(Arc::new(Atlas { texture, tile_width, tile_height, columns, rows }))
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    pub fn sample(&self, tile: usize, u: f32, v: f32) -> Color {
+        let tile = tile % self.tile_count();
+        let origin_x = tile % self.columns * self.tile_width;
+        let origin_y = tile / self.columns * self.tile_height;
+
+        let x = u.rem_euclid(1.0) * self.tile_width as f32;
+        let y = v.rem_euclid(1.0) * self.tile_height as f32;
+        self.texture.filtered(x, y, |x, y| {
+            let y = y.clamp(0, self.tile_height as i32 - 1) as usize;
+            (
+                origin_x + x.clamp(0, self.tile_width as i32 - 1) as usize,
+                origin_y + self.tile_height - 1 - y,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color::new(255, 0, 0);
+    const BLUE: Color = Color::new(0, 0, 255);
+
+    // One row: red on the left, blue on the right.
+    fn stripe(filter: FilterMode, wrap: WrapMode) -> Texture {
+        Texture::from_texels(2, 1, vec![RED, BLUE]).unwrap().with_filter(filter).with_wrap(wrap)
+    }
+
+    #[test]
+    fn texel_count_must_match_the_size() {
+        assert!(Texture::from_texels(2, 2, vec![RED; 3]).is_err());
+        assert!(Texture::from_texels(0, 1, vec![]).is_err());
+    }
+
+    #[test]
+    fn nearest_picks_the_covering_texel() {
+        let texture = stripe(FilterMode::Nearest, WrapMode::Clamp);
+        assert_eq!(texture.sample(0.2, 0.5), RED);
+        assert_eq!(texture.sample(0.49, 0.5), RED);
+        assert_eq!(texture.sample(0.51, 0.5), BLUE);
+    }
+
+    #[test]
+    fn bilinear_matches_texel_centers_and_blends_between() {
+        let texture = stripe(FilterMode::Bilinear, WrapMode::Clamp);
+        assert_eq!(texture.sample(0.25, 0.5), RED);
+        assert_eq!(texture.sample(0.75, 0.5), BLUE);
+        assert_eq!(texture.sample(0.5, 0.5), RED.lerp(BLUE, 0.5));
+    }
+
+    #[test]
+    fn clamped_edges_do_not_bleed_across() {
+        let texture = stripe(FilterMode::Bilinear, WrapMode::Clamp);
+        assert_eq!(texture.sample(0.05, 0.5), RED);
+        assert_eq!(texture.sample(0.95, 0.5), BLUE);
+    }
+
+    #[test]
+    fn repeated_edges_blend_with_the_opposite_side() {
+        let texture = stripe(FilterMode::Bilinear, WrapMode::Repeat);
+        assert_eq!(texture.sample(0.0, 0.5), RED.lerp(BLUE, 0.5));
+        assert_eq!(texture.sample(1.25, 0.5), RED);
+        assert_eq!(texture.sample(-0.25, 0.5), BLUE);
+    }
+
+    // One column: red on top, blue on the bottom.
+    fn column(wrap: WrapMode) -> Texture {
+        Texture::from_texels(1, 2, vec![RED, BLUE]).unwrap().with_wrap(wrap)
+    }
+
+    #[test]
+    fn v_runs_from_the_bottom_row_up() {
+        let texture = column(WrapMode::Repeat);
+        assert_eq!(texture.sample(0.5, 0.0), BLUE);
+        assert_eq!(texture.sample(0.5, 0.49), BLUE);
+        assert_eq!(texture.sample(0.5, 0.51), RED);
+        assert_eq!(texture.sample(0.5, 0.999), RED);
+    }
+
+    #[test]
+    fn repeat_starts_over_at_each_whole_v() {
+        let texture = column(WrapMode::Repeat);
+        assert_eq!(texture.sample(0.5, 1.0), BLUE);
+        assert_eq!(texture.sample(0.5, 2.25), BLUE);
+        assert_eq!(texture.sample(0.5, -0.25), RED);
+    }
+
+    #[test]
+    fn clamp_holds_the_edge_rows() {
+        let texture = column(WrapMode::Clamp);
+        assert_eq!(texture.sample(0.5, 1.0), RED);
+        assert_eq!(texture.sample(0.5, 3.0), RED);
+        assert_eq!(texture.sample(0.5, -1.0), BLUE);
+    }
+
+    #[test]
+    fn atlas_tiles_keep_v_up() {
+        // Two 1x2 tiles side by side; each is red on top and blue below.
+        let texture = Texture::from_texels(2, 2, vec![RED, RED, BLUE, BLUE]).unwrap();
+        let atlas = Atlas { texture, tile_width: 1, tile_height: 2, columns: 2, rows: 1 };
+        for tile in 0..2 {
+            assert_eq!(atlas.sample(tile, 0.5, 0.0), BLUE);
+            assert_eq!(atlas.sample(tile, 0.5, 0.75), RED);
+            assert_eq!(atlas.sample(tile, 0.5, 1.0), BLUE);
+        }
+    }
+
+    fn scratch_file(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("texture_test_{}_{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn is_placeholder(handle: &TextureHandle) -> bool {
+        let texture = handle.get();
+        texture.width == PLACEHOLDER_SIZE && PLACEHOLDER_COLORS.contains(&texture.sample(0.5 / PLACEHOLDER_SIZE as f32, 0.5))
+    }
+
+    #[test]
+    fn placeholder_is_a_magenta_and_black_checker() {
+        let placeholder = Texture::placeholder();
+        let first = placeholder.sample(0.5 / PLACEHOLDER_SIZE as f32, 0.5 / PLACEHOLDER_SIZE as f32);
+        let next = placeholder.sample(1.5 / PLACEHOLDER_SIZE as f32, 0.5 / PLACEHOLDER_SIZE as f32);
+        assert!(PLACEHOLDER_COLORS.contains(&first));
+        assert_ne!(first, next);
+    }
+
+    #[test]
+    fn missing_files_keep_the_placeholder() {
+        let mut manager = TextureManager::default();
+        let handle = manager.load("assets/does_not_exist.png", |texture| texture);
+        manager.wait();
+        assert!(is_placeholder(&handle));
+    }
+
+    #[test]
+    fn corrupt_files_keep_the_placeholder() {
+        let path = scratch_file("corrupt.png", b"\x89PNG\r\n\x1a\nnot really a png");
+        let mut manager = TextureManager::default();
+        let handle = manager.load(&path, |texture| texture);
+        manager.wait();
+        assert!(is_placeholder(&handle));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn loaded_files_replace_the_placeholder_in_every_copy() {
+        let path = std::env::temp_dir().join(format!("texture_test_{}_red.png", std::process::id()));
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0])).save(&path).unwrap();
+        let mut manager = TextureManager::default();
+        let handle = manager.load(&path.to_string_lossy(), |texture| texture.with_wrap(WrapMode::Clamp));
+        let copy = handle.clone();
+        manager.wait();
+
+        assert_eq!(copy.sample(0.5, 0.5), RED);
+        assert_eq!(copy.get().wrap, WrapMode::Clamp);
+        assert_eq!(copy, handle);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn transparent_pixels_load_as_alpha() {
+        let path = std::env::temp_dir().join(format!("texture_test_{}_alpha.png", std::process::id()));
+        let mut image = image::RgbaImage::from_pixel(2, 1, image::Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, image::Rgba([255, 0, 0, 0]));
+        image.save(&path).unwrap();
+
+        let texture = Texture::load(&path.to_string_lossy()).unwrap();
+        assert_eq!(texture.sample_alpha(0.25, 0.5), 1.0);
+        assert_eq!(texture.sample_alpha(0.75, 0.5), 0.0);
+        assert_eq!(stripe(FilterMode::Nearest, WrapMode::Repeat).sample_alpha(0.75, 0.5), 1.0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn data_textures_keep_the_stored_values() {
+        let path = std::env::temp_dir().join(format!("texture_test_{}_normal.png", std::process::id()));
+        image::RgbImage::from_pixel(1, 1, image::Rgb([128, 128, 255])).save(&path).unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let data = Texture::load_as(&path, ColorSpace::Linear).unwrap().sample(0.5, 0.5).to_unit_rgb();
+        assert_eq!(data, [128.0 / 255.0, 128.0 / 255.0, 1.0]);
+        let color = Texture::load(&path).unwrap().sample(0.5, 0.5).to_unit_rgb();
+        assert!(color[0] < 0.25, "sRGB 128 decodes to about 0.22, got {}", color[0]);
+
+        let mut manager = TextureManager::default();
+        let handle = manager.load_data(&path, |texture| texture);
+        manager.wait();
+        assert_eq!(handle.sample(0.5, 0.5).to_unit_rgb(), data);
+        std::fs::remove_file(path).unwrap();
+    }
+}
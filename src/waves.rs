@@ -0,0 +1,107 @@
+use nalgebra_glm::Vec3;
+use crate::math;
+
+// Amplitude, wave vector (x, z) and speed of each ripple. The wavelengths
+// are a few cubes wide at most so the pond shows several crests at once.
+const RIPPLES: [(f32, f32, f32, f32); 3] = [
+    (0.004, 70.0, 25.0, 0.6),
+    (0.003, -30.0, 85.0, 0.8),
+    (0.0015, 110.0, -60.0, 1.1),
+];
+// Focusing is kept within this factor either way, since the lens estimate
+// blows up where the curvature nearly cancels the depth.
+const MAX_FOCUS: f32 = 4.0;
+
+// Ripples on the water as a sum of sine waves. Slopes and curvature are
+// analytic, so light under the water can follow the waves exactly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Waves {
+    pub time: f32,
+}
+
+impl Waves {
+    fn phases(&self, x: f32, z: f32) -> impl Iterator<Item = (f32, f32, f32, f32)> + '_ {
+        RIPPLES
+            .iter()
+            .map(move |&(amplitude, kx, kz, speed)| (amplitude, kx, kz, kx * x + kz * z + speed * self.time))
+    }
+
+    pub fn height(&self, x: f32, z: f32) -> f32 {
+        self.phases(x, z).map(|(amplitude, _, _, phase)| amplitude * math::sin(phase)).sum()
+    }
+
+    // Partial derivatives of the height along x and z.
+    pub fn slope(&self, x: f32, z: f32) -> (f32, f32) {
+        self.phases(x, z).fold((0.0, 0.0), |(dx, dz), (amplitude, kx, kz, phase)| {
+            (dx + amplitude * kx * math::cos(phase), dz + amplitude * kz * math::cos(phase))
+        })
+    }
+
+    // The Laplacian of the height: negative under crests, which bend light
+    // inward like a converging lens.
+    pub fn curvature(&self, x: f32, z: f32) -> f32 {
+        self.phases(x, z)
+            .map(|(amplitude, kx, kz, phase)| -amplitude * (kx * kx + kz * kz) * math::sin(phase))
+            .sum()
+    }
+
+    pub fn normal(&self, x: f32, z: f32) -> Vec3 {
+        let (dx, dz) = self.slope(x, z);
+        Vec3::new(-dx, 1.0, -dz).normalize()
+    }
+
+    // How much light refracted by the surface at (x, z) gathers at a point
+    // `depth` below it, from the local lens the curvature forms. One means
+    // no change; above one is a bright caustic band.
+    pub fn focus(&self, x: f32, z: f32, depth: f32, refractive_index: f32) -> f32 {
+        let bending = depth * (1.0 - 1.0 / refractive_index) * self.curvature(x, z);
+        1.0 / (1.0 + bending).clamp(1.0 / MAX_FOCUS, MAX_FOCUS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H: f32 = 1e-3;
+
+    #[test]
+    fn slope_and_curvature_match_the_height() {
+        let waves = Waves { time: 3.7 };
+        for &(x, z) in &[(0.0, 0.0), (0.13, -0.07), (-0.4, 0.22)] {
+            let dx = (waves.height(x + H, z) - waves.height(x - H, z)) / (2.0 * H);
+            let dz = (waves.height(x, z + H) - waves.height(x, z - H)) / (2.0 * H);
+            let (slope_x, slope_z) = waves.slope(x, z);
+            assert!((dx - slope_x).abs() < 1e-2 && (dz - slope_z).abs() < 1e-2);
+
+            let laplacian = (waves.slope(x + H, z).0 - waves.slope(x - H, z).0) / (2.0 * H)
+                + (waves.slope(x, z + H).1 - waves.slope(x, z - H).1) / (2.0 * H);
+            assert!((laplacian - waves.curvature(x, z)).abs() < 0.5, "{} vs {}", laplacian, waves.curvature(x, z));
+        }
+    }
+
+    #[test]
+    fn crests_focus_and_troughs_spread() {
+        let waves = Waves { time: 0.0 };
+        let samples: Vec<(f32, f32)> = (0..200)
+            .map(|i| {
+                let x = i as f32 * 0.005;
+                (waves.curvature(x, 0.0), waves.focus(x, 0.0, 0.1, 1.33))
+            })
+            .collect();
+        for &(curvature, focus) in &samples {
+            assert!(curvature >= 0.0 || focus > 1.0);
+            assert!(curvature <= 0.0 || focus < 1.0);
+            assert!((1.0 / MAX_FOCUS..=MAX_FOCUS).contains(&focus));
+        }
+        assert_eq!(waves.focus(0.3, 0.1, 0.0, 1.33), 1.0);
+    }
+
+    #[test]
+    fn ripples_move_with_time() {
+        let still = Waves { time: 0.0 };
+        let later = Waves { time: 1.0 };
+        assert_ne!(still.height(0.1, 0.1), later.height(0.1, 0.1));
+        assert!((still.normal(0.1, 0.1).magnitude() - 1.0).abs() < 1e-5);
+    }
+}